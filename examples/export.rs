@@ -0,0 +1,43 @@
+//! Export messages to stdout.
+//!
+//! Usage: `export <jsonl|csv|markdown> [--app <id>]... [--since <rfc3339>] [--until <rfc3339>]`
+
+use gotify::{export::ExportFormat, models::ApplicationId};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+#[tokio::main]
+async fn main() -> eyre::Result<()> {
+    let client: gotify::ClientClient = gotify::Client::new(
+        &*std::env::var("GOTIFY_URL")?,
+        std::env::var("GOTIFY_CLIENT_TOKEN")?,
+    )?;
+
+    let mut args = std::env::args().skip(1);
+
+    let format = match args.next().as_deref() {
+        Some("jsonl") => ExportFormat::JsonLines,
+        Some("csv") => ExportFormat::csv(),
+        Some("markdown") => ExportFormat::Markdown,
+        _ => eyre::bail!("expected one of `jsonl`, `csv` or `markdown`"),
+    };
+    let mut export = client.export_messages(format);
+
+    while let Some(arg) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| eyre::eyre!("missing value for `{arg}`"))?;
+        export = match arg.as_str() {
+            "--app" => export.with_application(ApplicationId(value.parse()?)),
+            "--since" => export.with_since(OffsetDateTime::parse(&value, &Rfc3339)?),
+            "--until" => export.with_until(OffsetDateTime::parse(&value, &Rfc3339)?),
+            _ => eyre::bail!("unknown argument `{arg}`"),
+        };
+    }
+
+    let summary = export
+        .write_to(std::io::BufWriter::new(std::io::stdout().lock()))
+        .await?;
+    eprintln!("exported {} messages", summary.exported);
+
+    Ok(())
+}
@@ -1,30 +1,85 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 use reqwest::Method;
 
-use crate::{models::Message, utils::request_builder, AppClient};
+use crate::{models::Message, AppClient};
 
 /// Create messages.
 impl AppClient {
     /// Create a message.
-    pub fn create_message(&self, message: impl Into<String>) -> MessageBuilder {
+    pub fn create_message(&self, message: impl Into<String>) -> MessageBuilder<'_> {
         MessageBuilder::new(self, message)
     }
 }
 
-request_builder! {
-    name = MessageBuilder,
-    client_type = AppClient,
-    method = Method::POST,
-    uri = ["message"],
-    return_type = Message,
-    required_fields = {
-        message: impl Into<String> => .into() => String,
-    },
-    optional_fields = {
-        title: impl Into<String> => .into() => String,
-        extras: impl Into<HashMap<String, serde_json::Value>> => .into() => HashMap<String, serde_json::Value>,
-        priority: u8 => u8,
+/// Builder for [`AppClient::create_message()`].
+#[allow(missing_docs)]
+#[derive(Debug, serde::Serialize)]
+pub struct MessageBuilder<'client> {
+    #[serde(skip)]
+    client: &'client AppClient,
+    message: String,
+    title: Option<String>,
+    extras: Option<HashMap<String, serde_json::Value>>,
+    priority: Option<u8>,
+    #[serde(skip)]
+    ttl: Option<Duration>,
+}
+
+#[allow(missing_docs)]
+impl<'client> MessageBuilder<'client> {
+    pub fn new(client: &'client AppClient, message: impl Into<String>) -> Self {
+        Self {
+            client,
+            message: message.into(),
+            title: None,
+            extras: None,
+            priority: None,
+            ttl: None,
+        }
+    }
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+    pub fn with_extras(mut self, extras: impl Into<HashMap<String, serde_json::Value>>) -> Self {
+        self.extras = Some(extras.into());
+        self
+    }
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+    /// Declare how long the message should be kept.
+    ///
+    /// The time to live is stored in the message's extras (see
+    /// [`Message::TTL_EXTRAS_KEY`]). Gotify itself ignores it, but it is
+    /// honored by [`Client::enforce_retention()`](crate::Client::enforce_retention).
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+    pub async fn send(mut self) -> crate::Result<Message> {
+        if let Some(ttl) = self.ttl {
+            self.extras
+                .get_or_insert_with(HashMap::new)
+                .insert(Message::TTL_EXTRAS_KEY.into(), ttl.as_secs().into());
+        }
+        self.client
+            .request(Method::POST, ["message"])
+            .with_json_body(&self)
+            .send_and_read_json()
+            .await
+    }
+}
+
+impl<'client> std::future::IntoFuture for MessageBuilder<'client> {
+    type Output = crate::Result<Message>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
     }
 }
 
@@ -62,4 +117,23 @@ pub(crate) mod tests {
 
         Ok(())
     }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn create_message_with_ttl() -> eyre::Result<()> {
+        let message = app_client()
+            .create_message("Hello World")
+            .with_ttl(std::time::Duration::from_secs(3600))
+            .with_extras([("foo".into(), "bar".into())])
+            .await?;
+
+        assert_eq!(message.ttl(), Some(std::time::Duration::from_secs(3600)));
+        assert_eq!(
+            message.expires_at(),
+            Some(message.date + time::Duration::HOUR)
+        );
+        assert!(message.extras.unwrap().contains_key("foo"));
+
+        Ok(())
+    }
 }
@@ -1,8 +1,14 @@
 use std::borrow::Cow;
+#[cfg(feature = "image-cache")]
+use std::path::{Path, PathBuf};
 
 use reqwest::Method;
 
-use crate::{models::Application, utils::request_builder, ClientClient, Result};
+use crate::{
+    models::{Application, ApplicationId, ApplicationImage},
+    utils::request_builder,
+    ClientClient, Result,
+};
 
 /// Create, read, update and delete applications or modify application images.
 impl ClientClient {
@@ -13,15 +19,39 @@ impl ClientClient {
             .await
     }
     /// Create an application.
-    pub fn create_application(&self, name: impl Into<String>) -> ApplicationBuilder {
+    pub fn create_application(&self, name: impl Into<String>) -> ApplicationBuilder<'_> {
         ApplicationBuilder::new(self, name)
     }
     /// Update an application.
-    pub fn update_application(&self, id: i64, name: impl Into<String>) -> ApplicationUpdateBuilder {
+    pub fn update_application(
+        &self,
+        id: ApplicationId,
+        name: impl Into<String>,
+    ) -> ApplicationUpdateBuilder<'_> {
         ApplicationUpdateBuilder::new(self, id, name)
     }
+    /// Get, create or update an application by name and return it with a client
+    /// that can create messages for it.
+    ///
+    /// The returned [`AppClient`](crate::AppClient) shares the HTTP client of this client.
+    ///
+    /// This isn't atomic: the applications are listed before one is created,
+    /// so concurrent callers may each create an application with the same
+    /// name. If several applications have the name, the one with the lowest
+    /// id is used.
+    #[cfg(feature = "app")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "app")))]
+    pub fn ensure_application(&self, name: impl Into<String>) -> EnsureApplicationBuilder<'_> {
+        EnsureApplicationBuilder {
+            client: self,
+            name: name.into(),
+            description: None,
+            default_priority: None,
+            image: None,
+        }
+    }
     /// Delete an application.
-    pub async fn delete_application(&self, id: i64) -> Result<()> {
+    pub async fn delete_application(&self, id: ApplicationId) -> Result<()> {
         self.request(Method::DELETE, ["application".into(), id.to_string()])
             .send()
             .await
@@ -29,7 +59,7 @@ impl ClientClient {
     /// Upload an image for an application.
     pub async fn upload_application_image(
         &self,
-        id: i64,
+        id: ApplicationId,
         image_name: impl Into<Cow<'static, str>>,
         image_content: impl Into<Cow<'static, [u8]>>,
     ) -> Result<Application> {
@@ -41,8 +71,20 @@ impl ClientClient {
         .send_and_read_json()
         .await
     }
+    /// Download the image of an application.
+    pub fn get_application_image<'a>(
+        &'a self,
+        application: &'a Application,
+    ) -> ApplicationImageBuilder<'a> {
+        ApplicationImageBuilder {
+            client: self,
+            path: &application.image,
+            #[cfg(feature = "image-cache")]
+            cache_dir: None,
+        }
+    }
     /// Delete an image of an application.
-    pub async fn delete_application_image(&self, id: i64) -> Result<()> {
+    pub async fn delete_application_image(&self, id: ApplicationId) -> Result<()> {
         self.request(
             Method::DELETE,
             ["application".into(), id.to_string(), "image".into()],
@@ -74,7 +116,7 @@ request_builder! {
     return_type = Application,
     required_fields = {
         #[serde(skip)]
-        id: i64 => i64,
+        id: ApplicationId => ApplicationId,
         name: impl Into<String> => .into() => String,
     },
     optional_fields = {
@@ -83,9 +125,210 @@ request_builder! {
     }
 }
 
+/// Split an image path into URL segments, ignoring segments that would leave the image directory.
+pub(crate) fn image_segments(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|segment| !matches!(*segment, "" | "." | ".."))
+        .collect()
+}
+
+/// Builder for [`ClientClient::get_application_image()`].
+#[derive(Debug)]
+pub struct ApplicationImageBuilder<'client> {
+    client: &'client ClientClient,
+    path: &'client str,
+    #[cfg(feature = "image-cache")]
+    cache_dir: Option<PathBuf>,
+}
+
+#[allow(missing_docs)]
+impl<'client> ApplicationImageBuilder<'client> {
+    /// Cache downloaded images in a directory.
+    ///
+    /// Images are keyed by their path, which changes whenever a new image is
+    /// uploaded, so cached images never need to be invalidated.
+    /// Failing to read or write the cache is not considered an error.
+    #[cfg(feature = "image-cache")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "image-cache")))]
+    pub fn with_cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+    /// Download the image, or read it from the cache directory if it was downloaded before.
+    pub async fn send(self) -> Result<ApplicationImage> {
+        let segments = image_segments(self.path);
+
+        #[cfg(feature = "image-cache")]
+        let cache_file = self
+            .cache_dir
+            .as_ref()
+            .map(|dir| segments.iter().fold(dir.clone(), |path, s| path.join(s)));
+
+        #[cfg(feature = "image-cache")]
+        if let Some(path) = &cache_file {
+            if let Ok(content) = tokio::fs::read(path).await {
+                return Ok(ApplicationImage {
+                    content_type: guess_content_type(path),
+                    content,
+                });
+            }
+        }
+
+        let (content_type, content) = self
+            .client
+            .request(Method::GET, &segments)
+            .send_and_read_bytes()
+            .await?;
+
+        #[cfg(feature = "image-cache")]
+        if let Some(path) = &cache_file {
+            if let Some(parent) = path.parent() {
+                let _ = tokio::fs::create_dir_all(parent).await;
+            }
+            let _ = tokio::fs::write(path, &content).await;
+        }
+
+        Ok(ApplicationImage {
+            content_type,
+            content,
+        })
+    }
+}
+
+impl<'client> std::future::IntoFuture for ApplicationImageBuilder<'client> {
+    type Output = Result<ApplicationImage>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// Builder for [`ClientClient::ensure_application()`].
+///
+/// Only the properties that were set explicitly are compared with an
+/// existing application and updated if they differ.
+#[cfg(feature = "app")]
+#[cfg_attr(docsrs, doc(cfg(feature = "app")))]
+#[derive(Debug)]
+pub struct EnsureApplicationBuilder<'client> {
+    client: &'client ClientClient,
+    name: String,
+    description: Option<String>,
+    default_priority: Option<u8>,
+    image: Option<(Cow<'static, str>, Cow<'static, [u8]>)>,
+}
+
+#[cfg(feature = "app")]
+#[allow(missing_docs)]
+impl<'client> EnsureApplicationBuilder<'client> {
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+    pub fn with_default_priority(mut self, default_priority: u8) -> Self {
+        self.default_priority = Some(default_priority);
+        self
+    }
+    pub fn with_image(
+        mut self,
+        image_name: impl Into<Cow<'static, str>>,
+        image_content: impl Into<Cow<'static, [u8]>>,
+    ) -> Self {
+        self.image = Some((image_name.into(), image_content.into()));
+        self
+    }
+    pub async fn send(self) -> Result<(Application, crate::AppClient)> {
+        let client = self.client;
+
+        let existing = client
+            .get_applications()
+            .await?
+            .into_iter()
+            .filter(|a| a.name == self.name)
+            .min_by_key(|a| a.id);
+
+        let mut application = match existing {
+            None => {
+                let mut builder = client.create_application(&self.name);
+                if let Some(description) = &self.description {
+                    builder = builder.with_description(description);
+                }
+                if let Some(default_priority) = self.default_priority {
+                    builder = builder.with_default_priority(default_priority);
+                }
+                builder.await?
+            }
+            Some(existing)
+                if self
+                    .description
+                    .as_ref()
+                    .is_some_and(|d| *d != existing.description)
+                    || self
+                        .default_priority
+                        .is_some_and(|p| Some(p) != existing.default_priority) =>
+            {
+                let mut builder = client
+                    .update_application(existing.id, &self.name)
+                    .with_description(self.description.unwrap_or(existing.description));
+                if let Some(default_priority) = self.default_priority.or(existing.default_priority)
+                {
+                    builder = builder.with_default_priority(default_priority);
+                }
+                builder.await?
+            }
+            Some(existing) => existing,
+        };
+
+        if let Some((image_name, image_content)) = self.image {
+            let is_current = application.image.starts_with("image/")
+                && client
+                    .get_application_image(&application)
+                    .await
+                    .is_ok_and(|current| current.content == *image_content);
+
+            if !is_current {
+                application = client
+                    .upload_application_image(application.id, image_name, image_content)
+                    .await?;
+            }
+        }
+
+        let app_client = client.with_access_token(application.token.as_str())?;
+
+        Ok((application, app_client))
+    }
+}
+
+#[cfg(feature = "app")]
+impl<'client> std::future::IntoFuture for EnsureApplicationBuilder<'client> {
+    type Output = Result<(Application, crate::AppClient)>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+#[cfg(feature = "image-cache")]
+fn guess_content_type(path: &Path) -> Option<String> {
+    let content_type = match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        _ => return None,
+    };
+    Some(content_type.to_owned())
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
-    use crate::testsuite::*;
+    use crate::{models::ApplicationId, testsuite::*};
 
     #[apply(run_test_server!)]
     #[test]
@@ -147,11 +390,19 @@ pub(crate) mod tests {
     async fn delete_application() -> eyre::Result<()> {
         let client = client_client();
 
-        assert!(client.get_applications().await?.iter().any(|a| a.id == 1));
+        assert!(client
+            .get_applications()
+            .await?
+            .iter()
+            .any(|a| a.id == ApplicationId(1)));
 
-        client.delete_application(1).await?;
+        client.delete_application(ApplicationId(1)).await?;
 
-        assert!(!client.get_applications().await?.iter().any(|a| a.id == 1));
+        assert!(!client
+            .get_applications()
+            .await?
+            .iter()
+            .any(|a| a.id == ApplicationId(1)));
 
         Ok(())
     }
@@ -165,15 +416,129 @@ pub(crate) mod tests {
             .get_applications()
             .await?
             .iter()
-            .find(|a| a.id == 1)
+            .find(|a| a.id == ApplicationId(1))
             .is_some_and(|a| a.image == "static/defaultapp.png"));
 
         let application = client
-            .upload_application_image(1, "img.png", include_bytes!("../tests/img.png").as_slice())
+            .upload_application_image(
+                ApplicationId(1),
+                "img.png",
+                include_bytes!("../tests/img.png").as_slice(),
+            )
             .await?;
 
         assert!(application.image.starts_with("image/"));
 
         Ok(())
     }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn get_application_image() -> eyre::Result<()> {
+        let client = client_client();
+
+        let application = client
+            .upload_application_image(
+                ApplicationId(1),
+                "img.png",
+                include_bytes!("../tests/img.png").as_slice(),
+            )
+            .await?;
+
+        let image = client.get_application_image(&application).await?;
+        assert_eq!(image.content_type.as_deref(), Some("image/png"));
+        assert_eq!(image.content, include_bytes!("../tests/img.png"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "image-cache")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn cached_application_image() -> eyre::Result<()> {
+        let client = client_client();
+
+        let application = client
+            .upload_application_image(
+                ApplicationId(1),
+                "img.png",
+                include_bytes!("../tests/img.png").as_slice(),
+            )
+            .await?;
+        let cache_dir = temp_path("image-cache");
+
+        let image = client
+            .get_application_image(&application)
+            .with_cache_dir(&cache_dir)
+            .await?;
+        assert_eq!(image.content, include_bytes!("../tests/img.png"));
+        assert!(cache_dir.join(&application.image).exists());
+
+        client.delete_application_image(ApplicationId(1)).await?;
+
+        let image = client
+            .get_application_image(&application)
+            .with_cache_dir(&cache_dir)
+            .await?;
+        assert_eq!(image.content_type.as_deref(), Some("image/png"));
+        assert_eq!(image.content, include_bytes!("../tests/img.png"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "app")]
+    #[tokio::test]
+    async fn ensure_application_uses_the_lowest_id() -> eyre::Result<()> {
+        const APPLICATIONS: &str = r#"[
+            {"id": 7, "token": "newer", "name": "duplicate", "description": "", "internal": false, "image": "static/defaultapp.png"},
+            {"id": 3, "token": "older", "name": "duplicate", "description": "", "internal": false, "image": "static/defaultapp.png"}
+        ]"#;
+        let (url, requests) = http_stand_in(vec![(200, APPLICATIONS)]).await;
+        let client = crate::ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?;
+
+        let (application, _) = client.ensure_application("duplicate").await?;
+        assert_eq!(application.id, ApplicationId(3));
+        assert_eq!(application.token, "older");
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "app")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn ensure_application() -> eyre::Result<()> {
+        let client = client_client();
+
+        let (created, app_client) = client
+            .ensure_application("ensured-application")
+            .with_description("first description")
+            .await?;
+        assert_eq!(created.description, "first description");
+
+        let message = app_client.create_message("Hello World").await?;
+        assert_eq!(message.appid, created.id);
+
+        let (unchanged, _) = client.ensure_application("ensured-application").await?;
+        assert_eq!(unchanged.id, created.id);
+        assert_eq!(unchanged.description, "first description");
+
+        let (updated, _) = client
+            .ensure_application("ensured-application")
+            .with_default_priority(7)
+            .with_image("img.png", include_bytes!("../tests/img.png").as_slice())
+            .await?;
+        assert_eq!(updated.id, created.id);
+        assert_eq!(updated.description, "first description");
+        assert_eq!(updated.default_priority, Some(7));
+        assert!(updated.image.starts_with("image/"));
+
+        let (unchanged, _) = client
+            .ensure_application("ensured-application")
+            .with_image("img.png", include_bytes!("../tests/img.png").as_slice())
+            .await?;
+        assert_eq!(unchanged.image, updated.image);
+
+        Ok(())
+    }
 }
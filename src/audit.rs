@@ -0,0 +1,396 @@
+//! Audit stale clients and applications and prune them.
+//!
+//! [`ClientClient::audit()`] collects everything known about the server's
+//! clients, applications and users in a single [`AuditReport`].
+//! [`ClientClient::prune()`] deletes clients and applications that haven't
+//! been used for a given time. It runs in dry-run mode unless disabled explicitly
+//! and never deletes the client whose token is used to perform the pruning.
+
+use std::collections::HashMap;
+
+use futures_util::TryStreamExt;
+use reqwest::{Method, StatusCode};
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    applications::image_segments,
+    models::{Application, ApplicationId, Client, User},
+    ClientClient, Result,
+};
+
+/// The state of a server's clients, applications and users.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct AuditReport {
+    /// All clients of the authenticated user.
+    pub clients: Vec<ClientAudit>,
+    /// All applications of the authenticated user.
+    pub applications: Vec<ApplicationAudit>,
+    /// All users.
+    pub users: Vec<User>,
+    /// Images referenced by applications that don't exist on the server.
+    ///
+    /// Gotify's API offers no way to list the stored image files, so unreferenced
+    /// files can't be detected.
+    pub orphaned_images: Vec<String>,
+}
+
+/// A client with its usage information.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ClientAudit {
+    /// The audited client.
+    pub client: Client,
+    /// Time since the client was last used, `None` if it was never used.
+    pub last_used_age: Option<Duration>,
+    /// Whether this is the client performing the audit.
+    pub is_current: bool,
+}
+
+/// An application with its usage information.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ApplicationAudit {
+    /// The audited application.
+    pub application: Application,
+    /// Time since the application was last used, `None` if it was never used.
+    pub last_used_age: Option<Duration>,
+    /// The number of messages stored for the application.
+    pub message_count: usize,
+}
+
+/// Audit the server and prune stale clients and applications.
+impl ClientClient {
+    /// List clients, applications and users with their usage information.
+    pub async fn audit(&self) -> Result<AuditReport> {
+        let now = OffsetDateTime::now_utc();
+
+        let clients = self
+            .get_clients()
+            .await?
+            .into_iter()
+            .map(|client| ClientAudit {
+                last_used_age: client.last_used.map(|t| now - t),
+                is_current: self.is_current_client(&client),
+                client,
+            })
+            .collect();
+
+        let message_counts = self.count_messages_per_application().await?;
+
+        let mut applications = Vec::new();
+        let mut orphaned_images = Vec::new();
+        for application in self.get_applications().await? {
+            if !self.image_exists(&application.image).await? {
+                orphaned_images.push(application.image.clone());
+            }
+            applications.push(ApplicationAudit {
+                last_used_age: application.last_used.map(|t| now - t),
+                message_count: message_counts.get(&application.id).copied().unwrap_or(0),
+                application,
+            });
+        }
+
+        Ok(AuditReport {
+            clients,
+            applications,
+            users: self.get_users().await?,
+            orphaned_images,
+        })
+    }
+
+    /// Delete clients and applications that haven't been used for a given time.
+    pub fn prune(&self) -> PruneBuilder<'_> {
+        PruneBuilder {
+            client: self,
+            dry_run: true,
+            client_max_age: None,
+            application_max_age: None,
+            include_never_used: false,
+            protected_names: Vec::new(),
+        }
+    }
+
+    fn is_current_client(&self, client: &Client) -> bool {
+        self.access_token
+            .as_ref()
+            .is_some_and(|token| token.as_bytes() == client.token.as_bytes())
+    }
+
+    /// Check whether an image exists without downloading it.
+    ///
+    /// Only a 404 response counts as missing.
+    async fn image_exists(&self, path: &str) -> Result<bool> {
+        let status = self
+            .request(Method::HEAD, image_segments(path))
+            .send_and_read_status()
+            .await?;
+        Ok(status != StatusCode::NOT_FOUND)
+    }
+
+    async fn count_messages_per_application(&self) -> Result<HashMap<ApplicationId, usize>> {
+        let mut counts = HashMap::new();
+        let mut pages = std::pin::pin!(self.message_pages(None, None));
+        while let Some(page) = pages.try_next().await? {
+            for message in &page.messages {
+                *counts.entry(message.appid).or_default() += 1;
+            }
+        }
+
+        Ok(counts)
+    }
+}
+
+/// Builder for [`ClientClient::prune()`].
+#[derive(Debug)]
+pub struct PruneBuilder<'client> {
+    client: &'client ClientClient,
+    dry_run: bool,
+    client_max_age: Option<Duration>,
+    application_max_age: Option<Duration>,
+    include_never_used: bool,
+    protected_names: Vec<String>,
+}
+
+impl<'client> PruneBuilder<'client> {
+    /// Only report the stale clients and applications (`true`, the default) or also delete them (`false`).
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+    /// Prune clients that haven't been used for longer than this.
+    pub fn with_client_max_age(mut self, max_age: std::time::Duration) -> Self {
+        self.client_max_age = Some(Duration::try_from(max_age).unwrap_or(Duration::MAX));
+        self
+    }
+    /// Prune applications that haven't been used for longer than this.
+    ///
+    /// Deleting an application also deletes all of its messages.
+    pub fn with_application_max_age(mut self, max_age: std::time::Duration) -> Self {
+        self.application_max_age = Some(Duration::try_from(max_age).unwrap_or(Duration::MAX));
+        self
+    }
+    /// Also prune clients and applications that were never used.
+    pub fn with_never_used(mut self, include_never_used: bool) -> Self {
+        self.include_never_used = include_never_used;
+        self
+    }
+    /// Never prune clients or applications whose name matches this pattern.
+    ///
+    /// `*` matches any number of characters and `?` matches a single character.
+    pub fn with_protected_name(mut self, pattern: impl Into<String>) -> Self {
+        self.protected_names.push(pattern.into());
+        self
+    }
+
+    fn is_stale(&self, last_used: Option<OffsetDateTime>, max_age: Duration) -> bool {
+        match last_used {
+            Some(last_used) => OffsetDateTime::now_utc() - last_used > max_age,
+            None => self.include_never_used,
+        }
+    }
+    fn is_protected(&self, name: &str) -> bool {
+        self.protected_names
+            .iter()
+            .any(|pattern| glob_match(pattern, name))
+    }
+
+    /// Find the stale clients and applications and delete them unless in dry-run mode.
+    pub async fn send(self) -> Result<PruneReport> {
+        let client = self.client;
+        let mut report = PruneReport {
+            dry_run: self.dry_run,
+            clients: Vec::new(),
+            applications: Vec::new(),
+        };
+
+        if let Some(max_age) = self.client_max_age {
+            for c in client.get_clients().await? {
+                if client.is_current_client(&c)
+                    || self.is_protected(&c.name)
+                    || !self.is_stale(c.last_used, max_age)
+                {
+                    continue;
+                }
+                if !self.dry_run {
+                    client.delete_client(c.id).await?;
+                }
+                report.clients.push(c);
+            }
+        }
+
+        if let Some(max_age) = self.application_max_age {
+            for application in client.get_applications().await? {
+                if application.internal
+                    || self.is_protected(&application.name)
+                    || !self.is_stale(application.last_used, max_age)
+                {
+                    continue;
+                }
+                if !self.dry_run {
+                    client.delete_application(application.id).await?;
+                }
+                report.applications.push(application);
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl<'client> std::future::IntoFuture for PruneBuilder<'client> {
+    type Output = Result<PruneReport>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// The clients and applications that were (or in dry-run mode would have been) deleted.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct PruneReport {
+    /// Whether nothing was deleted.
+    pub dry_run: bool,
+    /// The stale clients.
+    pub clients: Vec<Client>,
+    /// The stale applications.
+    pub applications: Vec<Application>,
+}
+
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (pattern, name) = (pattern.as_bytes(), name.as_bytes());
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bn)) => {
+                    backtrack = Some((bp, bn + 1));
+                    p = bp + 1;
+                    n = bn + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{models::ApplicationId, testsuite::*, ClientClient};
+
+    use super::glob_match;
+
+    #[test]
+    fn protected_name_patterns() {
+        assert!(glob_match("gotify-*", "gotify-rs"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("App?", "App1"));
+        assert!(glob_match("*-session-*", "chrome-session-42"));
+        assert!(!glob_match("App?", "App10"));
+        assert!(!glob_match("gotify-*", "my-gotify-rs"));
+    }
+
+    #[tokio::test]
+    async fn only_missing_images_are_orphaned() -> eyre::Result<()> {
+        let (url, requests) = http_stand_in(vec![
+            (404, "{}"),
+            (
+                500,
+                r#"{"error": "", "errorCode": 500, "errorDescription": ""}"#,
+            ),
+            (200, "PNG"),
+        ])
+        .await;
+        let client = ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?;
+
+        assert!(!client.image_exists("image/missing.png").await?);
+        assert!(client.image_exists("image/broken.png").await?);
+        assert!(client.image_exists("image/../present.png").await?);
+        let requests = requests.lock().unwrap();
+        assert!(requests.iter().all(|(line, _)| line.starts_with("HEAD ")));
+        assert!(requests[2].0.contains("/image/present.png"));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn audit() -> eyre::Result<()> {
+        let report = client_client().audit().await?;
+
+        assert!(report
+            .clients
+            .iter()
+            .any(|c| c.is_current && c.client.name == "gotify-rs"));
+        assert_eq!(
+            report
+                .applications
+                .iter()
+                .find(|a| a.application.id == ApplicationId(3))
+                .unwrap()
+                .message_count,
+            2
+        );
+        assert!(report.users.iter().any(|u| u.name == "admin"));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn prune() -> eyre::Result<()> {
+        let client = client_client();
+
+        client.create_client("browser-session-1").await?;
+        client.create_client("cli").await?;
+
+        let report = client
+            .prune()
+            .with_client_max_age(Duration::ZERO)
+            .with_never_used(true)
+            .with_protected_name("cli")
+            .await?;
+        assert!(report.dry_run);
+        assert_eq!(
+            report.clients.iter().map(|c| &c.name).collect::<Vec<_>>(),
+            vec!["browser-session-1"]
+        );
+        assert_eq!(client.get_clients().await?.len(), 3);
+
+        client
+            .prune()
+            .with_client_max_age(Duration::ZERO)
+            .with_never_used(true)
+            .with_protected_name("cli")
+            .with_dry_run(false)
+            .await?;
+        assert_eq!(
+            client
+                .get_clients()
+                .await?
+                .into_iter()
+                .map(|c| c.name)
+                .collect::<Vec<_>>(),
+            vec!["gotify-rs", "cli"]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,597 @@
+//! Back up a whole server into a single archive and restore it.
+//!
+//! [`ClientClient::backup()`] writes a ZIP archive containing the server's
+//! users, applications (including their images), clients, plugin
+//! configurations and messages. Only the HTTP API is used, so no access to the
+//! Gotify host's filesystem is required.
+//!
+//! [`ClientClient::restore()`] recreates the contents of an archive on
+//! another (usually empty) server. As the server assigns new ids and tokens,
+//! the returned [`RestoreReport`] maps the old ids to the new entities.
+//!
+//! Some data can't be restored exactly:
+//! - User passwords can't be read from the server. Users are only restored if
+//!   a password is provided with [`RestoreBuilder::with_user_password()`] or
+//!   [`RestoreBuilder::with_default_user_password()`].
+//! - Messages are sent again using the new application tokens, so they get new
+//!   dates. The original id and date are stored in the extras key
+//!   [`RESTORED_EXTRAS_KEY`], which is also used to skip messages that were
+//!   restored before, so an interrupted restore can simply be run again.
+//! - Internal applications are created by plugins and are neither backed up nor restored.
+//! - Plugins aren't installed by a restore, only configured and enabled.
+//!
+//! ```no_run
+//! # async fn run(old: gotify::ClientClient, new: gotify::ClientClient) -> Result<(), Box<dyn std::error::Error>> {
+//! let mut archive = std::fs::File::options()
+//!     .read(true)
+//!     .write(true)
+//!     .create(true)
+//!     .open("gotify-backup.zip")?;
+//!
+//! old.backup().write_to(&mut archive).await?;
+//!
+//! let report = new
+//!     .restore()
+//!     .with_default_user_password("change me")
+//!     .read_from(&mut archive)
+//!     .await?;
+//! for (old_id, application) in &report.applications {
+//!     println!("{old_id} -> {} ({})", application.id, application.token);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Seek, Write},
+};
+
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+use crate::{
+    models::{
+        Application, ApplicationId, Client, ClientId, Message, MessageId, PluginCapability, User,
+        UserId,
+    },
+    ClientClient, Error,
+};
+
+/// The extras key of restored messages containing their original id and date.
+pub const RESTORED_EXTRAS_KEY: &str = "gotify-rs::restored";
+
+const FORMAT_VERSION: u32 = 1;
+
+/// Errors that can occur when writing or reading a backup.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to read or write the archive")]
+    Io(#[from] std::io::Error),
+    #[error("invalid archive")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("invalid archive entry")]
+    Json(#[from] serde_json::Error),
+    #[error("unsupported archive format version {0}")]
+    UnsupportedVersion(u32),
+}
+
+/// A restore that stopped early, with everything that was restored until then.
+///
+/// Running the restore again continues where it stopped.
+#[derive(Debug, thiserror::Error)]
+#[error("the restore stopped early")]
+#[non_exhaustive]
+pub struct RestoreError {
+    /// The entities restored before the failure.
+    pub report: RestoreReport,
+    /// The reason the restore stopped.
+    #[source]
+    pub source: BackupError,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+    format_version: u32,
+    server_version: String,
+    #[serde(with = "time::serde::iso8601")]
+    created: time::OffsetDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct PluginBackup {
+    module_path: String,
+    enabled: bool,
+    config: Option<String>,
+}
+
+/// The number of backed up entities.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct BackupSummary {
+    /// The number of users.
+    pub users: usize,
+    /// The number of applications.
+    pub applications: usize,
+    /// The number of application images.
+    pub images: usize,
+    /// The number of clients.
+    pub clients: usize,
+    /// The number of plugins.
+    pub plugins: usize,
+    /// The number of messages.
+    pub messages: usize,
+}
+
+/// The mapping from the ids in the archive to the restored entities.
+///
+/// Entities that already existed on the server (matched by name, module
+/// path or, for messages, by [`RESTORED_EXTRAS_KEY`]) are included as well.
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct RestoreReport {
+    /// The restored users.
+    pub users: BTreeMap<UserId, User>,
+    /// Users that weren't restored because no password was provided.
+    pub skipped_users: Vec<String>,
+    /// The restored applications, including their new tokens.
+    pub applications: BTreeMap<ApplicationId, Application>,
+    /// The restored clients, including their new tokens.
+    pub clients: BTreeMap<ClientId, Client>,
+    /// The ids of the restored messages.
+    pub messages: BTreeMap<MessageId, MessageId>,
+    /// Module paths of plugins that aren't installed on the server.
+    pub missing_plugins: Vec<String>,
+}
+
+/// Back up and restore the server.
+impl ClientClient {
+    /// Write a backup of the server.
+    pub fn backup(&self) -> BackupBuilder<'_> {
+        BackupBuilder {
+            client: self,
+            messages: true,
+        }
+    }
+    /// Restore a backup written by [`ClientClient::backup()`].
+    pub fn restore(&self) -> RestoreBuilder<'_> {
+        RestoreBuilder {
+            client: self,
+            user_passwords: HashMap::new(),
+            default_user_password: None,
+        }
+    }
+}
+
+/// Builder for [`ClientClient::backup()`].
+#[derive(Debug)]
+pub struct BackupBuilder<'client> {
+    client: &'client ClientClient,
+    messages: bool,
+}
+
+impl<'client> BackupBuilder<'client> {
+    /// Include messages in the backup. Enabled by default.
+    pub fn with_messages(mut self, messages: bool) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Write the archive. Messages are written page by page.
+    pub async fn write_to(
+        self,
+        writer: impl Write + Seek,
+    ) -> core::result::Result<BackupSummary, BackupError> {
+        let client = self.client;
+        let mut summary = BackupSummary::default();
+        let mut zip = ZipWriter::new(writer);
+
+        let manifest = Manifest {
+            format_version: FORMAT_VERSION,
+            server_version: client.version().await?.version,
+            created: time::OffsetDateTime::now_utc(),
+        };
+        write_json(&mut zip, "manifest.json", &manifest)?;
+
+        let users = client.get_users().await?;
+        summary.users = users.len();
+        write_json(&mut zip, "users.json", &users)?;
+
+        let applications = client
+            .get_applications()
+            .await?
+            .into_iter()
+            .filter(|application| !application.internal)
+            .collect::<Vec<_>>();
+        summary.applications = applications.len();
+        write_json(&mut zip, "applications.json", &applications)?;
+
+        for application in &applications {
+            if !application.image.starts_with("image/") {
+                continue;
+            }
+            let image = client.get_application_image(application).await?;
+            zip.start_file(&application.image, FileOptions::default())?;
+            zip.write_all(&image.content)?;
+            summary.images += 1;
+        }
+
+        let clients = client.get_clients().await?;
+        summary.clients = clients.len();
+        write_json(&mut zip, "clients.json", &clients)?;
+
+        let mut plugins = Vec::new();
+        for plugin in client.get_plugins().await? {
+            let config = if plugin.has_capability(&PluginCapability::Configurer) {
+                Some(client.get_plugin_config(plugin.id).await?)
+            } else {
+                None
+            };
+            plugins.push(PluginBackup {
+                module_path: plugin.module_path,
+                enabled: plugin.enabled,
+                config,
+            });
+        }
+        summary.plugins = plugins.len();
+        write_json(&mut zip, "plugins.json", &plugins)?;
+
+        // one entry per page, so a restore never has to hold all messages
+        if self.messages {
+            let mut pages = std::pin::pin!(client.message_pages(None, None));
+            let mut number = 0;
+            while let Some(page) = pages.try_next().await? {
+                number += 1;
+                zip.start_file(format!("messages/{number}.jsonl"), FileOptions::default())?;
+                for message in &page.messages {
+                    if applications.iter().any(|a| a.id == message.appid) {
+                        serde_json::to_writer(&mut zip, message)?;
+                        zip.write_all(b"\n")?;
+                        summary.messages += 1;
+                    }
+                }
+            }
+        }
+
+        zip.finish()?.flush()?;
+
+        Ok(summary)
+    }
+}
+
+fn write_json<W: Write + Seek>(
+    zip: &mut ZipWriter<W>,
+    name: &str,
+    value: &impl Serialize,
+) -> core::result::Result<(), BackupError> {
+    zip.start_file(name, FileOptions::default())?;
+    serde_json::to_writer_pretty(zip, value)?;
+    Ok(())
+}
+
+fn read_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> core::result::Result<Vec<u8>, BackupError> {
+    let mut content = Vec::new();
+    archive.by_name(name)?.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+/// Builder for [`ClientClient::restore()`].
+pub struct RestoreBuilder<'client> {
+    client: &'client ClientClient,
+    user_passwords: HashMap<String, String>,
+    default_user_password: Option<String>,
+}
+
+impl<'client> std::fmt::Debug for RestoreBuilder<'client> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RestoreBuilder")
+            .field("client", &self.client)
+            .field("users_with_password", &self.user_passwords.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'client> RestoreBuilder<'client> {
+    /// Set the password of a restored user.
+    pub fn with_user_password(
+        mut self,
+        name: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.user_passwords.insert(name.into(), password.into());
+        self
+    }
+    /// Set the password of restored users without a password set by [`with_user_password()`](Self::with_user_password).
+    pub fn with_default_user_password(mut self, password: impl Into<String>) -> Self {
+        self.default_user_password = Some(password.into());
+        self
+    }
+
+    /// Read the archive and restore its contents.
+    ///
+    /// Users, applications and clients that already exist are matched by name
+    /// and left unchanged. Messages that already exist in an application,
+    /// either because they were restored before or because the archive is
+    /// restored to the server it was taken from, are skipped, so restoring
+    /// the same archive twice doesn't duplicate messages.
+    ///
+    /// If the restore fails, the returned [`RestoreError`] contains everything
+    /// restored until then, including the tokens of created applications and
+    /// clients.
+    pub async fn read_from(
+        self,
+        reader: impl Read + Seek,
+    ) -> core::result::Result<RestoreReport, RestoreError> {
+        let mut report = RestoreReport::default();
+        match self.restore(reader, &mut report).await {
+            Ok(()) => Ok(report),
+            Err(source) => Err(RestoreError { report, source }),
+        }
+    }
+
+    async fn restore(
+        &self,
+        reader: impl Read + Seek,
+        report: &mut RestoreReport,
+    ) -> core::result::Result<(), BackupError> {
+        let client = self.client;
+        let mut archive = ZipArchive::new(reader)?;
+
+        let manifest: Manifest =
+            serde_json::from_slice(&read_entry(&mut archive, "manifest.json")?)?;
+        if manifest.format_version != FORMAT_VERSION {
+            return Err(BackupError::UnsupportedVersion(manifest.format_version));
+        }
+
+        let users: Vec<User> = serde_json::from_slice(&read_entry(&mut archive, "users.json")?)?;
+        let existing_users = client.get_users().await?;
+        for user in users {
+            if let Some(existing) = existing_users.iter().find(|u| u.name == user.name) {
+                report.users.insert(user.id, existing.clone());
+                continue;
+            }
+            let Some(password) = self
+                .user_passwords
+                .get(&user.name)
+                .or(self.default_user_password.as_ref())
+            else {
+                report.skipped_users.push(user.name);
+                continue;
+            };
+            let created = client.create_user(user.admin, &user.name, password).await?;
+            report.users.insert(user.id, created);
+        }
+
+        let applications: Vec<Application> =
+            serde_json::from_slice(&read_entry(&mut archive, "applications.json")?)?;
+        let existing_applications = client.get_applications().await?;
+        let mut matched_applications = Vec::new();
+        for application in applications {
+            if let Some(existing) = existing_applications
+                .iter()
+                .find(|a| a.name == application.name)
+            {
+                matched_applications.push(existing.id);
+                report.applications.insert(application.id, existing.clone());
+                continue;
+            }
+
+            let mut builder = client
+                .create_application(&application.name)
+                .with_description(&application.description);
+            if let Some(default_priority) = application.default_priority {
+                builder = builder.with_default_priority(default_priority);
+            }
+            let created = builder.await?;
+            report.applications.insert(application.id, created.clone());
+
+            if application.image.starts_with("image/") {
+                let image = read_entry(&mut archive, &application.image)?;
+                let name = application
+                    .image
+                    .rsplit('/')
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned();
+                let updated = client
+                    .upload_application_image(created.id, name, image)
+                    .await?;
+                report.applications.insert(application.id, updated);
+            }
+        }
+
+        let clients: Vec<Client> =
+            serde_json::from_slice(&read_entry(&mut archive, "clients.json")?)?;
+        let existing_clients = client.get_clients().await?;
+        for c in clients {
+            let restored = match existing_clients.iter().find(|e| e.name == c.name) {
+                Some(existing) => existing.clone(),
+                None => client.create_client(&c.name).await?,
+            };
+            report.clients.insert(c.id, restored);
+        }
+
+        let plugins: Vec<PluginBackup> =
+            serde_json::from_slice(&read_entry(&mut archive, "plugins.json")?)?;
+        for plugin in plugins {
+            let Some(existing) = client
+                .find_plugin_by_module_path(&plugin.module_path)
+                .await?
+            else {
+                report.missing_plugins.push(plugin.module_path);
+                continue;
+            };
+            if let Some(config) = plugin.config {
+                client.update_plugin_config(existing.id, config).await?;
+            }
+            if plugin.enabled && !existing.enabled {
+                client.enable_plugin(existing.id).await?;
+            }
+        }
+
+        let mut app_clients = HashMap::new();
+        for (id, application) in &report.applications {
+            let app_client: crate::AppClient = client
+                .with_access_token(application.token.as_str())
+                .map_err(Error::from)?;
+            app_clients.insert(*id, app_client);
+        }
+
+        // messages of matched applications, by their id in the archive
+        let mut restored = HashMap::new();
+        let mut originals = HashMap::new();
+        for id in matched_applications {
+            let mut pages = std::pin::pin!(client.message_pages(Some(id), None));
+            while let Some(page) = pages.try_next().await? {
+                for message in page.messages {
+                    if let Some(archive_id) = restored_id(&message) {
+                        restored.insert(archive_id, message.id);
+                    } else {
+                        originals.insert(message.id, message.date);
+                    }
+                }
+            }
+        }
+
+        // the archive contains the newest messages first
+        let mut pages = archive
+            .file_names()
+            .filter_map(|name| {
+                let number = name
+                    .strip_prefix("messages/")?
+                    .strip_suffix(".jsonl")?
+                    .parse::<u64>()
+                    .ok()?;
+                Some((number, name.to_owned()))
+            })
+            .collect::<Vec<_>>();
+        pages.sort_unstable();
+
+        for (_, name) in pages.iter().rev() {
+            let page = read_entry(&mut archive, name)?;
+            for line in page.split(|&b| b == b'\n').rev() {
+                if line.is_empty() {
+                    continue;
+                }
+                let message: Message = serde_json::from_slice(line)?;
+                let Some(app_client) = app_clients.get(&message.appid) else {
+                    continue;
+                };
+                if let Some(&id) = restored.get(&message.id) {
+                    report.messages.insert(message.id, id);
+                    continue;
+                }
+                if originals.get(&message.id) == Some(&message.date) {
+                    report.messages.insert(message.id, message.id);
+                    continue;
+                }
+                let archive_id = message.id;
+                let id = restore_message(app_client, message).await?;
+                report.messages.insert(archive_id, id);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the id in the archive of a message created by a restore.
+fn restored_id(message: &Message) -> Option<MessageId> {
+    let id = message
+        .extras
+        .as_ref()?
+        .get(RESTORED_EXTRAS_KEY)?
+        .get("id")?;
+    serde_json::from_value(id.clone()).ok()
+}
+
+async fn restore_message(
+    app_client: &crate::AppClient,
+    message: Message,
+) -> core::result::Result<MessageId, BackupError> {
+    let mut extras = message.extras.unwrap_or_default();
+    extras.insert(
+        RESTORED_EXTRAS_KEY.into(),
+        serde_json::json!({
+            "id": message.id,
+            "date": message.date.format(&time::format_description::well_known::Rfc3339).ok(),
+        }),
+    );
+
+    let mut builder = app_client
+        .create_message(message.message)
+        .with_priority(message.priority)
+        .with_extras(extras);
+    if let Some(title) = message.title {
+        builder = builder.with_title(title);
+    }
+    Ok(builder.await?.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::{models::ApplicationId, testsuite::*};
+
+    use super::RESTORED_EXTRAS_KEY;
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn backup_and_restore() -> eyre::Result<()> {
+        let client = client_client();
+
+        let mut archive = Cursor::new(Vec::new());
+        let summary = client.backup().write_to(&mut archive).await?;
+        assert_eq!(summary.users, 1);
+        assert_eq!(summary.applications, 3);
+        assert_eq!(
+            summary.messages,
+            client.get_messages().await?.messages.len()
+        );
+
+        client.delete_application(ApplicationId(3)).await?;
+
+        archive.set_position(0);
+        let report = client.restore().read_from(&mut archive).await?;
+
+        let restored = &report.applications[&ApplicationId(3)];
+        assert_eq!(restored.name, "App1");
+        assert_ne!(restored.id, ApplicationId(3));
+        // the messages of the other applications are still on the server
+        assert_eq!(report.messages.len(), summary.messages);
+        assert_eq!(
+            client.get_messages().await?.messages.len(),
+            summary.messages
+        );
+
+        let messages = client.get_application_messages(restored.id).await?.messages;
+        assert_eq!(
+            messages.iter().map(|m| &m.message).collect::<Vec<_>>(),
+            vec!["App1-Message1", "App1-Message0"]
+        );
+        assert!(messages[0]
+            .extras
+            .as_ref()
+            .unwrap()
+            .contains_key(RESTORED_EXTRAS_KEY));
+
+        // restoring again doesn't duplicate restored messages
+        archive.set_position(0);
+        let again = client.restore().read_from(&mut archive).await?;
+        assert_eq!(again.messages, report.messages);
+        assert_eq!(
+            client.get_messages().await?.messages.len(),
+            summary.messages
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,367 @@
+use std::{future::Future, pin::Pin};
+
+use futures_util::StreamExt;
+
+use crate::{ClientClient, Result};
+
+type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Run operations concurrently.
+impl ClientClient {
+    /// Run an operation for every item with a limited number of concurrent requests.
+    ///
+    /// Unlike a loop of awaited calls, a failing item doesn't abort the other
+    /// ones, unless [`BulkBuilder::with_fail_fast()`] is enabled.
+    pub fn bulk<'client, I, T, F, Fut>(
+        &'client self,
+        items: impl IntoIterator<Item = I>,
+        operation: F,
+    ) -> BulkBuilder<'client, I, T>
+    where
+        I: Clone + Send + 'client,
+        F: Fn(&'client ClientClient, I) -> Fut + Send + Sync + 'client,
+        Fut: Future<Output = Result<T>> + Send + 'client,
+    {
+        BulkBuilder {
+            client: self,
+            items: items.into_iter().collect(),
+            operation: Box::new(move |client, item| Box::pin(operation(client, item))),
+            concurrency: DEFAULT_CONCURRENCY,
+            fail_fast: false,
+            progress: None,
+        }
+    }
+    /// Delete multiple messages.
+    #[cfg(feature = "manage-messages")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-messages")))]
+    pub fn delete_messages_by_id(
+        &self,
+        ids: impl IntoIterator<Item = crate::models::MessageId>,
+    ) -> BulkBuilder<'_, crate::models::MessageId, ()> {
+        self.bulk(ids, |client, id| client.delete_message(id))
+    }
+    /// Create multiple applications by name.
+    #[cfg(feature = "manage-applications")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-applications")))]
+    pub fn create_applications(
+        &self,
+        names: impl IntoIterator<Item = impl Into<String>>,
+    ) -> BulkBuilder<'_, String, crate::models::Application> {
+        self.bulk(names.into_iter().map(Into::into), |client, name| {
+            client.create_application(name).send()
+        })
+    }
+    /// Delete multiple applications.
+    #[cfg(feature = "manage-applications")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-applications")))]
+    pub fn delete_applications(
+        &self,
+        ids: impl IntoIterator<Item = crate::models::ApplicationId>,
+    ) -> BulkBuilder<'_, crate::models::ApplicationId, ()> {
+        self.bulk(ids, |client, id| client.delete_application(id))
+    }
+    /// Delete multiple clients.
+    #[cfg(feature = "manage-clients")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-clients")))]
+    pub fn delete_clients(
+        &self,
+        ids: impl IntoIterator<Item = crate::models::ClientId>,
+    ) -> BulkBuilder<'_, crate::models::ClientId, ()> {
+        self.bulk(ids, |client, id| client.delete_client(id))
+    }
+}
+
+/// Builder for a bulk operation created by [`ClientClient::bulk()`] or one of its shorthands.
+#[allow(clippy::type_complexity)]
+pub struct BulkBuilder<'client, I, T> {
+    client: &'client ClientClient,
+    items: Vec<I>,
+    operation: Box<
+        dyn Fn(&'client ClientClient, I) -> BoxFuture<'client, Result<T>> + Send + Sync + 'client,
+    >,
+    concurrency: usize,
+    fail_fast: bool,
+    progress: Option<Box<dyn FnMut(BulkProgress) + Send + 'client>>,
+}
+
+impl<'client, I, T> BulkBuilder<'client, I, T>
+where
+    I: Clone + Send + 'client,
+    T: Send + 'client,
+{
+    /// Set the maximum number of concurrent requests. Defaults to `8`.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+    /// Stop starting new operations after the first failure.
+    ///
+    /// Operations that were already running are still awaited and reported.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.fail_fast = fail_fast;
+        self
+    }
+    /// Call a function after every finished operation.
+    pub fn with_progress(mut self, progress: impl FnMut(BulkProgress) + Send + 'client) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    #[allow(missing_docs)]
+    pub async fn send(self) -> BulkReport<I, T> {
+        let Self {
+            client,
+            items,
+            operation,
+            concurrency,
+            fail_fast,
+            mut progress,
+        } = self;
+
+        let total = items.len();
+        let operation = &operation;
+        let stopped = std::sync::atomic::AtomicBool::new(false);
+        let stopped = &stopped;
+
+        // new items are only taken from the input while no operation failed
+        let mut results = futures_util::stream::iter(items.into_iter().enumerate())
+            .take_while(|_| std::future::ready(!stopped.load(std::sync::atomic::Ordering::Relaxed)))
+            .map(|(index, item)| async move {
+                let result = operation(client, item.clone()).await;
+                (index, item, result)
+            })
+            .buffer_unordered(concurrency);
+
+        let mut report = BulkReport {
+            results: Vec::with_capacity(total),
+            skipped: 0,
+        };
+        let mut indices = Vec::with_capacity(total);
+        let mut failed = 0;
+
+        while let Some((index, item, result)) = results.next().await {
+            if result.is_err() {
+                failed += 1;
+            }
+            indices.push(index);
+            report.results.push((item, result));
+
+            if let Some(progress) = &mut progress {
+                progress(BulkProgress {
+                    completed: report.results.len(),
+                    failed,
+                    total,
+                });
+            }
+
+            if fail_fast && failed > 0 {
+                stopped.store(true, std::sync::atomic::Ordering::Relaxed);
+            }
+        }
+
+        // restore the order of the input items
+        let mut results = indices.into_iter().zip(report.results).collect::<Vec<_>>();
+        results.sort_by_key(|(index, _)| *index);
+        report.results = results.into_iter().map(|(_, result)| result).collect();
+        report.skipped = total - report.results.len();
+
+        report
+    }
+}
+
+impl<'client, I, T> std::future::IntoFuture for BulkBuilder<'client, I, T>
+where
+    I: Clone + Send + 'client,
+    T: Send + 'client,
+{
+    type Output = BulkReport<I, T>;
+    type IntoFuture = BoxFuture<'client, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+impl<'client, I: std::fmt::Debug, T> std::fmt::Debug for BulkBuilder<'client, I, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BulkBuilder")
+            .field("items", &self.items)
+            .field("concurrency", &self.concurrency)
+            .field("fail_fast", &self.fail_fast)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The progress of a bulk operation, passed to [`BulkBuilder::with_progress()`].
+#[derive(Clone, Copy, Debug)]
+#[non_exhaustive]
+pub struct BulkProgress {
+    /// The number of finished operations.
+    pub completed: usize,
+    /// The number of failed operations.
+    pub failed: usize,
+    /// The total number of items.
+    pub total: usize,
+}
+
+/// The results of a bulk operation.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct BulkReport<I, T> {
+    /// The result for every item that was processed, in the order of the input.
+    pub results: Vec<(I, Result<T>)>,
+    /// The number of items that weren't processed because of [`BulkBuilder::with_fail_fast()`].
+    pub skipped: usize,
+}
+
+impl<I, T> BulkReport<I, T> {
+    /// Check whether every item was processed successfully.
+    pub fn is_success(&self) -> bool {
+        self.skipped == 0 && self.results.iter().all(|(_, result)| result.is_ok())
+    }
+    /// Return the items that were processed successfully with their results.
+    pub fn succeeded(&self) -> impl Iterator<Item = (&I, &T)> {
+        self.results
+            .iter()
+            .filter_map(|(item, result)| result.as_ref().ok().map(|value| (item, value)))
+    }
+    /// Return the items that failed with their errors.
+    pub fn failed(&self) -> impl Iterator<Item = (&I, &crate::Error)> {
+        self.results
+            .iter()
+            .filter_map(|(item, result)| result.as_ref().err().map(|error| (item, error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testsuite::*;
+
+    #[tokio::test]
+    async fn fail_fast_awaits_running_operations() -> eyre::Result<()> {
+        use std::{
+            sync::atomic::{AtomicBool, Ordering},
+            time::Duration,
+        };
+
+        use crate::{ClientClient, Error, InitError};
+
+        let client = ClientClient::new("http://localhost:30081", GOTIFY_CLIENT_TOKEN)?;
+        let finished = AtomicBool::new(false);
+
+        let report = client
+            .bulk(0..4, |_, item| {
+                let finished = &finished;
+                async move {
+                    match item {
+                        0 => {
+                            tokio::time::sleep(Duration::from_millis(100)).await;
+                            finished.store(true, Ordering::Relaxed);
+                            Ok(item)
+                        }
+                        _ => Err(Error::Init(InitError::InvalidUrl(
+                            url::ParseError::EmptyHost,
+                        ))),
+                    }
+                }
+            })
+            .with_concurrency(2)
+            .with_fail_fast(true)
+            .await;
+
+        assert!(finished.load(Ordering::Relaxed));
+        assert_eq!(
+            report
+                .results
+                .iter()
+                .map(|(item, _)| *item)
+                .collect::<Vec<_>>(),
+            [0, 1]
+        );
+        assert!(report.results[0].1.is_ok());
+        assert_eq!(report.skipped, 2);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manage-messages")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn delete_messages_by_id() -> eyre::Result<()> {
+        use std::sync::{Arc, Mutex};
+
+        use crate::models::MessageId;
+
+        let client = client_client();
+
+        let mut ids = client
+            .get_messages()
+            .await?
+            .messages
+            .into_iter()
+            .map(|m| m.id)
+            .collect::<Vec<_>>();
+        ids.push(MessageId(999_999));
+
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let report = client
+            .delete_messages_by_id(ids.clone())
+            .with_concurrency(3)
+            .with_progress({
+                let progress = progress.clone();
+                move |p| progress.lock().unwrap().push(p.completed)
+            })
+            .await;
+
+        assert!(!report.is_success());
+        assert_eq!(
+            report.results.iter().map(|(id, _)| *id).collect::<Vec<_>>(),
+            ids
+        );
+        assert_eq!(
+            report.failed().map(|(id, _)| *id).collect::<Vec<_>>(),
+            vec![MessageId(999_999)]
+        );
+        assert_eq!(progress.lock().unwrap().len(), ids.len());
+        assert!(client.get_messages().await?.messages.is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manage-applications")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn create_applications() -> eyre::Result<()> {
+        let client = client_client();
+
+        let report = client
+            .create_applications((0..20).map(|i| format!("bulk-{i}")))
+            .await;
+
+        assert!(report.is_success());
+        assert_eq!(report.succeeded().count(), 20);
+        assert!(report.succeeded().all(|(name, app)| *name == app.name));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manage-clients")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn delete_clients_fail_fast() -> eyre::Result<()> {
+        use crate::models::ClientId;
+
+        let report = client_client()
+            .delete_clients([ClientId(999_998), ClientId(999_999)])
+            .with_concurrency(1)
+            .with_fail_fast(true)
+            .await;
+
+        assert_eq!(report.results.len(), 1);
+        assert_eq!(report.skipped, 1);
+
+        Ok(())
+    }
+}
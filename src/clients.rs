@@ -1,6 +1,10 @@
 use reqwest::Method;
 
-use crate::{models::Client, utils::request_builder, ClientClient, Result};
+use crate::{
+    models::{Client, ClientId},
+    utils::request_builder,
+    ClientClient, Result,
+};
 
 /// List, create, update or delete clients.
 impl ClientClient {
@@ -11,15 +15,15 @@ impl ClientClient {
             .await
     }
     /// Create a client.
-    pub fn create_client(&self, name: impl Into<String>) -> ClientBuilder {
+    pub fn create_client(&self, name: impl Into<String>) -> ClientBuilder<'_> {
         ClientBuilder::new(self, name)
     }
     /// Update a client.
-    pub fn update_client(&self, id: i64, name: impl Into<String>) -> ClientUpdateBuilder {
+    pub fn update_client(&self, id: ClientId, name: impl Into<String>) -> ClientUpdateBuilder<'_> {
         ClientUpdateBuilder::new(self, id, name)
     }
     /// Delete a client.
-    pub async fn delete_client(&self, id: i64) -> Result<()> {
+    pub async fn delete_client(&self, id: ClientId) -> Result<()> {
         self.request(Method::DELETE, ["client".into(), id.to_string()])
             .send()
             .await
@@ -45,7 +49,7 @@ request_builder! {
     return_type = Client,
     required_fields = {
         #[serde(skip)]
-        id: i64 => i64,
+        id: ClientId => ClientId,
         name: impl Into<String> => .into() => String,
     },
     optional_fields = {}
@@ -53,7 +57,7 @@ request_builder! {
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use crate::testsuite::*;
+    use crate::{models::ClientId, testsuite::*};
 
     #[apply(run_test_server!)]
     #[test]
@@ -89,7 +93,7 @@ pub(crate) mod tests {
             .iter()
             .any(|a| a.name == "new-client-name"));
 
-        let client = client.update_client(1, "new-client-name").await?;
+        let client = client.update_client(ClientId(1), "new-client-name").await?;
 
         assert_eq!(client.name, "new-client-name");
 
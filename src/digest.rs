@@ -0,0 +1,407 @@
+use std::{
+    collections::{hash_map, HashMap},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{models::Message, utils::markdown_extras, AppClient, Result};
+
+/// Combine bursts of messages into digest messages.
+///
+/// Messages are grouped by a key, which is their title unless it is supplied
+/// with [`with_digest_key()`](DigestMessageBuilder::with_digest_key). A group
+/// is sent once its window has passed since its first message or once it
+/// reached the maximum number of entries. A group with a single message is
+/// sent unchanged, larger groups are combined into one markdown message that
+/// lists all entries and has their highest priority. The extras of combined
+/// messages are dropped.
+///
+/// Groups whose window has passed are sent by
+/// [`flush_expired()`](Self::flush_expired), which is called after every
+/// message and periodically by [`run()`](Self::run). Call
+/// [`flush()`](Self::flush) before shutting down to send all pending groups.
+/// A group that couldn't be sent stays pending and is sent by the next flush.
+#[derive(Debug)]
+pub struct DigestAppClient {
+    client: AppClient,
+    window: Duration,
+    max_entries: usize,
+    groups: Mutex<HashMap<String, Group>>,
+}
+
+#[derive(Debug)]
+struct Group {
+    since: Instant,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    message: String,
+    title: Option<String>,
+    priority: Option<u8>,
+    extras: Option<HashMap<String, serde_json::Value>>,
+}
+
+impl DigestAppClient {
+    /// The default time after the first message of a group at which the group is sent.
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+    /// The default number of entries at which a group is sent immediately.
+    pub const DEFAULT_MAX_ENTRIES: usize = 50;
+    /// The extras key of digest messages, containing the key and the number of entries.
+    pub const DIGEST_EXTRAS_KEY: &'static str = "gotify-rs::digest";
+
+    /// Wrap an [`AppClient`].
+    pub fn new(client: AppClient) -> Self {
+        Self {
+            client,
+            window: Self::DEFAULT_WINDOW,
+            max_entries: Self::DEFAULT_MAX_ENTRIES,
+            groups: Mutex::default(),
+        }
+    }
+    /// Send a group once `window` has passed since its first message.
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+    /// Send a group immediately once it has `max_entries` entries.
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Add a message to its group.
+    pub fn create_message(&self, message: impl Into<String>) -> DigestMessageBuilder<'_> {
+        DigestMessageBuilder {
+            client: self,
+            entry: Entry {
+                message: message.into(),
+                title: None,
+                priority: None,
+                extras: None,
+            },
+            key: None,
+        }
+    }
+
+    /// Return the number of messages waiting to be sent.
+    pub fn pending(&self) -> usize {
+        self.groups
+            .lock()
+            .unwrap()
+            .values()
+            .map(|group| group.entries.len())
+            .sum()
+    }
+
+    /// Send the groups whose window has passed and return how many messages were sent.
+    pub async fn flush_expired(&self) -> Result<usize> {
+        let now = Instant::now();
+        self.send_groups(|group| now.saturating_duration_since(group.since) >= self.window)
+            .await
+    }
+
+    /// Send all pending groups and return how many messages were sent.
+    pub async fn flush(&self) -> Result<usize> {
+        self.send_groups(|_| true).await
+    }
+
+    /// Call [`flush_expired()`](Self::flush_expired) every `period` until a request fails.
+    pub async fn run(&self, period: Duration) -> Result<()> {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.flush_expired().await?;
+        }
+    }
+
+    async fn send_groups(&self, mut select: impl FnMut(&Group) -> bool) -> Result<usize> {
+        let keys = self
+            .groups
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, group)| select(group))
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+
+        let mut count = 0;
+        for key in keys {
+            let Some(group) = self.groups.lock().unwrap().remove(&key) else {
+                continue;
+            };
+            if let Err(e) = self.send_group(&key, &group.entries).await {
+                self.restore_group(key, group);
+                return Err(e);
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Put back a group that couldn't be sent, before any entries added meanwhile.
+    fn restore_group(&self, key: String, group: Group) {
+        match self.groups.lock().unwrap().entry(key) {
+            hash_map::Entry::Occupied(mut occupied) => {
+                let newer = std::mem::replace(occupied.get_mut(), group);
+                occupied.get_mut().entries.extend(newer.entries);
+            }
+            hash_map::Entry::Vacant(vacant) => {
+                vacant.insert(group);
+            }
+        }
+    }
+
+    async fn send_group(&self, key: &str, entries: &[Entry]) -> Result<Message> {
+        if let [entry] = entries {
+            let mut builder = self.client.create_message(entry.message.clone());
+            if let Some(title) = &entry.title {
+                builder = builder.with_title(title.clone());
+            }
+            if let Some(priority) = entry.priority {
+                builder = builder.with_priority(priority);
+            }
+            if let Some(extras) = &entry.extras {
+                builder = builder.with_extras(extras.clone());
+            }
+            return builder.await;
+        }
+
+        let mut extras = markdown_extras();
+        extras.insert(
+            Self::DIGEST_EXTRAS_KEY.into(),
+            serde_json::json!({ "key": key, "count": entries.len() }),
+        );
+
+        let mut builder = self
+            .client
+            .create_message(render_digest(entries))
+            .with_title(digest_title(key, entries))
+            .with_extras(extras);
+        if let Some(priority) = entries.iter().filter_map(|entry| entry.priority).max() {
+            builder = builder.with_priority(priority);
+        }
+        builder.await
+    }
+}
+
+fn digest_title(key: &str, entries: &[Entry]) -> String {
+    let name = match entries[0].title.as_deref() {
+        Some(title) if !title.is_empty() => title,
+        _ if !key.is_empty() => key,
+        _ => "Messages",
+    };
+    format!("{name} ({})", entries.len())
+}
+
+/// Render the entries of a group as a markdown list.
+fn render_digest(entries: &[Entry]) -> String {
+    let mut digest = String::new();
+    for entry in entries {
+        digest.push_str("- ");
+        if let Some(title) = entry.title.as_deref().filter(|title| !title.is_empty()) {
+            digest.push_str(&format!("**{title}**: "));
+        }
+        let mut lines = entry.message.lines();
+        digest.push_str(lines.next().unwrap_or_default());
+        for line in lines {
+            digest.push_str("\n  ");
+            digest.push_str(line);
+        }
+        digest.push('\n');
+    }
+    digest
+}
+
+/// Builder for a message sent by a [`DigestAppClient`].
+#[derive(Debug)]
+pub struct DigestMessageBuilder<'client> {
+    client: &'client DigestAppClient,
+    entry: Entry,
+    key: Option<String>,
+}
+
+#[allow(missing_docs)]
+impl<'client> DigestMessageBuilder<'client> {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.entry.title = Some(title.into());
+        self
+    }
+    pub fn with_extras(mut self, extras: impl Into<HashMap<String, serde_json::Value>>) -> Self {
+        self.entry.extras = Some(extras.into());
+        self
+    }
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.entry.priority = Some(priority);
+        self
+    }
+    /// Group the message by this key instead of its title.
+    pub fn with_digest_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+    /// Add the message to its group and send expired or full groups.
+    ///
+    /// If a request fails, the message stays queued and is sent with its group
+    /// by the next flush.
+    pub async fn send(self) -> Result<DigestOutcome> {
+        let client = self.client;
+        let key = self
+            .key
+            .or_else(|| self.entry.title.clone())
+            .unwrap_or_default();
+
+        // queue the message before any request, so a failure doesn't lose it
+        let full = {
+            let mut groups = client.groups.lock().unwrap();
+            let group = groups.entry(key.clone()).or_insert_with(|| Group {
+                since: Instant::now(),
+                entries: Vec::new(),
+            });
+            group.entries.push(self.entry);
+            if group.entries.len() >= client.max_entries {
+                groups.remove(&key)
+            } else {
+                None
+            }
+        };
+
+        if let Some(group) = full {
+            return match client.send_group(&key, &group.entries).await {
+                Ok(message) => Ok(DigestOutcome::Sent(message)),
+                Err(e) => {
+                    client.restore_group(key, group);
+                    Err(e)
+                }
+            };
+        }
+        client.flush_expired().await?;
+        Ok(DigestOutcome::Queued)
+    }
+}
+
+impl<'client> std::future::IntoFuture for DigestMessageBuilder<'client> {
+    type Output = Result<DigestOutcome>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// The result of adding a message to a [`DigestAppClient`].
+#[derive(Debug)]
+pub enum DigestOutcome {
+    /// The message completed its group, which was sent as this message.
+    Sent(Message),
+    /// The message waits for its group to be sent.
+    Queued,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::testsuite::*;
+
+    use super::{render_digest, DigestAppClient, DigestOutcome, Entry};
+
+    #[test]
+    fn markdown_list() {
+        let entries = [
+            Entry {
+                message: "job 1 failed\nexit code 2".into(),
+                title: Some("CI".into()),
+                priority: Some(5),
+                extras: None,
+            },
+            Entry {
+                message: "job 2 failed".into(),
+                title: None,
+                priority: None,
+                extras: None,
+            },
+        ];
+
+        assert_eq!(
+            render_digest(&entries),
+            "- **CI**: job 1 failed\n  exit code 2\n- job 2 failed\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_groups_stay_pending() -> eyre::Result<()> {
+        let client = DigestAppClient::new(crate::AppClient::new(
+            "http://localhost:30081",
+            GOTIFY_APP_TOKEN,
+        )?)
+        .with_window(Duration::ZERO)
+        .with_max_entries(2);
+
+        assert!(client
+            .create_message("job 1 failed")
+            .with_title("CI")
+            .await
+            .is_err());
+        assert!(client
+            .create_message("deployed")
+            .with_title("CD")
+            .await
+            .is_err());
+        assert_eq!(client.pending(), 2);
+
+        // the full group is put back when it can't be sent
+        assert!(client
+            .create_message("job 2 failed")
+            .with_title("CI")
+            .await
+            .is_err());
+        assert_eq!(client.pending(), 3);
+        assert!(client.flush().await.is_err());
+        assert_eq!(client.pending(), 3);
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn digest() -> eyre::Result<()> {
+        let client = DigestAppClient::new((*app_client()).clone())
+            .with_window(Duration::from_secs(3600))
+            .with_max_entries(3);
+
+        for (job, priority) in [("job 1", 2), ("job 2", 8)] {
+            let outcome = client
+                .create_message(format!("{job} failed"))
+                .with_title("CI")
+                .with_priority(priority)
+                .await?;
+            assert!(matches!(outcome, DigestOutcome::Queued));
+        }
+        client.create_message("deployed").with_title("CD").await?;
+        assert_eq!(client.pending(), 3);
+
+        let DigestOutcome::Sent(digest) = client
+            .create_message("job 3 failed")
+            .with_title("CI")
+            .with_priority(4)
+            .await?
+        else {
+            panic!("the full group wasn't sent");
+        };
+        assert_eq!(digest.title.as_deref(), Some("CI (3)"));
+        assert_eq!(digest.priority, 8);
+        assert_eq!(
+            digest.message,
+            "- **CI**: job 1 failed\n- **CI**: job 2 failed\n- **CI**: job 3 failed\n"
+        );
+
+        assert_eq!(client.flush().await?, 1);
+        assert_eq!(client.pending(), 0);
+
+        Ok(())
+    }
+}
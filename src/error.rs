@@ -15,11 +15,33 @@ pub enum InitError {
 /// Errors that can occur when accessing an API endpoint.
 #[allow(missing_docs)]
 #[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
 pub enum Error {
     #[error("HTTP request failed")]
     Reqwest(#[from] reqwest::Error),
     #[error("Gotify's API returned an error")]
     Response(#[from] crate::models::Error),
+    #[error("failed to create a derived client")]
+    Init(#[from] InitError),
+    #[cfg(feature = "manage-plugins")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
+    #[error("no plugin with module path {0:?} is installed")]
+    PluginNotFound(String),
+    #[cfg(feature = "manage-plugins")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
+    #[error("plugin {id} does not have the {capability} capability")]
+    MissingCapability {
+        id: crate::models::PluginId,
+        capability: crate::models::PluginCapability,
+    },
+    /// An unsuccessful response of a custom plugin route that isn't a Gotify error.
+    #[cfg(feature = "manage-plugins")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
+    #[error("the plugin responded with {status}")]
+    PluginResponse {
+        status: reqwest::StatusCode,
+        body: String,
+    },
 }
 
 /// Alias for the `Result` returned when accessing an API endpoint.
@@ -0,0 +1,433 @@
+//! Export messages as JSON Lines, CSV or Markdown.
+//!
+//! [`ClientClient::export_messages()`] pages through the message history and
+//! writes every page as soon as it is received, so exports of any size can be
+//! written to files or sockets without keeping all messages in memory.
+//!
+//! ```no_run
+//! # async fn run(client: gotify::ClientClient) -> Result<(), gotify::export::ExportError> {
+//! use gotify::{export::ExportFormat, models::ApplicationId};
+//!
+//! let socket = tokio::net::TcpStream::connect("archive.example.com:9000").await?;
+//! let summary = client
+//!     .export_messages(ExportFormat::csv())
+//!     .with_application(ApplicationId(3))
+//!     .write_to_async(tokio::io::BufWriter::new(socket))
+//!     .await?;
+//! println!("exported {} messages", summary.exported);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{borrow::Cow, collections::HashMap, io::Write, pin::Pin};
+
+use futures_util::{Stream, TryStreamExt};
+use time::{format_description::well_known::Rfc3339, Date, OffsetDateTime};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::{
+    models::{ApplicationId, Message, PagedMessages},
+    ClientClient, Error,
+};
+
+/// The format of an export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExportFormat {
+    /// One JSON object per line, containing all fields including the extras.
+    JsonLines,
+    /// Comma-separated values with a header row.
+    Csv(Vec<CsvColumn>),
+    /// A human-readable report grouped by day.
+    Markdown,
+}
+
+impl ExportFormat {
+    /// CSV with the [default columns](CsvColumn::DEFAULT).
+    pub fn csv() -> Self {
+        Self::Csv(CsvColumn::DEFAULT.to_vec())
+    }
+}
+
+/// A column of a CSV export.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CsvColumn {
+    /// The message id.
+    Id,
+    /// The date in RFC 3339 format.
+    Date,
+    /// The id of the application.
+    ApplicationId,
+    /// The name of the application.
+    Application,
+    /// The priority.
+    Priority,
+    /// The title, empty if the message has none.
+    Title,
+    /// The message text.
+    Message,
+    /// All extras as a JSON object.
+    Extras,
+    /// A single top-level extras key. Strings are written as is, other values as JSON.
+    Extra(String),
+}
+
+impl CsvColumn {
+    /// The columns used by [`ExportFormat::csv()`].
+    pub const DEFAULT: &'static [CsvColumn] = &[
+        CsvColumn::Id,
+        CsvColumn::Date,
+        CsvColumn::Application,
+        CsvColumn::Priority,
+        CsvColumn::Title,
+        CsvColumn::Message,
+    ];
+
+    fn header(&self) -> Cow<'_, str> {
+        match self {
+            CsvColumn::Id => "id".into(),
+            CsvColumn::Date => "date".into(),
+            CsvColumn::ApplicationId => "application_id".into(),
+            CsvColumn::Application => "application".into(),
+            CsvColumn::Priority => "priority".into(),
+            CsvColumn::Title => "title".into(),
+            CsvColumn::Message => "message".into(),
+            CsvColumn::Extras => "extras".into(),
+            CsvColumn::Extra(key) => format!("extras.{key}").into(),
+        }
+    }
+}
+
+/// Errors that can occur when exporting messages.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum ExportError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to write the export")]
+    Io(#[from] std::io::Error),
+    #[error("failed to serialize a message")]
+    Json(#[from] serde_json::Error),
+}
+
+/// The result of a finished export.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ExportSummary {
+    /// The number of exported messages.
+    pub exported: usize,
+}
+
+/// Export messages.
+impl ClientClient {
+    /// Export messages, newest first.
+    pub fn export_messages(&self, format: ExportFormat) -> ExportBuilder<'_> {
+        ExportBuilder {
+            client: self,
+            format,
+            applications: Vec::new(),
+            since: None,
+            until: None,
+        }
+    }
+}
+
+/// Builder for [`ClientClient::export_messages()`].
+#[derive(Debug)]
+pub struct ExportBuilder<'client> {
+    client: &'client ClientClient,
+    format: ExportFormat,
+    applications: Vec<ApplicationId>,
+    since: Option<OffsetDateTime>,
+    until: Option<OffsetDateTime>,
+}
+
+impl<'client> ExportBuilder<'client> {
+    /// Only export messages of this application.
+    ///
+    /// Can be called multiple times to export the messages of multiple applications.
+    pub fn with_application(mut self, id: ApplicationId) -> Self {
+        self.applications.push(id);
+        self
+    }
+    /// Only export messages created at or after this time.
+    pub fn with_since(mut self, since: OffsetDateTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+    /// Only export messages created at or before this time.
+    pub fn with_until(mut self, until: OffsetDateTime) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Write the export to a blocking writer.
+    ///
+    /// The writes block the async runtime's thread, so this is meant for writers
+    /// that don't wait for long, like buffers or local files wrapped in a
+    /// [`BufWriter`](std::io::BufWriter). Use [`write_to_async()`](Self::write_to_async)
+    /// for sockets and other slow writers.
+    pub async fn write_to(
+        self,
+        mut writer: impl Write,
+    ) -> core::result::Result<ExportSummary, ExportError> {
+        let mut exporter = Exporter::new(self).await?;
+        while let Some(chunk) = exporter.next_chunk().await? {
+            writer.write_all(&chunk)?;
+        }
+        writer.flush()?;
+
+        Ok(exporter.summary)
+    }
+    /// Write the export to an asynchronous writer.
+    pub async fn write_to_async(
+        self,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> core::result::Result<ExportSummary, ExportError> {
+        let mut exporter = Exporter::new(self).await?;
+        while let Some(chunk) = exporter.next_chunk().await? {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+
+        Ok(exporter.summary)
+    }
+}
+
+type Pages<'client> = Pin<Box<dyn Stream<Item = Result<PagedMessages, Error>> + Send + 'client>>;
+
+/// Renders one page of messages at a time.
+struct Exporter<'client> {
+    builder: ExportBuilder<'client>,
+    application_names: HashMap<ApplicationId, String>,
+    pages: Pages<'client>,
+    header_written: bool,
+    done: bool,
+    current_day: Option<Date>,
+    summary: ExportSummary,
+}
+
+impl<'client> Exporter<'client> {
+    async fn new(builder: ExportBuilder<'client>) -> core::result::Result<Self, ExportError> {
+        let application_names = builder
+            .client
+            .get_applications()
+            .await?
+            .into_iter()
+            .map(|application| (application.id, application.name))
+            .collect();
+
+        let single_application = match builder.applications[..] {
+            [id] => Some(id),
+            _ => None,
+        };
+
+        Ok(Self {
+            pages: Box::pin(builder.client.message_pages(single_application, None)),
+            builder,
+            application_names,
+            header_written: false,
+            done: false,
+            current_day: None,
+            summary: ExportSummary { exported: 0 },
+        })
+    }
+
+    async fn next_chunk(&mut self) -> core::result::Result<Option<Vec<u8>>, ExportError> {
+        let mut chunk = Vec::new();
+
+        if !self.header_written {
+            self.header_written = true;
+            self.write_header(&mut chunk);
+        }
+        if self.done {
+            return Ok((!chunk.is_empty()).then_some(chunk));
+        }
+
+        let Some(page) = self.pages.try_next().await? else {
+            self.done = true;
+            return Ok((!chunk.is_empty()).then_some(chunk));
+        };
+
+        for message in &page.messages {
+            // messages are sorted from newest to oldest
+            if self.builder.since.is_some_and(|since| message.date < since) {
+                self.done = true;
+                break;
+            }
+            if self.builder.until.is_some_and(|until| message.date > until)
+                || !self.builder.applications.is_empty()
+                    && !self.builder.applications.contains(&message.appid)
+            {
+                continue;
+            }
+
+            self.write_message(&mut chunk, message)?;
+            self.summary.exported += 1;
+        }
+
+        Ok(Some(chunk))
+    }
+
+    fn write_header(&self, out: &mut Vec<u8>) {
+        match &self.builder.format {
+            ExportFormat::JsonLines => {}
+            ExportFormat::Csv(columns) => {
+                write_csv_row(out, columns.iter().map(CsvColumn::header));
+            }
+            ExportFormat::Markdown => out.extend_from_slice(b"# Messages\n"),
+        }
+    }
+
+    fn write_message(
+        &mut self,
+        out: &mut Vec<u8>,
+        message: &Message,
+    ) -> core::result::Result<(), ExportError> {
+        let application = self
+            .application_names
+            .get(&message.appid)
+            .map(String::as_str)
+            .unwrap_or_default();
+
+        match &self.builder.format {
+            ExportFormat::JsonLines => {
+                serde_json::to_writer(&mut *out, message)?;
+                out.push(b'\n');
+            }
+            ExportFormat::Csv(columns) => {
+                write_csv_row(
+                    out,
+                    columns
+                        .iter()
+                        .map(|column| csv_value(column, message, application)),
+                );
+            }
+            ExportFormat::Markdown => {
+                let day = message.date.date();
+                if self.current_day != Some(day) {
+                    self.current_day = Some(day);
+                    writeln!(out, "\n## {day}")?;
+                }
+
+                let (hour, minute, second) = message.date.to_hms();
+                write!(out, "\n### {hour:02}:{minute:02}:{second:02}")?;
+                if let Some(title) = &message.title {
+                    write!(out, " {title}")?;
+                }
+                writeln!(
+                    out,
+                    "\n\n*{application} · priority {} · #{}*\n\n{}",
+                    message.priority, message.id, message.message
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn csv_value<'a>(column: &CsvColumn, message: &'a Message, application: &'a str) -> Cow<'a, str> {
+    match column {
+        CsvColumn::Id => message.id.to_string().into(),
+        CsvColumn::Date => message.date.format(&Rfc3339).unwrap_or_default().into(),
+        CsvColumn::ApplicationId => message.appid.to_string().into(),
+        CsvColumn::Application => application.into(),
+        CsvColumn::Priority => message.priority.to_string().into(),
+        CsvColumn::Title => message.title.as_deref().unwrap_or_default().into(),
+        CsvColumn::Message => message.message.as_str().into(),
+        CsvColumn::Extras => message
+            .extras
+            .as_ref()
+            .map(|extras| serde_json::to_string(extras).unwrap_or_default())
+            .unwrap_or_default()
+            .into(),
+        CsvColumn::Extra(key) => match message.extras.as_ref().and_then(|e| e.get(key)) {
+            Some(serde_json::Value::String(value)) => value.as_str().into(),
+            Some(value) => value.to_string().into(),
+            None => "".into(),
+        },
+    }
+}
+
+fn write_csv_row<'a>(out: &mut Vec<u8>, fields: impl Iterator<Item = Cow<'a, str>>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        if field.contains([',', '"', '\n', '\r']) {
+            out.push(b'"');
+            out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    out.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{models::ApplicationId, testsuite::*};
+
+    use super::{write_csv_row, CsvColumn, ExportFormat};
+
+    #[test]
+    fn csv_quoting() {
+        let mut out = Vec::new();
+        write_csv_row(
+            &mut out,
+            ["plain", "a,b", "say \"hi\"", "two\nlines"]
+                .into_iter()
+                .map(Into::into),
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\"\n"
+        );
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn export_messages() -> eyre::Result<()> {
+        let client = client_client();
+
+        let mut out = Vec::new();
+        let summary = client
+            .export_messages(ExportFormat::JsonLines)
+            .write_to(&mut out)
+            .await?;
+        assert_eq!(String::from_utf8(out)?.lines().count(), summary.exported);
+        assert_eq!(
+            summary.exported,
+            client.get_messages().await?.messages.len()
+        );
+
+        let mut out = Vec::new();
+        client
+            .export_messages(ExportFormat::Csv(vec![
+                CsvColumn::Application,
+                CsvColumn::Message,
+            ]))
+            .with_application(ApplicationId(3))
+            .write_to_async(&mut out)
+            .await?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "application,message\nApp1,App1-Message1\nApp1,App1-Message0\n"
+        );
+
+        let mut out = Vec::new();
+        client
+            .export_messages(ExportFormat::Markdown)
+            .with_application(ApplicationId(3))
+            .write_to(&mut out)
+            .await?;
+        let report = String::from_utf8(out)?;
+        assert!(report.starts_with("# Messages\n\n## "));
+        assert!(report.contains("*App1 · priority"));
+
+        Ok(())
+    }
+}
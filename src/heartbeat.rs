@@ -0,0 +1,784 @@
+//! Alert when jobs stop checking in.
+//!
+//! A [`HeartbeatMonitor`] watches named [`Check`]s. Every job calls
+//! [`ping()`](HeartbeatMonitor::ping) or requests `/ping/<name>` from the
+//! [HTTP endpoint](HeartbeatMonitor::serve) when it ran. If a check isn't
+//! pinged within its interval plus grace period, an alert is sent, and a
+//! recovery message follows with the next ping.
+//!
+//! ```no_run
+//! # async fn monitor() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::time::Duration;
+//!
+//! use gotify::heartbeat::{Check, HeartbeatMonitor};
+//!
+//! let client: gotify::AppClient = gotify::Client::new("https://gotify.example.com", "app-token")?;
+//! let monitor = HeartbeatMonitor::open(client, "heartbeats.json")?
+//!     .with_check(
+//!         Check::new("nightly-backup", Duration::from_secs(24 * 3600))
+//!             .with_grace_period(Duration::from_secs(3600)),
+//!     )
+//!     .with_on_failure(|check, error| eprintln!("failed to notify about {check}: {error}"));
+//! let monitor = std::sync::Arc::new(monitor);
+//!
+//! // the backup job runs `curl -X POST http://localhost:8090/ping/nightly-backup`
+//! let listener = tokio::net::TcpListener::bind("127.0.0.1:8090").await?;
+//! tokio::try_join!(monitor.run(), monitor.clone().serve(listener))?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The time of the last ping and whether an alert was sent are persisted, so
+//! a restart neither resets the deadlines nor repeats alerts.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::{models::Message, utils::write_atomically, AppClient, Error};
+
+/// The extras key of alert and recovery messages, containing the check's name and status.
+pub const HEARTBEAT_EXTRAS_KEY: &str = "gotify-rs::heartbeat";
+
+/// A job that is expected to ping regularly.
+#[derive(Clone, Debug)]
+pub struct Check {
+    name: String,
+    interval: Duration,
+    grace_period: Duration,
+    priority: u8,
+    recovery_priority: u8,
+}
+
+impl Check {
+    /// The priority of alerts by default.
+    pub const DEFAULT_PRIORITY: u8 = 8;
+    /// The priority of recovery messages by default.
+    pub const DEFAULT_RECOVERY_PRIORITY: u8 = 4;
+
+    /// Expect a ping every `interval`.
+    pub fn new(name: impl Into<String>, interval: Duration) -> Self {
+        Self {
+            name: name.into(),
+            interval,
+            grace_period: Duration::ZERO,
+            priority: Self::DEFAULT_PRIORITY,
+            recovery_priority: Self::DEFAULT_RECOVERY_PRIORITY,
+        }
+    }
+    /// Only alert if the ping is late by more than `grace_period`.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+    /// Send alerts with this priority.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Send recovery messages with this priority.
+    pub fn with_recovery_priority(mut self, priority: u8) -> Self {
+        self.recovery_priority = priority;
+        self
+    }
+
+    fn deadline(&self, state: &CheckState) -> OffsetDateTime {
+        let since = state.last_ping.unwrap_or(state.first_seen);
+        since
+            .checked_add(
+                time::Duration::try_from(self.interval + self.grace_period)
+                    .unwrap_or(time::Duration::MAX),
+            )
+            .unwrap_or(OffsetDateTime::now_utc() + time::Duration::weeks(52 * 100))
+    }
+}
+
+/// The persisted state of a check.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct CheckState {
+    /// When the check was first monitored.
+    #[serde(with = "time::serde::iso8601")]
+    pub first_seen: OffsetDateTime,
+    /// The last ping of the check.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub last_ping: Option<OffsetDateTime>,
+    /// When the alert was sent, `None` if the check is up.
+    #[serde(default, with = "time::serde::iso8601::option")]
+    pub down_since: Option<OffsetDateTime>,
+}
+
+/// Errors that can occur when using a [`HeartbeatMonitor`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum HeartbeatError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to read or write the state file")]
+    Io(#[from] std::io::Error),
+    #[error("invalid state file")]
+    Format(#[from] serde_json::Error),
+    #[error("there is no check named {0:?}")]
+    UnknownCheck(String),
+}
+
+/// The result of [`HeartbeatMonitor::check()`].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct CheckReport {
+    /// The checks an alert was sent for.
+    pub alerted: Vec<String>,
+    /// The checks a delayed recovery message was sent for.
+    pub recovered: Vec<String>,
+    /// The checks whose alert or recovery message couldn't be sent.
+    pub failed: Vec<(String, Error)>,
+}
+
+type FailureHandler = Box<dyn Fn(&str, &Error) + Send + Sync>;
+
+/// Sends alerts for checks that weren't pinged in time.
+pub struct HeartbeatMonitor {
+    client: AppClient,
+    path: PathBuf,
+    checks: BTreeMap<String, Check>,
+    state: Mutex<BTreeMap<String, CheckState>>,
+    /// The checks an alert or recovery message is currently sent for.
+    sending: Mutex<BTreeSet<String>>,
+    pinged: tokio::sync::Notify,
+    retry_delay: Duration,
+    on_failure: Option<FailureHandler>,
+}
+
+impl HeartbeatMonitor {
+    /// The default delay before a message that couldn't be sent is retried by [`run()`](Self::run).
+    pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+    /// Open a state file, creating an empty state if it doesn't exist.
+    pub fn open(client: AppClient, path: impl Into<PathBuf>) -> Result<Self, HeartbeatError> {
+        let path = path.into();
+        let state = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            client,
+            path,
+            checks: BTreeMap::new(),
+            state: Mutex::new(state),
+            sending: Mutex::default(),
+            pinged: tokio::sync::Notify::new(),
+            retry_delay: Self::DEFAULT_RETRY_DELAY,
+            on_failure: None,
+        })
+    }
+    /// Monitor a check. A check without state is expected to ping within its interval from now.
+    pub fn with_check(mut self, check: Check) -> Self {
+        self.state
+            .get_mut()
+            .unwrap()
+            .entry(check.name.clone())
+            .or_insert_with(|| CheckState {
+                first_seen: OffsetDateTime::now_utc(),
+                last_ping: None,
+                down_since: None,
+            });
+        self.checks.insert(check.name.clone(), check);
+        self
+    }
+    /// Retry messages that couldn't be sent by [`run()`](Self::run) after `retry_delay`.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+    /// Call `handler` with the check's name whenever [`run()`](Self::run) couldn't send a message.
+    pub fn with_on_failure(
+        mut self,
+        handler: impl Fn(&str, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_failure = Some(Box::new(handler));
+        self
+    }
+
+    /// Return the state of all monitored checks.
+    pub fn states(&self) -> BTreeMap<String, CheckState> {
+        let state = self.state.lock().unwrap();
+        self.checks
+            .keys()
+            .filter_map(|name| Some((name.clone(), state.get(name)?.clone())))
+            .collect()
+    }
+
+    /// Record a ping and send a recovery message if an alert was sent before.
+    ///
+    /// Returns the recovery message. If it can't be sent, the ping is still
+    /// recorded and the next [`check()`](Self::check) sends the message again.
+    pub async fn ping(&self, name: &str) -> Result<Option<Message>, HeartbeatError> {
+        let check = self
+            .checks
+            .get(name)
+            .ok_or_else(|| HeartbeatError::UnknownCheck(name.to_owned()))?;
+
+        let down_since = {
+            let mut state = self.state.lock().unwrap();
+            let check_state = state.get_mut(name).expect("checks have a state");
+            check_state.last_ping = Some(OffsetDateTime::now_utc());
+            let down_since = check_state.down_since;
+            self.persist(&state)?;
+            down_since
+        };
+        self.pinged.notify_one();
+
+        match down_since {
+            Some(down_since) => self.recover(check, down_since).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Send alerts for overdue checks and recovery messages that [`ping()`](Self::ping) couldn't send.
+    ///
+    /// Messages that can't be sent are reported in [`CheckReport::failed`] and
+    /// sent again by the next check. Only fails if the state file can't be written.
+    pub async fn check(&self) -> Result<CheckReport, HeartbeatError> {
+        let now = OffsetDateTime::now_utc();
+        let (overdue, recovered) = {
+            let state = self.state.lock().unwrap();
+            let mut overdue = Vec::new();
+            let mut recovered = Vec::new();
+            for check in self.checks.values() {
+                let Some(check_state) = state.get(&check.name) else {
+                    continue;
+                };
+                match check_state.down_since {
+                    None if check.deadline(check_state) < now => {
+                        overdue.push((check, check_state.last_ping))
+                    }
+                    Some(down_since)
+                        if check_state.last_ping.is_some_and(|ping| ping > down_since) =>
+                    {
+                        recovered.push((check, down_since))
+                    }
+                    _ => {}
+                }
+            }
+            (overdue, recovered)
+        };
+
+        let mut report = CheckReport::default();
+        for (check, down_since) in recovered {
+            match self.recover(check, down_since).await {
+                Ok(Some(_)) => report.recovered.push(check.name.clone()),
+                Ok(None) => {}
+                Err(HeartbeatError::Request(e)) => report.failed.push((check.name.clone(), e)),
+                Err(e) => return Err(e),
+            }
+        }
+        for (check, previous_ping) in overdue {
+            match self.alert(check, previous_ping, now).await {
+                Ok(Some(_)) => report.alerted.push(check.name.clone()),
+                Ok(None) => {}
+                Err(HeartbeatError::Request(e)) => report.failed.push((check.name.clone(), e)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Check for overdue checks whenever a deadline passes.
+    ///
+    /// Messages that can't be sent are passed to the
+    /// [failure handler](Self::with_on_failure) and retried after the
+    /// [retry delay](Self::with_retry_delay). Only stops if the state file
+    /// can't be written.
+    pub async fn run(&self) -> Result<(), HeartbeatError> {
+        loop {
+            let report = self.check().await?;
+            if let Some(on_failure) = &self.on_failure {
+                for (name, error) in &report.failed {
+                    on_failure(name, error);
+                }
+            }
+
+            let now = OffsetDateTime::now_utc();
+            let next_deadline = {
+                let state = self.state.lock().unwrap();
+                self.checks
+                    .values()
+                    .filter_map(|check| {
+                        let check_state = state.get(&check.name)?;
+                        check_state
+                            .down_since
+                            .is_none()
+                            .then(|| check.deadline(check_state))
+                            // overdue checks whose alert failed are retried below
+                            .filter(|deadline| *deadline >= now)
+                    })
+                    .min()
+            };
+            let mut sleep = match next_deadline {
+                // wake up just after the deadline, so the check is overdue
+                Some(deadline) => {
+                    Duration::try_from(deadline - now).unwrap_or_default()
+                        + Duration::from_millis(10)
+                }
+                // all checks are down, wait for a ping
+                None => Duration::from_secs(3600),
+            };
+            if !report.failed.is_empty() {
+                sleep = sleep.min(self.retry_delay);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = self.pinged.notified() => {}
+            }
+        }
+    }
+
+    /// Answer `GET`, `HEAD` or `POST` requests to `/ping/<name>` until accepting a connection fails.
+    ///
+    /// Every connection is handled in its own task. The name may be
+    /// percent-encoded, e.g. `/ping/nightly%20backup`. Responds with `200 OK`
+    /// for known checks, even if the recovery message couldn't be sent yet,
+    /// and with `404 Not Found` otherwise. Connections whose request line and
+    /// headers exceed 8 KiB are closed without a response.
+    pub async fn serve(
+        self: Arc<Self>,
+        listener: tokio::net::TcpListener,
+    ) -> Result<(), HeartbeatError> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let monitor = self.clone();
+            tokio::spawn(async move {
+                let _ = monitor.handle_connection(stream).await;
+            });
+        }
+    }
+
+    async fn handle_connection(&self, stream: tokio::net::TcpStream) -> std::io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        // a misbehaving client must not keep the connection open for long
+        let request_line = match tokio::time::timeout(
+            Duration::from_secs(5),
+            read_request_line(&mut stream),
+        )
+        .await
+        {
+            Ok(request_line) => request_line?,
+            Err(_) => return Ok(()),
+        };
+
+        let mut parts = request_line.split_whitespace();
+        let (method, target) = (
+            parts.next().unwrap_or_default(),
+            parts.next().unwrap_or_default(),
+        );
+        let name = target
+            .split('?')
+            .next()
+            .and_then(|path| path.strip_prefix("/ping/"))
+            .filter(|_| matches!(method, "GET" | "POST" | "HEAD"))
+            .and_then(|name| percent_decode_str(name).decode_utf8().ok());
+
+        let status = match name {
+            Some(name) => match self.ping(&name).await {
+                // the ping was recorded and the recovery message is retried
+                Ok(_) | Err(HeartbeatError::Request(_)) => "200 OK",
+                Err(HeartbeatError::UnknownCheck(_)) => "404 Not Found",
+                Err(_) => "500 Internal Server Error",
+            },
+            None => "404 Not Found",
+        };
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\ncontent-type: text/plain\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+            status.len() + 1
+        );
+        if method != "HEAD" {
+            response.push_str(status);
+            response.push('\n');
+        }
+        stream.get_mut().write_all(response.as_bytes()).await?;
+        stream.get_mut().shutdown().await
+    }
+
+    /// Send an alert and mark the check as down.
+    ///
+    /// A ping that arrives while the alert is sent is later than `now`, so the
+    /// next [`check()`](Self::check) sends the recovery message.
+    async fn alert(
+        &self,
+        check: &Check,
+        previous_ping: Option<OffsetDateTime>,
+        now: OffsetDateTime,
+    ) -> Result<Option<Message>, HeartbeatError> {
+        let Some(_sending) = SendingGuard::acquire(&self.sending, &check.name) else {
+            return Ok(None);
+        };
+
+        let last_ping = match previous_ping {
+            Some(last_ping) => format!(
+                "The last ping was {} ago.",
+                format_duration((now - last_ping).try_into().unwrap_or_default())
+            ),
+            None => "It never pinged.".to_owned(),
+        };
+        let text = format!(
+            "{} didn't check in within {} plus a grace period of {}. {last_ping}",
+            check.name,
+            format_duration(check.interval),
+            format_duration(check.grace_period),
+        );
+        let message = self
+            .send(
+                check,
+                "down",
+                format!("Heartbeat missed: {}", check.name),
+                text,
+            )
+            .await?;
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(check_state) = state.get_mut(&check.name) {
+            check_state.down_since = Some(now);
+        }
+        self.persist(&state)?;
+        Ok(Some(message))
+    }
+
+    /// Send a recovery message and mark the check as up.
+    async fn recover(
+        &self,
+        check: &Check,
+        down_since: OffsetDateTime,
+    ) -> Result<Option<Message>, HeartbeatError> {
+        let Some(_sending) = SendingGuard::acquire(&self.sending, &check.name) else {
+            return Ok(None);
+        };
+
+        let text = format!(
+            "{} checked in again after being down for {}.",
+            check.name,
+            format_duration(
+                (OffsetDateTime::now_utc() - down_since)
+                    .try_into()
+                    .unwrap_or_default()
+            )
+        );
+        let message = self
+            .send(
+                check,
+                "up",
+                format!("Heartbeat recovered: {}", check.name),
+                text,
+            )
+            .await?;
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(check_state) = state.get_mut(&check.name) {
+            if check_state.down_since == Some(down_since) {
+                check_state.down_since = None;
+            }
+        }
+        self.persist(&state)?;
+        Ok(Some(message))
+    }
+
+    async fn send(
+        &self,
+        check: &Check,
+        status: &str,
+        title: String,
+        text: String,
+    ) -> Result<Message, Error> {
+        let priority = match status {
+            "down" => check.priority,
+            _ => check.recovery_priority,
+        };
+        self.client
+            .create_message(text)
+            .with_title(title)
+            .with_priority(priority)
+            .with_extras(std::collections::HashMap::from([(
+                HEARTBEAT_EXTRAS_KEY.into(),
+                serde_json::json!({ "check": check.name, "status": status }),
+            )]))
+            .await
+    }
+
+    /// Write the state to its file atomically.
+    fn persist(&self, state: &BTreeMap<String, CheckState>) -> Result<(), HeartbeatError> {
+        write_atomically(&self.path, &serde_json::to_vec_pretty(state)?)?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for HeartbeatMonitor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HeartbeatMonitor")
+            .field("client", &self.client)
+            .field("path", &self.path)
+            .field("checks", &self.checks)
+            .field("state", &self.state)
+            .field("retry_delay", &self.retry_delay)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Marks a check as being sent for until it is dropped.
+struct SendingGuard<'a> {
+    sending: &'a Mutex<BTreeSet<String>>,
+    name: &'a str,
+}
+
+impl<'a> SendingGuard<'a> {
+    /// Return `None` if a message is already sent for the check.
+    fn acquire(sending: &'a Mutex<BTreeSet<String>>, name: &'a str) -> Option<Self> {
+        sending
+            .lock()
+            .unwrap()
+            .insert(name.to_owned())
+            .then_some(Self { sending, name })
+    }
+}
+
+impl Drop for SendingGuard<'_> {
+    fn drop(&mut self) {
+        self.sending.lock().unwrap().remove(self.name);
+    }
+}
+
+/// The maximum size of the request line and headers of a ping.
+const MAX_REQUEST_HEAD_SIZE: u64 = 8 * 1024;
+
+/// Read the request line and skip the headers, the body is ignored.
+///
+/// Fails if the request line and headers exceed [`MAX_REQUEST_HEAD_SIZE`].
+async fn read_request_line(
+    stream: &mut BufReader<tokio::net::TcpStream>,
+) -> std::io::Result<String> {
+    let mut head = stream.take(MAX_REQUEST_HEAD_SIZE);
+    let mut request_line = String::new();
+    head.read_line(&mut request_line).await?;
+    loop {
+        let mut header = String::new();
+        if head.read_line(&mut header).await? == 0 {
+            if head.limit() == 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "the request headers are too large",
+                ));
+            }
+            return Ok(request_line);
+        }
+        if header.trim().is_empty() {
+            return Ok(request_line);
+        }
+    }
+}
+
+/// Format a duration like `1d 2h 30m`, omitting seconds for durations of an hour or more.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let parts = [
+        (secs / 86400, "d"),
+        (secs / 3600 % 24, "h"),
+        (secs / 60 % 60, "m"),
+        (if secs < 3600 { secs % 60 } else { 0 }, "s"),
+    ];
+    let formatted = parts
+        .iter()
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{value}{unit}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if formatted.is_empty() {
+        "0s".to_owned()
+    } else {
+        formatted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use time::OffsetDateTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{testsuite::*, AppClient};
+
+    use super::{format_duration, Check, HeartbeatMonitor};
+
+    #[test]
+    fn durations() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_secs(90)), "1m 30s");
+        assert_eq!(format_duration(Duration::from_secs(93_784)), "1d 2h 3m");
+    }
+
+    #[tokio::test]
+    async fn http_ping() -> eyre::Result<()> {
+        let path = temp_path("heartbeat-http.json");
+        let client = AppClient::new("http://localhost:30081", GOTIFY_APP_TOKEN)?;
+        let monitor = Arc::new(
+            HeartbeatMonitor::open(client.clone(), &path)?
+                .with_check(Check::new("backup", Duration::from_secs(3600)))
+                .with_check(Check::new("nightly backup", Duration::from_secs(3600))),
+        );
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+
+        let request = |method: &'static str, target: &'static str, headers: String| async move {
+            let mut stream = tokio::net::TcpStream::connect(address).await?;
+            stream
+                .write_all(
+                    format!("{method} {target} HTTP/1.1\r\nhost: localhost\r\n{headers}\r\n")
+                        .as_bytes(),
+                )
+                .await?;
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await?;
+            std::io::Result::Ok(response)
+        };
+        let requests = async {
+            // an idle connection doesn't block other requests
+            let _idle = tokio::net::TcpStream::connect(address).await?;
+            eyre::Ok([
+                request("POST", "/ping/backup", String::new()).await?,
+                request("POST", "/ping/unknown", String::new()).await?,
+                request("HEAD", "/ping/nightly%20backup", String::new()).await?,
+                request(
+                    "GET",
+                    "/ping/backup",
+                    format!("x-padding: {}\r\n", "a".repeat(10_000)),
+                )
+                .await
+                // the connection is closed without a response, possibly reset
+                .unwrap_or_default(),
+            ])
+        };
+
+        let [ok, not_found, head, too_large] = tokio::select! {
+            result = requests => result?,
+            result = monitor.clone().serve(listener) => panic!("the server stopped: {result:?}"),
+        };
+        assert!(ok.starts_with("HTTP/1.1 200 OK"));
+        assert!(ok.ends_with("\r\n\r\n200 OK\n"));
+        assert!(not_found.starts_with("HTTP/1.1 404 Not Found"));
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.ends_with("\r\n\r\n"));
+        assert!(too_large.is_empty());
+
+        let reopened = HeartbeatMonitor::open(client, &path)?
+            .with_check(Check::new("backup", Duration::from_secs(3600)))
+            .with_check(Check::new("nightly backup", Duration::from_secs(3600)));
+        assert!(reopened.states()["backup"].last_ping.is_some());
+        assert!(reopened.states()["nightly backup"].last_ping.is_some());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_messages_are_retried() -> eyre::Result<()> {
+        let path = temp_path("heartbeat-retry.json");
+        let client = AppClient::new("http://localhost:30081", GOTIFY_APP_TOKEN)?;
+        let monitor = HeartbeatMonitor::open(client, &path)?
+            .with_check(Check::new("backup", Duration::ZERO))
+            .with_check(Check::new("cleanup", Duration::from_secs(3600)));
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let report = monitor.check().await?;
+        assert!(report.alerted.is_empty());
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, "backup");
+        assert!(monitor.states()["backup"].down_since.is_none());
+
+        let down_since = OffsetDateTime::now_utc();
+        monitor
+            .state
+            .lock()
+            .unwrap()
+            .get_mut("cleanup")
+            .unwrap()
+            .down_since = Some(down_since);
+        assert!(monitor.ping("cleanup").await.is_err());
+        let state = &monitor.states()["cleanup"];
+        assert!(state.last_ping.is_some());
+        assert_eq!(state.down_since, Some(down_since));
+
+        let report = monitor.check().await?;
+        assert!(report.failed.iter().any(|(name, _)| name == "cleanup"));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ping_during_alert_is_recovered() -> eyre::Result<()> {
+        let message =
+            r#"{"id":1,"appid":1,"message":"","priority":0,"date":"2023-08-01T12:00:00Z"}"#;
+        let (url, requests) = http_stand_in(vec![(200, message), (200, message)]).await;
+        let path = temp_path("heartbeat-race.json");
+        let monitor =
+            HeartbeatMonitor::open(AppClient::new(url.as_str(), GOTIFY_APP_TOKEN)?, &path)?
+                .with_check(Check::new("backup", Duration::from_secs(60)));
+
+        // the check is overdue but was pinged while the alert was sent
+        let now = OffsetDateTime::now_utc();
+        monitor
+            .state
+            .lock()
+            .unwrap()
+            .get_mut("backup")
+            .unwrap()
+            .last_ping = Some(now + time::Duration::seconds(1));
+        assert!(monitor
+            .alert(&monitor.checks["backup"], None, now)
+            .await?
+            .is_some());
+        assert_eq!(monitor.states()["backup"].down_since, Some(now));
+
+        assert_eq!(monitor.check().await?.recovered, ["backup"]);
+        assert!(monitor.states()["backup"].down_since.is_none());
+        assert_eq!(requests.lock().unwrap().len(), 2);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn alert_and_recovery() -> eyre::Result<()> {
+        let path = temp_path("heartbeat-alert.json");
+        let monitor = HeartbeatMonitor::open((*app_client()).clone(), &path)?
+            .with_check(Check::new("backup", Duration::from_millis(50)).with_priority(9));
+
+        assert!(monitor.check().await?.alerted.is_empty());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(monitor.check().await?.alerted, ["backup"]);
+        assert!(monitor.check().await?.alerted.is_empty());
+        assert!(monitor.states()["backup"].down_since.is_some());
+
+        let recovery = monitor.ping("backup").await?.expect("a recovery message");
+        assert_eq!(
+            recovery.title.as_deref(),
+            Some("Heartbeat recovered: backup")
+        );
+        assert_eq!(recovery.priority, 4);
+        assert!(monitor.ping("backup").await?.is_none());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
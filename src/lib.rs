@@ -11,11 +11,33 @@
 //! | Feature flag | Enabled methods | Note |
 //! | ------------ | --------------- | ---- |
 //! | `app` | [`Client::create_message()`](crate::Client::create_message) | |
-//! | `manage-applications` | [`Client::get_applications()`](crate::Client::get_applications), [`Client::create_application()`](crate::Client::create_application), [`Client::update_application()`](crate::Client::update_application), [`Client::delete_application()`](crate::Client::delete_application), [`Client::delete_application_image()`](crate::Client::delete_application_image) | |
+//! | `audit` | [`Client::audit()`](crate::Client::audit), [`Client::prune()`](crate::Client::prune) | see the [`audit`](crate::audit) module |
+//! | `backup` | [`Client::backup()`](crate::Client::backup), [`Client::restore()`](crate::Client::restore) | see the [`backup`](crate::backup) module |
+//! | `bulk` | [`Client::bulk()`](crate::Client::bulk), [`Client::delete_messages_by_id()`](crate::Client::delete_messages_by_id), [`Client::create_applications()`](crate::Client::create_applications), [`Client::delete_applications()`](crate::Client::delete_applications), [`Client::delete_clients()`](crate::Client::delete_clients) | the shorthands also require the corresponding `manage-*` feature |
+//! | `digest` | [`DigestAppClient`](crate::DigestAppClient) | combines bursts of messages into markdown digests |
+//! | `export` | [`Client::export_messages()`](crate::Client::export_messages) | see the [`export`](crate::export) module |
+//! | `forward` | [`Forwarder`](crate::forward::Forwarder) | see the [`forward`](crate::forward) module |
+//! | `forward-email` | [`EmailSink`](crate::forward::EmailSink) | sends forwarded messages via SMTP using [`lettre`](https://docs.rs/lettre) |
+//! | `heartbeat` | [`HeartbeatMonitor`](crate::heartbeat::HeartbeatMonitor) | see the [`heartbeat`](crate::heartbeat) module |
+//! | `image-cache` | [`ApplicationImageBuilder::with_cache_dir()`](crate::builder::ApplicationImageBuilder::with_cache_dir) | caches images downloaded by [`Client::get_application_image()`](crate::Client::get_application_image) on disk |
+//! | `manage-applications` | [`Client::get_applications()`](crate::Client::get_applications), [`Client::create_application()`](crate::Client::create_application), [`Client::update_application()`](crate::Client::update_application), [`Client::delete_application()`](crate::Client::delete_application), [`Client::upload_application_image()`](crate::Client::upload_application_image), [`Client::get_application_image()`](crate::Client::get_application_image), [`Client::delete_application_image()`](crate::Client::delete_application_image), [`Client::ensure_application()`](crate::Client::ensure_application) | `ensure_application()` also requires `app` |
 //! | `manage-clients` | [`Client::get_clients()`](crate::Client::get_clients), [`Client::create_client()`](crate::Client::create_client), [`Client::update_client()`](crate::Client::update_client), [`Client::delete_client()`](crate::Client::delete_client) | |
 //! | `manage-messages` | [`Client::get_application_messages()`](crate::Client::get_application_messages), [`Client::delete_application_messages()`](crate::Client::delete_application_messages), [`Client::get_messages()`](crate::Client::get_messages), [`Client::delete_messages()`](crate::Client::delete_messages), [`Client::delete_message()`](crate::Client::delete_message) | doesn't include [`Client::create_message()`](crate::Client::create_message) and [`Client::stream_messages()`](crate::Client::stream_messages) |
-//! | `manage-plugins` | [`Client::get_plugins()`](crate::Client::get_plugins), [`Client::get_plugin_config()`](crate::Client::get_plugin_config), [`Client::update_plugin_config()`](crate::Client::update_plugin_config), [`Client::disable_plugin()`](crate::Client::disable_plugin), [`Client::get_plugin_display()`](crate::Client::get_plugin_display), [`Client::enable_plugin()`](crate::Client::enable_plugin) | |
+//! | `manage-plugins` | [`Client::get_plugins()`](crate::Client::get_plugins), [`Client::find_plugin_by_module_path()`](crate::Client::find_plugin_by_module_path), [`Client::ensure_plugin_enabled()`](crate::Client::ensure_plugin_enabled), [`Client::get_plugin_config()`](crate::Client::get_plugin_config), [`Client::get_plugin_config_as()`](crate::Client::get_plugin_config_as), [`Client::update_plugin_config()`](crate::Client::update_plugin_config), [`Client::update_plugin_config_from()`](crate::Client::update_plugin_config_from), [`Client::disable_plugin()`](crate::Client::disable_plugin), [`Client::get_plugin_display()`](crate::Client::get_plugin_display), [`Client::enable_plugin()`](crate::Client::enable_plugin), [`Client::plugin_handle()`](crate::Client::plugin_handle) | |
 //! | `manage-users` | [`Client::get_current_user()`](crate::Client::get_current_user), [`Client::update_current_user()`](crate::Client::update_current_user), [`Client::get_users()`](crate::Client::get_users), [`Client::get_user()`](crate::Client::get_user), [`Client::update_user()`](crate::Client::update_user), [`Client::delete_user()`](crate::Client::delete_user) | |
+//! | `log` | [`GotifyLogger`](crate::GotifyLogger) | forwards `log` records to Gotify |
+//! | `mirror` | [`Mirror`](crate::mirror::Mirror) | see the [`mirror`](crate::mirror) module, bundles SQLite via [`rusqlite`](https://docs.rs/rusqlite) |
+//! | `multi` | [`MultiAppClient`](crate::MultiAppClient) | sends messages to multiple servers with failover or broadcast |
+//! | `outbox` | [`Outbox`](crate::Outbox) | queues messages in a file while the server is unavailable and delivers them in order |
+//! | `provision` | [`Client::plan_provisioning()`](crate::Client::plan_provisioning), [`Client::apply_provisioning()`](crate::Client::apply_provisioning) | see the [`provision`](crate::provision) module |
+//! | `queue` | [`Client::consume()`](crate::Client::consume) | see the [`queue`](crate::queue) module |
+//! | `replicate` | [`Replicator`](crate::Replicator) | replicates messages from one server to another |
+//! | `report` | [`install_panic_hook()`](crate::install_panic_hook), [`PanicHook`](crate::PanicHook), [`Client::create_error_report()`](crate::Client::create_error_report) | |
+//! | `retention` | [`Client::enforce_retention()`](crate::Client::enforce_retention), [`Client::spawn_retention()`](crate::Client::spawn_retention) | see the [`retention`](crate::retention) module |
+//! | `rules` | [`RuleEngine`](crate::rules::RuleEngine) | see the [`rules`](crate::rules) module |
+//! | `schedule` | [`Scheduler`](crate::schedule::Scheduler) | see the [`schedule`](crate::schedule) module |
+//! | `throttle` | [`ThrottledAppClient`](crate::ThrottledAppClient) | suppresses duplicate messages, limits the sending rate and summarizes suppressed messages |
+//! | `tracing` | [`GotifyLayer`](crate::GotifyLayer) | forwards `tracing` events to Gotify |
 //! | `websocket` | [`Client::stream_messages()`](crate::Client::stream_messages) | enables additional dependencies (mainly [`tokio-tungstenite`](https://docs.rs/tokio-tungstenite)) |
 //!
 //! </details>
@@ -59,20 +81,88 @@
 
 use std::marker::PhantomData;
 
-use reqwest::{
-    header::{HeaderMap, HeaderValue, InvalidHeaderValue},
-    Method,
-};
+#[cfg(any(feature = "app", feature = "client-core"))]
+use reqwest::header::InvalidHeaderValue;
+use reqwest::{header::HeaderValue, Method};
 use url::Url;
 
 use crate::utils::UrlAppend;
 
+#[cfg(feature = "bulk")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bulk")))]
+pub use crate::bulk::{BulkProgress, BulkReport};
+#[cfg(feature = "digest")]
+#[cfg_attr(docsrs, doc(cfg(feature = "digest")))]
+pub use crate::digest::{DigestAppClient, DigestOutcome};
 pub use crate::error::{Error, InitError, Result};
+#[cfg(feature = "tracing")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tracing")))]
+pub use crate::logging::GotifyLayer;
+#[cfg(feature = "log")]
+#[cfg_attr(docsrs, doc(cfg(feature = "log")))]
+pub use crate::logging::GotifyLogger;
+#[cfg(any(feature = "log", feature = "tracing"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "log", feature = "tracing"))))]
+pub use crate::logging::{ForwardConfig, ForwardLevel};
+#[cfg(feature = "multi")]
+#[cfg_attr(docsrs, doc(cfg(feature = "multi")))]
+pub use crate::multi::{
+    CircuitState, Delivery, DeliveryError, DeliveryMode, MultiAppClient, TargetHealth,
+    TargetOutcome, TargetResult,
+};
+#[cfg(feature = "outbox")]
+#[cfg_attr(docsrs, doc(cfg(feature = "outbox")))]
+pub use crate::outbox::{Outbox, OutboxError, OutboxFailure, OutboxItem, OutboxOutcome};
+#[cfg(feature = "manage-plugins")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
+pub use crate::plugins::{PluginConfigError, PluginHandle};
+#[cfg(feature = "replicate")]
+#[cfg_attr(docsrs, doc(cfg(feature = "replicate")))]
+pub use crate::replicate::{ReplicationError, ReplicationState, Replicator};
+#[cfg(feature = "report")]
+#[cfg_attr(docsrs, doc(cfg(feature = "report")))]
+pub use crate::report::{install_panic_hook, PanicHook};
+#[cfg(feature = "throttle")]
+#[cfg_attr(docsrs, doc(cfg(feature = "throttle")))]
+pub use crate::throttle::{SendOutcome, SuppressionReason, ThrottledAppClient};
 #[cfg(feature = "websocket")]
 #[cfg_attr(docsrs, doc(cfg(feature = "websocket")))]
 pub use crate::websocket::{WebsocketConnectError, WebsocketError};
 
+#[cfg(feature = "audit")]
+#[cfg_attr(docsrs, doc(cfg(feature = "audit")))]
+pub mod audit;
+#[cfg(feature = "backup")]
+#[cfg_attr(docsrs, doc(cfg(feature = "backup")))]
+pub mod backup;
+#[cfg(feature = "export")]
+#[cfg_attr(docsrs, doc(cfg(feature = "export")))]
+pub mod export;
+#[cfg(feature = "forward")]
+#[cfg_attr(docsrs, doc(cfg(feature = "forward")))]
+pub mod forward;
+#[cfg(feature = "heartbeat")]
+#[cfg_attr(docsrs, doc(cfg(feature = "heartbeat")))]
+pub mod heartbeat;
+#[cfg(feature = "mirror")]
+#[cfg_attr(docsrs, doc(cfg(feature = "mirror")))]
+pub mod mirror;
 pub mod models;
+#[cfg(feature = "provision")]
+#[cfg_attr(docsrs, doc(cfg(feature = "provision")))]
+pub mod provision;
+#[cfg(feature = "queue")]
+#[cfg_attr(docsrs, doc(cfg(feature = "queue")))]
+pub mod queue;
+#[cfg(feature = "retention")]
+#[cfg_attr(docsrs, doc(cfg(feature = "retention")))]
+pub mod retention;
+#[cfg(feature = "rules")]
+#[cfg_attr(docsrs, doc(cfg(feature = "rules")))]
+pub mod rules;
+#[cfg(feature = "schedule")]
+#[cfg_attr(docsrs, doc(cfg(feature = "schedule")))]
+pub mod schedule;
 
 /// Builder structs used by some methods that send data to Gotify's API.
 ///
@@ -82,15 +172,41 @@ pub mod builder {
     #[cfg(feature = "app")]
     #[cfg_attr(docsrs, doc(cfg(feature = "app")))]
     pub use crate::app::MessageBuilder;
+    #[cfg(all(feature = "app", feature = "manage-applications"))]
+    #[cfg_attr(
+        docsrs,
+        doc(cfg(all(feature = "app", feature = "manage-applications")))
+    )]
+    pub use crate::applications::EnsureApplicationBuilder;
     #[cfg(feature = "manage-applications")]
     #[cfg_attr(docsrs, doc(cfg(feature = "manage-applications")))]
-    pub use crate::applications::{ApplicationBuilder, ApplicationUpdateBuilder};
+    pub use crate::applications::{
+        ApplicationBuilder, ApplicationImageBuilder, ApplicationUpdateBuilder,
+    };
+    #[cfg(feature = "bulk")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "bulk")))]
+    pub use crate::bulk::BulkBuilder;
     #[cfg(feature = "manage-clients")]
     #[cfg_attr(docsrs, doc(cfg(feature = "manage-clients")))]
     pub use crate::clients::{ClientBuilder, ClientUpdateBuilder};
+    #[cfg(feature = "digest")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "digest")))]
+    pub use crate::digest::DigestMessageBuilder;
     #[cfg(feature = "manage-messages")]
     #[cfg_attr(docsrs, doc(cfg(feature = "manage-messages")))]
     pub use crate::messages::{GetApplicationMessagesBuilder, GetMessagesBuilder};
+    #[cfg(feature = "multi")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "multi")))]
+    pub use crate::multi::MultiMessageBuilder;
+    #[cfg(feature = "outbox")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "outbox")))]
+    pub use crate::outbox::OutboxMessageBuilder;
+    #[cfg(feature = "manage-plugins")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
+    pub use crate::plugins::PluginRequestBuilder;
+    #[cfg(feature = "throttle")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "throttle")))]
+    pub use crate::throttle::ThrottledMessageBuilder;
     #[cfg(feature = "manage-users")]
     #[cfg_attr(docsrs, doc(cfg(feature = "manage-users")))]
     pub use crate::users::{CreateUserBuilder, UpdateCurrentUserBuilder, UpdateUserBuilder};
@@ -102,17 +218,41 @@ mod app;
 #[cfg(feature = "manage-applications")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-applications")))]
 mod applications;
+#[cfg(feature = "bulk")]
+#[cfg_attr(docsrs, doc(cfg(feature = "bulk")))]
+mod bulk;
 #[cfg(feature = "manage-clients")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-clients")))]
 mod clients;
+#[cfg(feature = "digest")]
+#[cfg_attr(docsrs, doc(cfg(feature = "digest")))]
+mod digest;
 mod error;
 mod health;
+#[cfg(any(feature = "log", feature = "tracing"))]
+#[cfg_attr(docsrs, doc(cfg(any(feature = "log", feature = "tracing"))))]
+mod logging;
 #[cfg(feature = "manage-messages")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-messages")))]
 mod messages;
+#[cfg(feature = "multi")]
+#[cfg_attr(docsrs, doc(cfg(feature = "multi")))]
+mod multi;
+#[cfg(feature = "outbox")]
+#[cfg_attr(docsrs, doc(cfg(feature = "outbox")))]
+mod outbox;
 #[cfg(feature = "manage-plugins")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
 mod plugins;
+#[cfg(feature = "replicate")]
+#[cfg_attr(docsrs, doc(cfg(feature = "replicate")))]
+mod replicate;
+#[cfg(feature = "report")]
+#[cfg_attr(docsrs, doc(cfg(feature = "report")))]
+mod report;
+#[cfg(feature = "throttle")]
+#[cfg_attr(docsrs, doc(cfg(feature = "throttle")))]
+mod throttle;
 #[cfg(feature = "manage-users")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-users")))]
 mod users;
@@ -138,6 +278,7 @@ mod utils;
 pub struct Client<T> {
     base_url: Url,
     http: reqwest::Client,
+    access_token: Option<HeaderValue>,
     token: PhantomData<T>,
 }
 
@@ -207,18 +348,40 @@ impl<T: TokenType> Client<T> {
         Ok(Client {
             base_url: server_url.try_into()?,
             http: reqwest::Client::builder()
-                .default_headers({
-                    let mut headers = HeaderMap::new();
-                    headers.insert("X-Gotify-Key", access_token.try_into()?);
-                    headers
-                })
                 .build()
                 .map_err(InitError::Reqwest)?,
+            access_token: Some(sensitive_header_value(access_token)?),
             token: PhantomData,
         })
     }
 }
 
+impl<T> Client<T> {
+    /// Create a client for the same server with a different access token,
+    /// sharing the underlying HTTP client.
+    #[cfg(any(feature = "app", feature = "client-core"))]
+    pub(crate) fn with_access_token<U: TokenType>(
+        &self,
+        access_token: impl TryInto<HeaderValue, Error = InvalidHeaderValue>,
+    ) -> core::result::Result<Client<U>, InitError> {
+        Ok(Client {
+            base_url: self.base_url.clone(),
+            http: self.http.clone(),
+            access_token: Some(sensitive_header_value(access_token)?),
+            token: PhantomData,
+        })
+    }
+}
+
+#[cfg(any(feature = "app", feature = "client-core"))]
+fn sensitive_header_value(
+    value: impl TryInto<HeaderValue, Error = InvalidHeaderValue>,
+) -> core::result::Result<HeaderValue, InvalidHeaderValue> {
+    let mut value = value.try_into()?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
 impl Client<Unauthenticated> {
     /// Create a new unauthenticated client.
     ///
@@ -228,7 +391,10 @@ impl Client<Unauthenticated> {
     ) -> core::result::Result<Self, InitError> {
         Ok(Client {
             base_url: server_url.try_into()?,
-            http: reqwest::Client::new(),
+            http: reqwest::Client::builder()
+                .build()
+                .map_err(InitError::Reqwest)?,
+            access_token: None,
             token: PhantomData,
         })
     }
@@ -237,28 +403,19 @@ impl Client<Unauthenticated> {
     ///
     /// The type of the used access token (app token or client token)
     /// must be provided as a generic parameter or be inferable.
+    #[cfg(any(feature = "app", feature = "client-core"))]
+    #[cfg_attr(docsrs, doc(cfg(any(feature = "app", feature = "client-core"))))]
     pub fn authenticate<T: TokenType>(
         self,
         access_token: impl TryInto<HeaderValue, Error = InvalidHeaderValue>,
     ) -> core::result::Result<Client<T>, InitError> {
-        Ok(Client {
-            base_url: self.base_url,
-            http: reqwest::Client::builder()
-                .default_headers({
-                    let mut headers = HeaderMap::new();
-                    headers.insert("X-Gotify-Key", access_token.try_into()?);
-                    headers
-                })
-                .build()
-                .map_err(InitError::Reqwest)?,
-            token: PhantomData,
-        })
+        self.with_access_token(access_token)
     }
 }
 
 pub(crate) struct RequestBuilder(reqwest::RequestBuilder);
 impl RequestBuilder {
-    #[cfg(any(feature = "app", feature = "client-core"))]
+    #[cfg(feature = "client-core")]
     pub fn with_query(self, params: impl serde::Serialize) -> Self {
         Self(self.0.query(&params))
     }
@@ -267,10 +424,22 @@ impl RequestBuilder {
         Self(self.0.json(&body))
     }
     #[cfg(feature = "manage-plugins")]
-    pub fn with_string_body(self, body: String) -> Self {
+    pub fn with_header(self, name: &str, value: &str) -> Self {
+        Self(self.0.header(name, value))
+    }
+    #[cfg(feature = "manage-plugins")]
+    pub fn with_body(self, body: impl Into<reqwest::Body>) -> Self {
         Self(self.0.body(body))
     }
-    #[cfg(feature = "manage-messages")]
+    #[cfg(feature = "manage-plugins")]
+    pub fn with_yaml_body(self, body: String) -> Self {
+        Self(
+            self.0
+                .header(reqwest::header::CONTENT_TYPE, "application/x-yaml")
+                .body(body),
+        )
+    }
+    #[cfg(feature = "manage-applications")]
     pub fn with_file(
         self,
         file_name: impl Into<std::borrow::Cow<'static, str>>,
@@ -307,6 +476,40 @@ impl RequestBuilder {
             Err(Error::Response(r.json().await?))
         }
     }
+    #[cfg(feature = "manage-applications")]
+    pub async fn send_and_read_bytes(self) -> Result<(Option<String>, Vec<u8>)> {
+        let r = self.0.send().await?;
+
+        if r.status().is_success() {
+            let content_type = r
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .map(ToOwned::to_owned);
+            Ok((content_type, r.bytes().await?.to_vec()))
+        } else {
+            Err(Error::Response(r.json().await?))
+        }
+    }
+    #[cfg(feature = "audit")]
+    pub async fn send_and_read_status(self) -> Result<reqwest::StatusCode> {
+        Ok(self.0.send().await?.status())
+    }
+    /// Send a request to a custom plugin route, which may respond with any error format.
+    #[cfg(feature = "manage-plugins")]
+    pub async fn send_to_plugin(self) -> Result<reqwest::Response> {
+        let r = self.0.send().await?;
+
+        if r.status().is_success() {
+            return Ok(r);
+        }
+        let status = r.status();
+        let body = r.text().await?;
+        Err(match serde_json::from_str(&body) {
+            Ok(e) => Error::Response(e),
+            Err(_) => Error::PluginResponse { status, body },
+        })
+    }
     #[cfg(feature = "manage-plugins")]
     pub async fn send_and_read_string(self) -> Result<String> {
         let r = self.0.send().await?;
@@ -325,11 +528,16 @@ impl<T> Client<T> {
         method: Method,
         uri: impl IntoIterator<Item = impl AsRef<str>>,
     ) -> RequestBuilder {
-        RequestBuilder(self.http.request(method, self.base_url.append(uri)))
+        let request = self.http.request(method, self.base_url.append(uri));
+
+        RequestBuilder(match &self.access_token {
+            Some(access_token) => request.header("X-Gotify-Key", access_token.clone()),
+            None => request,
+        })
     }
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "app", feature = "manage-messages"))]
 mod tests {
     use crate::testsuite::*;
 
@@ -0,0 +1,622 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt::Write as _,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{utils::markdown_extras, AppClient};
+
+/// The severity of a forwarded event.
+///
+/// Independent from the `tracing` and `log` level types so that the same
+/// [`ForwardConfig`] can be used for both backends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ForwardLevel {
+    #[allow(missing_docs)]
+    Error,
+    #[allow(missing_docs)]
+    Warn,
+    #[allow(missing_docs)]
+    Info,
+    #[allow(missing_docs)]
+    Debug,
+    #[allow(missing_docs)]
+    Trace,
+}
+
+impl ForwardLevel {
+    fn as_str(self) -> &'static str {
+        match self {
+            ForwardLevel::Error => "ERROR",
+            ForwardLevel::Warn => "WARN",
+            ForwardLevel::Info => "INFO",
+            ForwardLevel::Debug => "DEBUG",
+            ForwardLevel::Trace => "TRACE",
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+impl From<tracing_core::Level> for ForwardLevel {
+    fn from(level: tracing_core::Level) -> Self {
+        match level {
+            tracing_core::Level::ERROR => ForwardLevel::Error,
+            tracing_core::Level::WARN => ForwardLevel::Warn,
+            tracing_core::Level::INFO => ForwardLevel::Info,
+            tracing_core::Level::DEBUG => ForwardLevel::Debug,
+            tracing_core::Level::TRACE => ForwardLevel::Trace,
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+impl From<log::Level> for ForwardLevel {
+    fn from(level: log::Level) -> Self {
+        match level {
+            log::Level::Error => ForwardLevel::Error,
+            log::Level::Warn => ForwardLevel::Warn,
+            log::Level::Info => ForwardLevel::Info,
+            log::Level::Debug => ForwardLevel::Debug,
+            log::Level::Trace => ForwardLevel::Trace,
+        }
+    }
+}
+
+/// Configures which events are forwarded to Gotify and how.
+///
+/// By default, `ERROR` and `WARN` events from all targets are forwarded
+/// with priorities 8 and 5, at most 10 messages per minute.
+#[derive(Clone, Debug)]
+pub struct ForwardConfig {
+    max_level: ForwardLevel,
+    priorities: HashMap<ForwardLevel, u8>,
+    included_targets: Vec<String>,
+    excluded_targets: Vec<String>,
+    rate_limit: Option<(u32, Duration)>,
+    queue_capacity: usize,
+    flush_timeout: Duration,
+}
+
+impl Default for ForwardConfig {
+    fn default() -> Self {
+        Self {
+            max_level: ForwardLevel::Warn,
+            priorities: HashMap::from([
+                (ForwardLevel::Error, 8),
+                (ForwardLevel::Warn, 5),
+                (ForwardLevel::Info, 3),
+                (ForwardLevel::Debug, 1),
+                (ForwardLevel::Trace, 0),
+            ]),
+            included_targets: Vec::new(),
+            excluded_targets: Vec::new(),
+            rate_limit: Some((10, Duration::from_secs(60))),
+            queue_capacity: 128,
+            flush_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl ForwardConfig {
+    /// Create the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Forward events up to and including this level.
+    pub fn with_max_level(mut self, level: impl Into<ForwardLevel>) -> Self {
+        self.max_level = level.into();
+        self
+    }
+    /// Set the message priority used for events of a level.
+    pub fn with_priority(mut self, level: impl Into<ForwardLevel>, priority: u8) -> Self {
+        self.priorities.insert(level.into(), priority);
+        self
+    }
+    /// Only forward events whose target starts with this prefix.
+    ///
+    /// Can be called multiple times to allow multiple prefixes.
+    pub fn with_target(mut self, prefix: impl Into<String>) -> Self {
+        self.included_targets.push(prefix.into());
+        self
+    }
+    /// Never forward events whose target starts with this prefix.
+    pub fn without_target(mut self, prefix: impl Into<String>) -> Self {
+        self.excluded_targets.push(prefix.into());
+        self
+    }
+    /// Forward at most `max_messages` per `period`. Excess events are dropped.
+    pub fn with_rate_limit(mut self, max_messages: u32, period: Duration) -> Self {
+        self.rate_limit = Some((max_messages, period));
+        self
+    }
+    /// Disable rate limiting.
+    pub fn without_rate_limit(mut self) -> Self {
+        self.rate_limit = None;
+        self
+    }
+    /// Set how many messages may wait to be sent before new events are dropped.
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity;
+        self
+    }
+    /// Set how long flushing waits for queued messages to be sent. Defaults to 5 seconds.
+    pub fn with_flush_timeout(mut self, timeout: Duration) -> Self {
+        self.flush_timeout = timeout;
+        self
+    }
+
+    fn is_enabled(&self, level: ForwardLevel, target: &str) -> bool {
+        level <= self.max_level
+            && (self.included_targets.is_empty()
+                || self
+                    .included_targets
+                    .iter()
+                    .any(|prefix| target.starts_with(prefix.as_str())))
+            && !self
+                .excluded_targets
+                .iter()
+                .any(|prefix| target.starts_with(prefix.as_str()))
+    }
+}
+
+thread_local! {
+    static IS_FORWARDER_THREAD: Cell<bool> = const { Cell::new(false) };
+}
+
+struct Notification {
+    title: String,
+    message: String,
+    priority: u8,
+}
+
+/// The state shared by the `tracing` and `log` backends.
+///
+/// Messages are handed to a dedicated thread through a bounded queue, so
+/// forwarding an event never blocks the caller.
+struct Forwarder {
+    config: ForwardConfig,
+    sender: mpsc::SyncSender<Notification>,
+    window: Mutex<(Instant, u32)>,
+    dropped: AtomicU64,
+    /// The number of queued messages that weren't sent yet.
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl Forwarder {
+    fn new(client: AppClient, config: ForwardConfig) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Notification>(config.queue_capacity);
+        let pending = Arc::new((Mutex::new(0), Condvar::new()));
+
+        std::thread::Builder::new()
+            .name("gotify-forwarder".into())
+            .spawn({
+                let pending = pending.clone();
+                move || {
+                    IS_FORWARDER_THREAD.with(|flag| flag.set(true));
+
+                    let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+                        .enable_all()
+                        .build()
+                    else {
+                        return;
+                    };
+
+                    while let Ok(notification) = receiver.recv() {
+                        let _ = runtime.block_on(
+                            client
+                                .create_message(notification.message)
+                                .with_title(notification.title)
+                                .with_priority(notification.priority)
+                                .with_extras(markdown_extras())
+                                .send(),
+                        );
+
+                        let (count, sent) = &*pending;
+                        *count.lock().unwrap() -= 1;
+                        sent.notify_all();
+                    }
+                }
+            })
+            .expect("failed to spawn the forwarder thread");
+
+        Self {
+            config,
+            sender,
+            window: Mutex::new((Instant::now(), 0)),
+            dropped: AtomicU64::new(0),
+            pending,
+        }
+    }
+
+    /// Block until all queued messages were sent or the flush timeout has passed.
+    fn flush(&self) {
+        // the forwarder thread would wait for itself
+        if IS_FORWARDER_THREAD.with(Cell::get) {
+            return;
+        }
+        let (count, sent) = &*self.pending;
+        let _ = sent
+            .wait_timeout_while(count.lock().unwrap(), self.config.flush_timeout, |count| {
+                *count > 0
+            })
+            .unwrap();
+    }
+
+    fn is_enabled(&self, level: ForwardLevel, target: &str) -> bool {
+        // events emitted while sending a message must not be forwarded again
+        !IS_FORWARDER_THREAD.with(Cell::get) && self.config.is_enabled(level, target)
+    }
+
+    fn forward(&self, level: ForwardLevel, target: &str, mut message: String) {
+        if !self.acquire() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            let _ = write!(message, "\n\n_{dropped} earlier events were dropped_");
+        }
+
+        let notification = Notification {
+            title: format!("{} {target}", level.as_str()),
+            message,
+            priority: self.config.priorities.get(&level).copied().unwrap_or(0),
+        };
+
+        *self.pending.0.lock().unwrap() += 1;
+        if self.sender.try_send(notification).is_err() {
+            *self.pending.0.lock().unwrap() -= 1;
+            // keep the count of earlier dropped events for the next message
+            self.dropped.fetch_add(dropped + 1, Ordering::Relaxed);
+        }
+    }
+
+    fn acquire(&self) -> bool {
+        let Some((max_messages, period)) = self.config.rate_limit else {
+            return true;
+        };
+
+        let mut window = self.window.lock().unwrap();
+        let now = Instant::now();
+
+        if now.duration_since(window.0) >= period {
+            *window = (now, 0);
+        }
+        if window.1 < max_messages {
+            window.1 += 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+pub use self::tracing_layer::GotifyLayer;
+
+#[cfg(feature = "tracing")]
+mod tracing_layer {
+    use std::fmt::Write as _;
+
+    use tracing_core::{
+        field::{Field, Visit},
+        span, Event, Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+    use super::{ForwardConfig, Forwarder};
+    use crate::AppClient;
+
+    /// A [`tracing_subscriber::Layer`] that forwards events to Gotify.
+    ///
+    /// The event's fields and the fields of all its parent spans are
+    /// rendered as markdown.
+    pub struct GotifyLayer {
+        forwarder: Forwarder,
+    }
+
+    impl GotifyLayer {
+        /// Create a new layer that sends messages with the given client.
+        pub fn new(client: AppClient, config: ForwardConfig) -> Self {
+            Self {
+                forwarder: Forwarder::new(client, config),
+            }
+        }
+
+        /// Block until all queued events were sent or the flush timeout has passed.
+        ///
+        /// Events that are still queued when the program exits are lost. Once the
+        /// layer is part of the global subscriber, it can be flushed with
+        /// `tracing::dispatcher::get_default(|d| d.downcast_ref::<GotifyLayer>().map(GotifyLayer::flush))`.
+        pub fn flush(&self) {
+            self.forwarder.flush();
+        }
+    }
+
+    impl std::fmt::Debug for GotifyLayer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("GotifyLayer")
+                .field("config", &self.forwarder.config)
+                .finish_non_exhaustive()
+        }
+    }
+
+    /// Fields of a span, stored in the span's extensions.
+    struct SpanFields(Vec<(&'static str, String)>);
+
+    #[derive(Default)]
+    struct FieldVisitor {
+        message: Option<String>,
+        fields: Vec<(&'static str, String)>,
+    }
+
+    impl Visit for FieldVisitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "message" {
+                self.message = Some(value.to_owned());
+            } else {
+                self.fields.push((field.name(), value.to_owned()));
+            }
+        }
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            if field.name() == "message" {
+                self.message = Some(format!("{value:?}"));
+            } else {
+                self.fields.push((field.name(), format!("{value:?}")));
+            }
+        }
+    }
+
+    impl<S> Layer<S> for GotifyLayer
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            let Some(span) = ctx.span(id) else { return };
+
+            let mut visitor = FieldVisitor::default();
+            attrs.record(&mut visitor);
+
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+
+        fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+            let Some(span) = ctx.span(id) else { return };
+
+            let mut visitor = FieldVisitor::default();
+            values.record(&mut visitor);
+
+            let mut extensions = span.extensions_mut();
+            if let Some(fields) = extensions.get_mut::<SpanFields>() {
+                fields.0.extend(visitor.fields);
+            }
+        }
+
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+
+            if !self
+                .forwarder
+                .is_enabled((*metadata.level()).into(), metadata.target())
+            {
+                return;
+            }
+
+            let mut visitor = FieldVisitor::default();
+            event.record(&mut visitor);
+
+            let mut message = visitor.message.unwrap_or_default();
+
+            if !visitor.fields.is_empty() {
+                message.push('\n');
+                for (name, value) in &visitor.fields {
+                    let _ = write!(message, "\n- **{name}**: `{value}`");
+                }
+            }
+
+            if let Some(scope) = ctx.event_scope(event) {
+                let mut spans = String::new();
+                for span in scope.from_root() {
+                    let _ = write!(spans, "\n- `{}`", span.name());
+                    if let Some(fields) = span.extensions().get::<SpanFields>() {
+                        for (name, value) in &fields.0 {
+                            let _ = write!(spans, " {name}=`{value}`");
+                        }
+                    }
+                }
+                if !spans.is_empty() {
+                    let _ = write!(message, "\n\n**Spans**\n{spans}");
+                }
+            }
+
+            if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+                let _ = write!(message, "\n\n_at {file}:{line}_");
+            }
+
+            self.forwarder
+                .forward((*metadata.level()).into(), metadata.target(), message);
+        }
+    }
+}
+
+#[cfg(feature = "log")]
+pub use self::log_backend::GotifyLogger;
+
+#[cfg(feature = "log")]
+mod log_backend {
+    use std::fmt::Write as _;
+
+    use super::{ForwardConfig, ForwardLevel, Forwarder};
+    use crate::AppClient;
+
+    /// A [`log::Log`] implementation that forwards records to Gotify.
+    pub struct GotifyLogger {
+        forwarder: Forwarder,
+    }
+
+    impl GotifyLogger {
+        /// Create a new logger that sends messages with the given client.
+        pub fn new(client: AppClient, config: ForwardConfig) -> Self {
+            Self {
+                forwarder: Forwarder::new(client, config),
+            }
+        }
+
+        /// Install this logger as the global logger and set the maximum log level accordingly.
+        pub fn init(self) -> Result<(), log::SetLoggerError> {
+            let max_level = match self.forwarder.config.max_level {
+                ForwardLevel::Error => log::LevelFilter::Error,
+                ForwardLevel::Warn => log::LevelFilter::Warn,
+                ForwardLevel::Info => log::LevelFilter::Info,
+                ForwardLevel::Debug => log::LevelFilter::Debug,
+                ForwardLevel::Trace => log::LevelFilter::Trace,
+            };
+
+            log::set_boxed_logger(Box::new(self))?;
+            log::set_max_level(max_level);
+
+            Ok(())
+        }
+    }
+
+    impl std::fmt::Debug for GotifyLogger {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("GotifyLogger")
+                .field("config", &self.forwarder.config)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl log::Log for GotifyLogger {
+        fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+            self.forwarder
+                .is_enabled(metadata.level().into(), metadata.target())
+        }
+
+        fn log(&self, record: &log::Record<'_>) {
+            if !self.enabled(record.metadata()) {
+                return;
+            }
+
+            let mut message = record.args().to_string();
+
+            if let (Some(file), Some(line)) = (record.file(), record.line()) {
+                let _ = write!(message, "\n\n_at {file}:{line}_");
+            }
+
+            self.forwarder
+                .forward(record.level().into(), record.target(), message);
+        }
+
+        /// Block until all queued records were sent or the flush timeout has passed.
+        fn flush(&self) {
+            self.forwarder.flush();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::testsuite::*;
+
+    use super::{ForwardConfig, ForwardLevel, Forwarder};
+
+    #[test]
+    fn flush_waits_for_queued_messages() -> eyre::Result<()> {
+        let forwarder = Forwarder::new(
+            crate::AppClient::new("http://localhost:30081", GOTIFY_APP_TOKEN)?,
+            ForwardConfig::new()
+                .without_rate_limit()
+                .with_flush_timeout(Duration::from_secs(30)),
+        );
+        for i in 0..3 {
+            forwarder.forward(ForwardLevel::Error, "test", format!("event {i}"));
+        }
+
+        forwarder.flush();
+        assert_eq!(*forwarder.pending.0.lock().unwrap(), 0);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manage-messages")]
+    async fn wait_for_message(title: &str) -> eyre::Result<crate::models::Message> {
+        for _ in 0..100 {
+            if let Some(message) = client_client()
+                .get_messages()
+                .await?
+                .messages
+                .into_iter()
+                .find(|m| m.title.as_deref() == Some(title))
+            {
+                return Ok(message);
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        eyre::bail!("message was not forwarded")
+    }
+
+    #[cfg(all(feature = "tracing", feature = "manage-messages"))]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn tracing_layer() -> eyre::Result<()> {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let layer = super::GotifyLayer::new(
+            crate::AppClient::new(GOTIFY_URL, GOTIFY_APP_TOKEN)?,
+            ForwardConfig::new().with_target("gotify_test"),
+        );
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::error_span!("job", id = 42);
+            let _guard = span.enter();
+
+            tracing::info!(target: "gotify_test", "ignored");
+            tracing::warn!(target: "other", "ignored");
+            tracing::error!(target: "gotify_test", attempt = 3, "backup failed");
+        });
+
+        let message = wait_for_message("ERROR gotify_test").await?;
+        assert_eq!(message.priority, 8);
+        assert!(message.message.starts_with("backup failed"));
+        assert!(message.message.contains("**attempt**: `3`"));
+        assert!(message.message.contains("`job` id=`42`"));
+
+        Ok(())
+    }
+
+    #[cfg(all(feature = "log", feature = "manage-messages"))]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn log_backend() -> eyre::Result<()> {
+        use log::Log;
+
+        let logger = super::GotifyLogger::new(
+            crate::AppClient::new(GOTIFY_URL, GOTIFY_APP_TOKEN)?,
+            ForwardConfig::new().with_priority(log::Level::Warn, 6),
+        );
+
+        logger.log(
+            &log::Record::builder()
+                .level(log::Level::Warn)
+                .target("gotify_test")
+                .args(format_args!("disk almost full"))
+                .build(),
+        );
+
+        let message = wait_for_message("WARN gotify_test").await?;
+        assert_eq!(message.priority, 6);
+        assert_eq!(message.message, "disk almost full");
+
+        Ok(())
+    }
+}
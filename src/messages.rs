@@ -1,15 +1,19 @@
 use reqwest::Method;
 
-use crate::{models::PagedMessages, utils::request_builder, ClientClient, Result};
+use crate::{
+    models::{ApplicationId, MessageId, PagedMessages},
+    utils::request_builder,
+    ClientClient, Result,
+};
 
 /// List or delete messages.
 impl ClientClient {
     /// Return all messages from a specific application.
-    pub fn get_application_messages(&self, id: i64) -> GetApplicationMessagesBuilder {
+    pub fn get_application_messages(&self, id: ApplicationId) -> GetApplicationMessagesBuilder<'_> {
         GetApplicationMessagesBuilder::new(self, id)
     }
     /// Delete all messages from a specific application.
-    pub async fn delete_application_messages(&self, id: i64) -> Result<()> {
+    pub async fn delete_application_messages(&self, id: ApplicationId) -> Result<()> {
         self.request(
             Method::DELETE,
             ["application".into(), id.to_string(), "message".into()],
@@ -18,7 +22,7 @@ impl ClientClient {
         .await
     }
     /// Return all messages.
-    pub fn get_messages(&self) -> GetMessagesBuilder {
+    pub fn get_messages(&self) -> GetMessagesBuilder<'_> {
         GetMessagesBuilder::new(self)
     }
     /// Delete all messages.
@@ -26,11 +30,57 @@ impl ClientClient {
         self.request(Method::DELETE, ["message"]).send().await
     }
     /// Delete a message with an id.
-    pub async fn delete_message(&self, id: i64) -> Result<()> {
+    pub async fn delete_message(&self, id: MessageId) -> Result<()> {
         self.request(Method::DELETE, ["message".into(), id.to_string()])
             .send()
             .await
     }
+
+    /// Stream pages of up to 200 messages, newest first, optionally of a single application.
+    ///
+    /// Paging starts below `since` if given and the stream ends after the last page.
+    #[cfg(any(
+        feature = "audit",
+        feature = "backup",
+        feature = "export",
+        feature = "mirror",
+        feature = "queue",
+        feature = "replicate",
+        feature = "retention"
+    ))]
+    pub(crate) fn message_pages(
+        &self,
+        id: Option<ApplicationId>,
+        since: Option<MessageId>,
+    ) -> impl futures_util::Stream<Item = Result<PagedMessages>> + Send + '_ {
+        // `None` once the last page was returned
+        futures_util::stream::try_unfold(Some(since), move |since| async move {
+            let Some(since) = since else {
+                return Ok(None);
+            };
+            let page = match (id, since) {
+                (Some(id), Some(since)) => {
+                    self.get_application_messages(id)
+                        .with_limit(200)
+                        .with_since(since)
+                        .await?
+                }
+                (Some(id), None) => self.get_application_messages(id).with_limit(200).await?,
+                (None, Some(since)) => {
+                    self.get_messages()
+                        .with_limit(200)
+                        .with_since(since)
+                        .await?
+                }
+                (None, None) => self.get_messages().with_limit(200).await?,
+            };
+            let next = match page.messages.last() {
+                Some(last) if page.paging.next.is_some() => Some(Some(last.id)),
+                _ => None,
+            };
+            Ok(Some((page, next)))
+        })
+    }
 }
 
 request_builder! {
@@ -41,11 +91,11 @@ request_builder! {
     return_type = PagedMessages,
     required_fields = {
         #[serde(skip)]
-        id: i64 => i64,
+        id: ApplicationId => ApplicationId,
     },
     optional_fields = {
         limit: usize => usize,
-        since: i64 => i64,
+        since: MessageId => MessageId,
     }
 }
 request_builder! {
@@ -57,20 +107,23 @@ request_builder! {
     required_fields = {},
     optional_fields = {
         limit: usize => usize,
-        since: i64 => i64,
+        since: MessageId => MessageId,
     }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use crate::testsuite::*;
+    use crate::{
+        models::{ApplicationId, MessageId},
+        testsuite::*,
+    };
 
     #[apply(run_test_server!)]
     #[test]
     async fn get_application_messages() -> eyre::Result<()> {
         let client = client_client();
 
-        let messages = client.get_application_messages(3).await?;
+        let messages = client.get_application_messages(ApplicationId(3)).await?;
 
         assert_eq!(
             messages
@@ -81,7 +134,10 @@ pub(crate) mod tests {
             vec!["App1-Message1", "App1-Message0"]
         );
 
-        let messages = client.get_application_messages(3).with_limit(1).await?;
+        let messages = client
+            .get_application_messages(ApplicationId(3))
+            .with_limit(1)
+            .await?;
 
         assert_eq!(
             messages
@@ -101,15 +157,15 @@ pub(crate) mod tests {
         let client = client_client();
 
         assert!(!client
-            .get_application_messages(3)
+            .get_application_messages(ApplicationId(3))
             .await?
             .messages
             .is_empty());
 
-        client.delete_application_messages(3).await?;
+        client.delete_application_messages(ApplicationId(3)).await?;
 
         assert!(client
-            .get_application_messages(3)
+            .get_application_messages(ApplicationId(3))
             .await?
             .messages
             .is_empty());
@@ -125,7 +181,7 @@ pub(crate) mod tests {
         let messages = client.get_messages().with_limit(3).await?;
         assert_eq!(messages.messages.len(), 3);
 
-        let messages = client.get_messages().with_since(5).await?;
+        let messages = client.get_messages().with_since(MessageId(5)).await?;
         assert_eq!(messages.messages.len(), 4);
 
         Ok(())
@@ -155,16 +211,16 @@ pub(crate) mod tests {
             .await?
             .messages
             .iter()
-            .any(|m| m.id == 1));
+            .any(|m| m.id == MessageId(1)));
 
-        client.delete_message(1).await?;
+        client.delete_message(MessageId(1)).await?;
 
         assert!(!client
             .get_messages()
             .await?
             .messages
             .iter()
-            .any(|m| m.id == 1));
+            .any(|m| m.id == MessageId(1)));
 
         Ok(())
     }
@@ -0,0 +1,653 @@
+//! Keep a local SQLite copy of the messages and applications of a server.
+//!
+//! Gotify's API only supports paging through messages and doesn't store
+//! whether a message was read. A [`Mirror`] copies all messages into a local
+//! database, where they can be queried by application, priority, date and
+//! full text, and marked as read or starred.
+//!
+//! [`Mirror::sync()`] downloads all messages that aren't mirrored yet,
+//! [`Mirror::reconcile()`] removes messages that were deleted on the server
+//! and [`Mirror::run()`] keeps the mirror up to date using the message stream.
+//!
+//! ```no_run
+//! # async fn run(client: gotify::ClientClient) -> Result<(), gotify::mirror::MirrorError> {
+//! use gotify::mirror::Mirror;
+//!
+//! let mirror = Mirror::open(client, "inbox.sqlite")?;
+//! mirror.sync().await?;
+//!
+//! for message in mirror.query().with_search("disk full").with_unread(true).fetch()? {
+//!     println!("{}", message.message.message);
+//!     mirror.set_read(message.message.id, true)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{collections::HashSet, path::Path, sync::Mutex, time::Duration};
+
+use futures_util::{StreamExt, TryStreamExt};
+use rusqlite::{params, types::Value, Connection, OptionalExtension};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    models::{Application, ApplicationId, Message, MessageId},
+    ClientClient, Error, WebsocketConnectError, WebsocketError,
+};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS applications (
+    id INTEGER PRIMARY KEY,
+    data TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    appid INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    priority INTEGER NOT NULL,
+    title TEXT,
+    message TEXT NOT NULL,
+    extras TEXT,
+    read INTEGER NOT NULL DEFAULT 0,
+    starred INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS sync_state (
+    key TEXT PRIMARY KEY,
+    value INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS messages_appid ON messages (appid);
+CREATE INDEX IF NOT EXISTS messages_timestamp ON messages (timestamp);
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5 (
+    title, message, content = 'messages', content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, title, message) VALUES (new.id, new.title, new.message);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, title, message)
+        VALUES ('delete', old.id, old.title, old.message);
+END;
+";
+
+/// All messages up to this id are mirrored.
+const SYNCED: &str = "synced";
+/// The newest message of an interrupted sync.
+const RESUME_TOP: &str = "resume_top";
+/// The oldest message of an interrupted sync.
+const RESUME_SINCE: &str = "resume_since";
+
+/// Errors that can occur when updating or querying a [`Mirror`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum MirrorError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to connect to the message stream")]
+    Connect(#[source] Box<WebsocketConnectError>),
+    #[error("the message stream failed")]
+    Stream(#[source] Box<WebsocketError>),
+    #[error("database error")]
+    Database(#[from] rusqlite::Error),
+}
+
+impl From<WebsocketConnectError> for MirrorError {
+    fn from(e: WebsocketConnectError) -> Self {
+        Self::Connect(Box::new(e))
+    }
+}
+impl From<WebsocketError> for MirrorError {
+    fn from(e: WebsocketError) -> Self {
+        Self::Stream(Box::new(e))
+    }
+}
+
+/// A message stored in a [`Mirror`] with its local flags.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct MirroredMessage {
+    /// The mirrored message.
+    pub message: Message,
+    /// Whether the message was marked as read.
+    pub read: bool,
+    /// Whether the message was starred.
+    pub starred: bool,
+}
+
+/// A local SQLite copy of a server's messages and applications.
+#[derive(Debug)]
+pub struct Mirror {
+    client: ClientClient,
+    db: Mutex<Connection>,
+}
+
+impl Mirror {
+    /// Open or create a mirror database.
+    pub fn open(client: ClientClient, path: impl AsRef<Path>) -> Result<Self, MirrorError> {
+        Self::with_connection(client, Connection::open(path)?)
+    }
+    /// Create a mirror that is only kept in memory.
+    pub fn open_in_memory(client: ClientClient) -> Result<Self, MirrorError> {
+        Self::with_connection(client, Connection::open_in_memory()?)
+    }
+    fn with_connection(client: ClientClient, db: Connection) -> Result<Self, MirrorError> {
+        db.execute_batch(SCHEMA)?;
+        Ok(Self {
+            client,
+            db: Mutex::new(db),
+        })
+    }
+
+    fn db(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.db.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Download the applications and all messages that aren't mirrored yet.
+    ///
+    /// On an empty database, this downloads the whole message history.
+    /// An interrupted sync is continued where it stopped.
+    /// Returns the number of new messages.
+    pub async fn sync(&self) -> Result<usize, MirrorError> {
+        self.sync_applications().await?;
+
+        let mut added = 0;
+        if let Some(since) = self.marker(RESUME_SINCE)? {
+            added += self.download(Some(MessageId(since))).await?;
+        }
+        added += self.download(None).await?;
+
+        Ok(added)
+    }
+
+    /// Download the messages older than `since` down to the [`SYNCED`] marker.
+    ///
+    /// Progress is saved after every page, so an interrupted download can be resumed
+    /// from [`RESUME_SINCE`].
+    async fn download(&self, since: Option<MessageId>) -> Result<usize, MirrorError> {
+        let synced = self.marker(SYNCED)?;
+        let mut top = match since {
+            Some(_) => self.marker(RESUME_TOP)?,
+            None => None,
+        };
+
+        let mut added = 0;
+        let mut pages = std::pin::pin!(self.client.message_pages(None, since));
+        while let Some(page) = pages.try_next().await? {
+            let mut reached_synced = false;
+
+            let mut db = self.db();
+            let tx = db.transaction()?;
+            for message in &page.messages {
+                if synced.is_some_and(|synced| message.id.0 <= synced) {
+                    reached_synced = true;
+                    break;
+                }
+                if top.is_none() {
+                    top = Some(message.id.0);
+                    set_marker(&tx, RESUME_TOP, message.id.0)?;
+                }
+                added += insert_message(&tx, message)?;
+            }
+            if let Some(last) = page.messages.last() {
+                set_marker(&tx, RESUME_SINCE, last.id.0)?;
+            }
+            tx.commit()?;
+
+            if reached_synced {
+                break;
+            }
+        }
+
+        // everything down to the previous sync is mirrored now
+        let mut db = self.db();
+        let tx = db.transaction()?;
+        if let Some(top) = top {
+            set_marker(&tx, SYNCED, top)?;
+        }
+        tx.execute(
+            "DELETE FROM sync_state WHERE key IN (?1, ?2)",
+            [RESUME_TOP, RESUME_SINCE],
+        )?;
+        tx.commit()?;
+
+        Ok(added)
+    }
+
+    fn marker(&self, key: &str) -> Result<Option<i64>, MirrorError> {
+        Ok(self
+            .db()
+            .query_row(
+                "SELECT value FROM sync_state WHERE key = ?1",
+                [key],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Download the current list of applications.
+    pub async fn sync_applications(&self) -> Result<(), MirrorError> {
+        let applications = self.client.get_applications().await?;
+
+        let mut db = self.db();
+        let tx = db.transaction()?;
+        tx.execute("DELETE FROM applications", [])?;
+        for application in &applications {
+            tx.execute(
+                "INSERT INTO applications (id, data) VALUES (?1, ?2)",
+                params![
+                    application.id.0,
+                    serde_json::to_string(application).unwrap_or_default()
+                ],
+            )?;
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    /// Remove messages and applications that were deleted on the server.
+    ///
+    /// Returns the number of removed messages.
+    pub async fn reconcile(&self) -> Result<usize, MirrorError> {
+        self.sync_applications().await?;
+
+        let mut ids = HashSet::new();
+        let mut pages = std::pin::pin!(self.client.message_pages(None, None));
+        while let Some(page) = pages.try_next().await? {
+            ids.extend(page.messages.iter().map(|m| m.id.0));
+        }
+
+        let mut db = self.db();
+        let tx = db.transaction()?;
+        let local = tx
+            .prepare("SELECT id FROM messages")?
+            .query_map([], |row| row.get::<_, i64>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut removed = 0;
+        for id in local.into_iter().filter(|id| !ids.contains(id)) {
+            removed += tx.execute("DELETE FROM messages WHERE id = ?1", [id])?;
+        }
+        tx.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Keep the mirror up to date until the message stream ends.
+    ///
+    /// New messages are added as soon as they are streamed and deletions are
+    /// reconciled every `reconcile_interval`.
+    pub async fn run(&self, reconcile_interval: Duration) -> Result<(), MirrorError> {
+        // subscribe before syncing, so no message is missed in between
+        let stream = self.client.stream_messages().await?;
+        let mut stream = std::pin::pin!(stream);
+
+        self.sync().await?;
+
+        let mut interval = tokio::time::interval(reconcile_interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval.tick().await;
+
+        loop {
+            tokio::select! {
+                message = stream.next() => match message {
+                    Some(message) => {
+                        let message = message?;
+                        if self.insert_message(&message)? > 0 && !self.has_application(message.appid)? {
+                            self.sync_applications().await?;
+                        }
+                    }
+                    None => return Ok(()),
+                },
+                _ = interval.tick() => {
+                    self.reconcile().await?;
+                }
+            }
+        }
+    }
+
+    fn insert_message(&self, message: &Message) -> Result<usize, MirrorError> {
+        insert_message(&self.db(), message)
+    }
+
+    fn has_application(&self, id: ApplicationId) -> Result<bool, MirrorError> {
+        Ok(self
+            .db()
+            .query_row("SELECT 1 FROM applications WHERE id = ?1", [id.0], |_| {
+                Ok(())
+            })
+            .optional()?
+            .is_some())
+    }
+
+    /// Return the mirrored applications.
+    pub fn applications(&self) -> Result<Vec<Application>, MirrorError> {
+        let db = self.db();
+        let mut statement = db.prepare("SELECT data FROM applications ORDER BY id")?;
+        let applications = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .filter_map(|data| match data {
+                Ok(data) => serde_json::from_str(&data).ok().map(Ok),
+                Err(e) => Some(Err(e)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(applications)
+    }
+
+    /// Query the mirrored messages, newest first.
+    pub fn query(&self) -> MirrorQuery<'_> {
+        MirrorQuery {
+            mirror: self,
+            conditions: Vec::new(),
+            params: Vec::new(),
+            limit: None,
+        }
+    }
+
+    /// Mark a message as read or unread.
+    pub fn set_read(&self, id: MessageId, read: bool) -> Result<(), MirrorError> {
+        self.db().execute(
+            "UPDATE messages SET read = ?1 WHERE id = ?2",
+            params![read, id.0],
+        )?;
+        Ok(())
+    }
+    /// Mark all messages, optionally only of one application, as read.
+    pub fn mark_all_read(&self, application: Option<ApplicationId>) -> Result<(), MirrorError> {
+        self.db().execute(
+            "UPDATE messages SET read = 1 WHERE ?1 IS NULL OR appid = ?1",
+            [application.map(|id| id.0)],
+        )?;
+        Ok(())
+    }
+    /// Star or unstar a message.
+    pub fn set_starred(&self, id: MessageId, starred: bool) -> Result<(), MirrorError> {
+        self.db().execute(
+            "UPDATE messages SET starred = ?1 WHERE id = ?2",
+            params![starred, id.0],
+        )?;
+        Ok(())
+    }
+}
+
+/// Builder for [`Mirror::query()`].
+#[derive(Debug)]
+pub struct MirrorQuery<'mirror> {
+    mirror: &'mirror Mirror,
+    conditions: Vec<&'static str>,
+    params: Vec<Value>,
+    limit: Option<usize>,
+}
+
+impl<'mirror> MirrorQuery<'mirror> {
+    fn with_condition(mut self, condition: &'static str, param: impl Into<Value>) -> Self {
+        self.conditions.push(condition);
+        self.params.push(param.into());
+        self
+    }
+
+    /// Only return messages of this application.
+    pub fn with_application(self, id: ApplicationId) -> Self {
+        self.with_condition("appid = ?", id.0)
+    }
+    /// Only return messages with at least this priority.
+    pub fn with_min_priority(self, priority: u8) -> Self {
+        self.with_condition("priority >= ?", priority)
+    }
+    /// Only return messages created at or after this time.
+    pub fn with_since(self, since: OffsetDateTime) -> Self {
+        self.with_condition("timestamp >= ?", since.unix_timestamp())
+    }
+    /// Only return messages created at or before this time.
+    pub fn with_until(self, until: OffsetDateTime) -> Self {
+        self.with_condition("timestamp <= ?", until.unix_timestamp())
+    }
+    /// Only return messages whose title or text contains all words of the query.
+    pub fn with_search(self, query: &str) -> Self {
+        self.with_condition(
+            "id IN (SELECT rowid FROM messages_fts WHERE messages_fts MATCH ?)",
+            fts_query(query),
+        )
+    }
+    /// Only return read or unread messages.
+    pub fn with_unread(self, unread: bool) -> Self {
+        self.with_condition("read = ?", !unread)
+    }
+    /// Only return starred or unstarred messages.
+    pub fn with_starred(self, starred: bool) -> Self {
+        self.with_condition("starred = ?", starred)
+    }
+    /// Return at most this many messages.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Run the query.
+    pub fn fetch(self) -> Result<Vec<MirroredMessage>, MirrorError> {
+        let mut sql = String::from(
+            "SELECT id, appid, date, priority, title, message, extras, read, starred FROM messages",
+        );
+        if !self.conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&self.conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY id DESC");
+        if let Some(limit) = self.limit {
+            sql.push_str(&format!(" LIMIT {limit}"));
+        }
+
+        let db = self.mirror.db();
+        let mut statement = db.prepare(&sql)?;
+        let messages = statement
+            .query_map(rusqlite::params_from_iter(self.params), |row| {
+                let date: String = row.get(2)?;
+                let extras: Option<String> = row.get(6)?;
+                Ok(MirroredMessage {
+                    message: Message {
+                        id: MessageId(row.get(0)?),
+                        appid: ApplicationId(row.get(1)?),
+                        date: OffsetDateTime::parse(&date, &Rfc3339).map_err(|e| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                2,
+                                rusqlite::types::Type::Text,
+                                e.into(),
+                            )
+                        })?,
+                        priority: row.get(3)?,
+                        title: row.get(4)?,
+                        message: row.get(5)?,
+                        extras: extras.and_then(|extras| serde_json::from_str(&extras).ok()),
+                    },
+                    read: row.get(7)?,
+                    starred: row.get(8)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(messages)
+    }
+}
+
+fn insert_message(db: &Connection, message: &Message) -> Result<usize, MirrorError> {
+    Ok(db.execute(
+        "INSERT OR IGNORE INTO messages
+            (id, appid, date, timestamp, priority, title, message, extras)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            message.id.0,
+            message.appid.0,
+            message.date.format(&Rfc3339).unwrap_or_default(),
+            message.date.unix_timestamp(),
+            message.priority,
+            message.title,
+            message.message,
+            message
+                .extras
+                .as_ref()
+                .map(|extras| serde_json::to_string(extras).unwrap_or_default()),
+        ],
+    )?)
+}
+
+fn set_marker(db: &Connection, key: &str, value: i64) -> Result<(), MirrorError> {
+    db.execute(
+        "INSERT OR REPLACE INTO sync_state (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+/// Turn arbitrary text into an FTS5 query matching all of its words.
+fn fts_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        models::{ApplicationId, Message, MessageId},
+        testsuite::*,
+        ClientClient,
+    };
+
+    use super::{fts_query, Mirror};
+
+    fn message(id: i64, appid: i64, priority: u8, text: &str) -> Message {
+        Message {
+            priority,
+            ..test_message(id, appid, text)
+        }
+    }
+
+    #[test]
+    fn query() -> eyre::Result<()> {
+        let mirror = Mirror::open_in_memory(ClientClient::new(GOTIFY_URL, GOTIFY_CLIENT_TOKEN)?)?;
+        mirror.insert_message(&message(1, 1, 2, "backup finished"))?;
+        mirror.insert_message(&message(2, 2, 8, "disk almost full"))?;
+        mirror.insert_message(&message(3, 2, 5, "disk cleaned up"))?;
+        // messages are only inserted once
+        assert_eq!(
+            mirror.insert_message(&message(3, 2, 5, "disk cleaned up"))?,
+            0
+        );
+
+        let ids = |messages: Vec<super::MirroredMessage>| {
+            messages
+                .into_iter()
+                .map(|m| m.message.id.0)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(mirror.query().fetch()?), vec![3, 2, 1]);
+        assert_eq!(
+            ids(mirror.query().with_application(ApplicationId(2)).fetch()?),
+            vec![3, 2]
+        );
+        assert_eq!(
+            ids(mirror.query().with_min_priority(5).with_limit(1).fetch()?),
+            vec![3]
+        );
+        assert_eq!(
+            ids(mirror.query().with_search("DISK full").fetch()?),
+            vec![2]
+        );
+
+        mirror.set_read(MessageId(2), true)?;
+        mirror.set_starred(MessageId(1), true)?;
+        assert_eq!(ids(mirror.query().with_unread(true).fetch()?), vec![3, 1]);
+        assert_eq!(ids(mirror.query().with_starred(true).fetch()?), vec![1]);
+
+        mirror.mark_all_read(Some(ApplicationId(2)))?;
+        assert_eq!(ids(mirror.query().with_unread(true).fetch()?), vec![1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn fts_query_quoting() {
+        assert_eq!(fts_query("disk  full"), "\"disk\" \"full\"");
+        assert_eq!(fts_query("say \"hi\" OR"), "\"say\" \"\"\"hi\"\"\" \"OR\"");
+    }
+
+    #[tokio::test]
+    async fn interrupted_sync_is_resumed() -> eyre::Result<()> {
+        const FIRST_PAGE: &str = r#"{
+            "messages": [
+                {"id": 5, "appid": 1, "date": "2023-08-01T12:00:00Z", "message": "5", "priority": 0},
+                {"id": 4, "appid": 1, "date": "2023-08-01T12:00:00Z", "message": "4", "priority": 0}
+            ],
+            "paging": {"limit": 2, "next": "next", "since": 0, "size": 2}
+        }"#;
+        const SECOND_PAGE: &str = r#"{
+            "messages": [
+                {"id": 3, "appid": 1, "date": "2023-08-01T12:00:00Z", "message": "3", "priority": 0}
+            ],
+            "paging": {"limit": 2, "since": 4, "size": 1}
+        }"#;
+        const NEW_PAGE: &str = r#"{
+            "messages": [
+                {"id": 6, "appid": 1, "date": "2023-08-01T12:00:00Z", "message": "6", "priority": 0},
+                {"id": 5, "appid": 1, "date": "2023-08-01T12:00:00Z", "message": "5", "priority": 0}
+            ],
+            "paging": {"limit": 2, "next": "next", "since": 0, "size": 2}
+        }"#;
+        const ERROR: &str =
+            r#"{"error": "Internal Server Error", "errorCode": 500, "errorDescription": ""}"#;
+
+        let (url, requests) = http_stand_in(vec![
+            (200, "[]"),
+            (200, FIRST_PAGE),
+            (500, ERROR),
+            (200, "[]"),
+            (200, SECOND_PAGE),
+            (200, NEW_PAGE),
+        ])
+        .await;
+        let mirror = Mirror::open_in_memory(ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?)?;
+
+        assert!(mirror.sync().await.is_err());
+        assert_eq!(mirror.query().fetch()?.len(), 2);
+
+        // the older page is fetched although newer messages are already mirrored
+        assert_eq!(mirror.sync().await?, 2);
+        let ids = mirror
+            .query()
+            .fetch()?
+            .into_iter()
+            .map(|m| m.message.id.0)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![6, 5, 4, 3]);
+        assert!(requests.lock().unwrap()[4].0.contains("since=4"));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn sync_and_reconcile() -> eyre::Result<()> {
+        let client = client_client();
+        let mirror = Mirror::open_in_memory((*client).clone())?;
+
+        let count = client.get_messages().await?.messages.len();
+        assert_eq!(mirror.sync().await?, count);
+        assert_eq!(mirror.sync().await?, 0);
+        assert!(mirror.applications()?.iter().any(|a| a.name == "App1"));
+        assert_eq!(
+            mirror
+                .query()
+                .with_application(ApplicationId(3))
+                .fetch()?
+                .len(),
+            2
+        );
+
+        client.delete_message(MessageId(1)).await?;
+        assert_eq!(mirror.reconcile().await?, 1);
+        assert_eq!(mirror.query().fetch()?.len(), count - 1);
+
+        Ok(())
+    }
+}
@@ -3,15 +3,55 @@
 
 use serde::{Deserialize, Serialize};
 
+macro_rules! id_types {
+    ( $( $( #[ $attr:meta ] )* $name:ident ),* $(,)? ) => {
+        $(
+            $( #[ $attr ] )*
+            #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+            #[serde(transparent)]
+            pub struct $name(pub i64);
+
+            impl From<i64> for $name {
+                fn from(id: i64) -> Self {
+                    Self(id)
+                }
+            }
+            impl From<$name> for i64 {
+                fn from(id: $name) -> Self {
+                    id.0
+                }
+            }
+            impl std::fmt::Display for $name {
+                fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                    self.0.fmt(f)
+                }
+            }
+        )*
+    };
+}
+
+id_types! {
+    /// The id of an application.
+    ApplicationId,
+    /// The id of a client.
+    ClientId,
+    /// The id of a message.
+    MessageId,
+    /// The id of a plugin.
+    PluginId,
+    /// The id of a user.
+    UserId,
+}
+
 #[cfg(feature = "manage-applications")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-applications")))]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 #[non_exhaustive]
 pub struct Application {
     pub default_priority: Option<u8>,
     pub description: String,
-    pub id: i64,
+    pub id: ApplicationId,
     pub image: String,
     pub internal: bool,
     #[serde(default, with = "time::serde::iso8601::option")]
@@ -20,12 +60,24 @@ pub struct Application {
     pub token: String,
 }
 
+/// The image of an application, returned by [`ClientClient::get_application_image()`](crate::ClientClient::get_application_image).
+#[cfg(feature = "manage-applications")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manage-applications")))]
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ApplicationImage {
+    /// The MIME type reported by the server or guessed from the file extension of a cached image.
+    pub content_type: Option<String>,
+    /// The raw image data.
+    pub content: Vec<u8>,
+}
+
 #[cfg(feature = "manage-clients")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-clients")))]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[non_exhaustive]
 pub struct Client {
-    pub id: i64,
+    pub id: ClientId,
     #[serde(default, with = "time::serde::iso8601::option")]
     pub last_used: Option<time::OffsetDateTime>,
     pub name: String,
@@ -63,20 +115,40 @@ pub struct Health {
     docsrs,
     doc(cfg(any(feature = "app", feature = "manage-messages", feature = "websocket")))
 )]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 #[non_exhaustive]
 pub struct Message {
-    pub appid: i64,
+    pub appid: ApplicationId,
     #[serde(with = "time::serde::iso8601")]
     pub date: time::OffsetDateTime,
     pub extras: Option<std::collections::HashMap<String, serde_json::Value>>,
-    pub id: i64,
+    pub id: MessageId,
     pub message: String,
     pub priority: u8,
     pub title: Option<String>,
 }
 
+#[cfg(any(feature = "app", feature = "manage-messages", feature = "websocket"))]
+impl Message {
+    /// The extras key containing a message's time to live in seconds.
+    pub const TTL_EXTRAS_KEY: &'static str = "gotify-rs::ttl";
+
+    /// Return the time to live set by [`MessageBuilder::with_ttl()`](crate::builder::MessageBuilder::with_ttl).
+    pub fn ttl(&self) -> Option<std::time::Duration> {
+        self.extras
+            .as_ref()?
+            .get(Self::TTL_EXTRAS_KEY)?
+            .as_u64()
+            .map(std::time::Duration::from_secs)
+    }
+    /// Return the time after which the message is expired according to its time to live.
+    pub fn expires_at(&self) -> Option<time::OffsetDateTime> {
+        let ttl = time::Duration::try_from(self.ttl()?).ok()?;
+        self.date.checked_add(ttl)
+    }
+}
+
 #[cfg(feature = "manage-messages")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-messages")))]
 #[derive(Debug, Deserialize, Serialize)]
@@ -93,10 +165,56 @@ pub struct PagedMessages {
 pub struct Paging {
     pub limit: usize,
     pub next: Option<String>,
-    pub since: i64,
+    pub since: MessageId,
     pub size: usize,
 }
 
+#[cfg(feature = "manage-plugins")]
+#[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum PluginCapability {
+    Webhooker,
+    Displayer,
+    Configurer,
+    Messenger,
+    Storager,
+    /// A capability that is not known to this version of the crate.
+    Unknown(String),
+}
+#[cfg(feature = "manage-plugins")]
+impl From<String> for PluginCapability {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "webhooker" => PluginCapability::Webhooker,
+            "displayer" => PluginCapability::Displayer,
+            "configurer" => PluginCapability::Configurer,
+            "messenger" => PluginCapability::Messenger,
+            "storager" => PluginCapability::Storager,
+            _ => PluginCapability::Unknown(s),
+        }
+    }
+}
+#[cfg(feature = "manage-plugins")]
+impl From<PluginCapability> for String {
+    fn from(capability: PluginCapability) -> Self {
+        capability.to_string()
+    }
+}
+#[cfg(feature = "manage-plugins")]
+impl std::fmt::Display for PluginCapability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            PluginCapability::Webhooker => "webhooker",
+            PluginCapability::Displayer => "displayer",
+            PluginCapability::Configurer => "configurer",
+            PluginCapability::Messenger => "messenger",
+            PluginCapability::Storager => "storager",
+            PluginCapability::Unknown(s) => s,
+        })
+    }
+}
+
 #[cfg(feature = "manage-plugins")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-plugins")))]
 #[derive(Debug, Deserialize, Serialize)]
@@ -104,23 +222,30 @@ pub struct Paging {
 #[non_exhaustive]
 pub struct PluginConf {
     pub author: Option<String>,
-    pub capabilities: Vec<String>,
+    pub capabilities: Vec<PluginCapability>,
     pub enabled: bool,
-    pub id: i64,
+    pub id: PluginId,
     pub license: Option<String>,
     pub module_path: String,
     pub name: String,
     pub token: String,
     pub website: Option<String>,
 }
+#[cfg(feature = "manage-plugins")]
+impl PluginConf {
+    /// Check whether the plugin has a capability.
+    pub fn has_capability(&self, capability: &PluginCapability) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
 
 #[cfg(feature = "manage-users")]
 #[cfg_attr(docsrs, doc(cfg(feature = "manage-users")))]
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 #[non_exhaustive]
 pub struct User {
     pub admin: bool,
-    pub id: i64,
+    pub id: UserId,
     pub name: String,
 }
 
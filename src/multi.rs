@@ -0,0 +1,478 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use reqwest::header::{HeaderValue, InvalidHeaderValue};
+use url::Url;
+
+use crate::{models::Message, AppClient, Error, InitError};
+
+/// Send messages to multiple Gotify servers.
+///
+/// Every target is a pair of server URL and app token. Depending on the
+/// [`DeliveryMode`], a message is either sent to the first target that
+/// accepts it or to all targets at once.
+///
+/// Each target has its own circuit breaker: after a number of consecutive
+/// failures, the target is skipped until a cooldown period has passed.
+/// Afterwards, a single attempt decides whether the target is used again;
+/// other messages skip the target while it is in progress.
+#[derive(Debug)]
+pub struct MultiAppClient {
+    targets: Vec<Target>,
+    mode: DeliveryMode,
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+/// Determines how a [`MultiAppClient`] delivers messages.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeliveryMode {
+    /// Try the targets in the order they were added and stop at the first success.
+    Failover,
+    /// Send the message to all targets concurrently.
+    Broadcast,
+}
+
+#[derive(Debug)]
+struct Target {
+    client: AppClient,
+    health: Mutex<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    last_success: Option<Instant>,
+    last_failure: Option<Instant>,
+}
+
+impl MultiAppClient {
+    /// The default number of consecutive failures after which a target is skipped.
+    pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+    /// The default duration for which a failing target is skipped.
+    pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+    /// Create a new client from pairs of server URLs and app tokens.
+    ///
+    /// In [`DeliveryMode::Failover`], the order of the targets determines their priority.
+    pub fn new<U, T>(
+        mode: DeliveryMode,
+        targets: impl IntoIterator<Item = (U, T)>,
+    ) -> core::result::Result<Self, InitError>
+    where
+        U: TryInto<Url, Error = url::ParseError>,
+        T: TryInto<HeaderValue, Error = InvalidHeaderValue>,
+    {
+        Ok(Self::from_clients(
+            mode,
+            targets
+                .into_iter()
+                .map(|(server_url, access_token)| AppClient::new(server_url, access_token))
+                .collect::<core::result::Result<Vec<_>, _>>()?,
+        ))
+    }
+
+    /// Create a new client from already configured [`AppClient`]s.
+    pub fn from_clients(mode: DeliveryMode, clients: impl IntoIterator<Item = AppClient>) -> Self {
+        Self {
+            targets: clients
+                .into_iter()
+                .map(|client| Target {
+                    client,
+                    health: Mutex::default(),
+                })
+                .collect(),
+            mode,
+            failure_threshold: Self::DEFAULT_FAILURE_THRESHOLD,
+            cooldown: Self::DEFAULT_COOLDOWN,
+        }
+    }
+
+    /// Configure after how many consecutive failures a target is skipped and for how long.
+    ///
+    /// A `failure_threshold` of `0` disables circuit breaking.
+    pub fn with_circuit_breaker(mut self, failure_threshold: u32, cooldown: Duration) -> Self {
+        self.failure_threshold = failure_threshold;
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Return the delivery mode of this client.
+    pub fn mode(&self) -> DeliveryMode {
+        self.mode
+    }
+
+    /// Return the current health of all targets, in the order they were added.
+    pub fn health(&self) -> Vec<TargetHealth> {
+        let now = Instant::now();
+
+        self.targets
+            .iter()
+            .map(|target| {
+                let health = target.health.lock().unwrap();
+                TargetHealth {
+                    url: target.client.base_url.clone(),
+                    state: health.circuit_state(now),
+                    consecutive_failures: health.consecutive_failures,
+                    last_success: health.last_success,
+                    last_failure: health.last_failure,
+                }
+            })
+            .collect()
+    }
+
+    /// Create a message on the configured targets.
+    pub fn create_message(&self, message: impl Into<String>) -> MultiMessageBuilder<'_> {
+        MultiMessageBuilder {
+            client: self,
+            message: message.into(),
+            title: None,
+            extras: None,
+            priority: None,
+        }
+    }
+
+    async fn send_to(&self, target: &Target, builder: &MultiMessageBuilder<'_>) -> TargetResult {
+        if !target
+            .health
+            .lock()
+            .unwrap()
+            .try_acquire(Instant::now(), self.cooldown)
+        {
+            return TargetResult::Skipped;
+        }
+
+        let mut request = target.client.create_message(builder.message.clone());
+        if let Some(title) = &builder.title {
+            request = request.with_title(title.clone());
+        }
+        if let Some(extras) = &builder.extras {
+            request = request.with_extras(extras.clone());
+        }
+        if let Some(priority) = builder.priority {
+            request = request.with_priority(priority);
+        }
+
+        let result = request.await;
+
+        let mut health = target.health.lock().unwrap();
+        match result {
+            Ok(message) => {
+                health.record_success(Instant::now());
+                TargetResult::Sent(message)
+            }
+            Err(e) => {
+                health.record_failure(Instant::now(), self.failure_threshold, self.cooldown);
+                TargetResult::Failed(e)
+            }
+        }
+    }
+}
+
+impl HealthState {
+    fn circuit_state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(open_until) if now < open_until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+    /// Return whether a request may be sent to the target.
+    ///
+    /// Only one request probes a half-open target: the target is skipped for
+    /// another cooldown period until the probe is recorded, so a probe that
+    /// is cancelled doesn't block the target forever.
+    fn try_acquire(&mut self, now: Instant, cooldown: Duration) -> bool {
+        match self.circuit_state(now) {
+            CircuitState::Closed => true,
+            CircuitState::Open => false,
+            CircuitState::HalfOpen => {
+                self.open_until = Some(now + cooldown);
+                true
+            }
+        }
+    }
+    fn record_success(&mut self, now: Instant) {
+        self.consecutive_failures = 0;
+        self.open_until = None;
+        self.last_success = Some(now);
+    }
+    fn record_failure(&mut self, now: Instant, failure_threshold: u32, cooldown: Duration) {
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        self.last_failure = Some(now);
+        if failure_threshold > 0 && self.consecutive_failures >= failure_threshold {
+            self.open_until = Some(now + cooldown);
+        }
+    }
+}
+
+/// Builder for a message sent by a [`MultiAppClient`].
+#[derive(Debug)]
+pub struct MultiMessageBuilder<'client> {
+    client: &'client MultiAppClient,
+    message: String,
+    title: Option<String>,
+    extras: Option<HashMap<String, serde_json::Value>>,
+    priority: Option<u8>,
+}
+
+#[allow(missing_docs)]
+impl<'client> MultiMessageBuilder<'client> {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+    pub fn with_extras(mut self, extras: impl Into<HashMap<String, serde_json::Value>>) -> Self {
+        self.extras = Some(extras.into());
+        self
+    }
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+    pub async fn send(self) -> core::result::Result<Delivery, DeliveryError> {
+        let client = self.client;
+
+        let outcomes = match client.mode {
+            DeliveryMode::Failover => {
+                let mut outcomes = Vec::new();
+                for target in &client.targets {
+                    let result = client.send_to(target, &self).await;
+                    let sent = matches!(result, TargetResult::Sent(_));
+                    outcomes.push(TargetOutcome {
+                        url: target.client.base_url.clone(),
+                        result,
+                    });
+                    if sent {
+                        break;
+                    }
+                }
+                outcomes
+            }
+            DeliveryMode::Broadcast => {
+                futures_util::future::join_all(client.targets.iter().map(|target| async {
+                    TargetOutcome {
+                        url: target.client.base_url.clone(),
+                        result: client.send_to(target, &self).await,
+                    }
+                }))
+                .await
+            }
+        };
+
+        let delivery = Delivery { outcomes };
+
+        if delivery.message().is_some() {
+            Ok(delivery)
+        } else {
+            Err(DeliveryError::NoTargetSucceeded(delivery))
+        }
+    }
+}
+
+impl<'client> std::future::IntoFuture for MultiMessageBuilder<'client> {
+    type Output = core::result::Result<Delivery, DeliveryError>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// The result of sending a message with a [`MultiAppClient`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Delivery {
+    /// The outcome of every target that was considered, in the order of the targets.
+    ///
+    /// In [`DeliveryMode::Failover`], targets after the first successful one are omitted.
+    pub outcomes: Vec<TargetOutcome>,
+}
+
+impl Delivery {
+    /// Return the first message that was created successfully.
+    pub fn message(&self) -> Option<&Message> {
+        self.outcomes
+            .iter()
+            .find_map(|outcome| match &outcome.result {
+                TargetResult::Sent(message) => Some(message),
+                _ => None,
+            })
+    }
+}
+
+/// The outcome of sending a message to a single target.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct TargetOutcome {
+    /// The server URL of the target.
+    pub url: Url,
+    /// What happened when sending the message to the target.
+    pub result: TargetResult,
+}
+
+/// What happened when sending a message to a single target.
+#[derive(Debug)]
+pub enum TargetResult {
+    /// The message was created.
+    Sent(Message),
+    /// The request failed.
+    Failed(Error),
+    /// The target was skipped because its circuit breaker is open.
+    Skipped,
+}
+
+/// A snapshot of the health of a single target.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TargetHealth {
+    /// The server URL of the target.
+    pub url: Url,
+    /// The state of the target's circuit breaker.
+    pub state: CircuitState,
+    /// The number of failed requests since the last successful one.
+    pub consecutive_failures: u32,
+    /// When the last request succeeded.
+    pub last_success: Option<Instant>,
+    /// When the last request failed.
+    pub last_failure: Option<Instant>,
+}
+
+/// The state of a target's circuit breaker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The target is used normally.
+    Closed,
+    /// The target failed too often and is skipped until the cooldown has passed,
+    /// or a single request is probing the target.
+    Open,
+    /// The cooldown has passed and the next request decides whether the target is used again.
+    HalfOpen,
+}
+
+/// Errors that can occur when sending a message with a [`MultiAppClient`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum DeliveryError {
+    #[error("the message could not be delivered to any target")]
+    NoTargetSucceeded(Delivery),
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::testsuite::*;
+
+    use super::{CircuitState, DeliveryMode, HealthState, MultiAppClient, TargetResult};
+
+    const UNREACHABLE_URL: &str = "http://localhost:30081";
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn failover() -> eyre::Result<()> {
+        let client = MultiAppClient::new(
+            DeliveryMode::Failover,
+            [
+                (UNREACHABLE_URL, GOTIFY_APP_TOKEN),
+                (GOTIFY_URL, GOTIFY_APP_TOKEN),
+                (GOTIFY_URL, GOTIFY_APP_TOKEN),
+            ],
+        )?;
+
+        let delivery = client.create_message("Hello World").await?;
+
+        assert_eq!(delivery.outcomes.len(), 2);
+        assert!(matches!(
+            delivery.outcomes[0].result,
+            TargetResult::Failed(_)
+        ));
+        assert_eq!(delivery.message().unwrap().message, "Hello World");
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn broadcast() -> eyre::Result<()> {
+        let client = MultiAppClient::new(
+            DeliveryMode::Broadcast,
+            [
+                (GOTIFY_URL, GOTIFY_APP_TOKEN),
+                (UNREACHABLE_URL, GOTIFY_APP_TOKEN),
+                (GOTIFY_URL, GOTIFY_APP_TOKEN),
+            ],
+        )?;
+
+        let delivery = client
+            .create_message("Hello World")
+            .with_title("Hi")
+            .await?;
+
+        assert_eq!(delivery.outcomes.len(), 3);
+        assert!(matches!(delivery.outcomes[0].result, TargetResult::Sent(_)));
+        assert!(matches!(
+            delivery.outcomes[1].result,
+            TargetResult::Failed(_)
+        ));
+        assert!(matches!(delivery.outcomes[2].result, TargetResult::Sent(_)));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn circuit_breaker() -> eyre::Result<()> {
+        let client = MultiAppClient::new(
+            DeliveryMode::Failover,
+            [
+                (UNREACHABLE_URL, GOTIFY_APP_TOKEN),
+                (GOTIFY_URL, GOTIFY_APP_TOKEN),
+            ],
+        )?
+        .with_circuit_breaker(2, Duration::from_secs(3600));
+
+        for _ in 0..2 {
+            let delivery = client.create_message("Hello World").await?;
+            assert!(matches!(
+                delivery.outcomes[0].result,
+                TargetResult::Failed(_)
+            ));
+        }
+
+        let health = client.health();
+        assert_eq!(health[0].state, CircuitState::Open);
+        assert_eq!(health[0].consecutive_failures, 2);
+        assert_eq!(health[1].state, CircuitState::Closed);
+
+        let delivery = client.create_message("Hello World").await?;
+        assert!(matches!(delivery.outcomes[0].result, TargetResult::Skipped));
+        assert_eq!(delivery.message().unwrap().message, "Hello World");
+
+        Ok(())
+    }
+
+    #[test]
+    fn half_open_allows_a_single_probe() {
+        let cooldown = Duration::from_secs(30);
+        let now = Instant::now();
+        let mut health = HealthState::default();
+
+        health.record_failure(now, 1, cooldown);
+        assert!(!health.try_acquire(now, cooldown));
+
+        let later = now + cooldown;
+        assert_eq!(health.circuit_state(later), CircuitState::HalfOpen);
+        assert!(health.try_acquire(later, cooldown));
+        assert!(!health.try_acquire(later, cooldown));
+
+        health.record_success(later);
+        assert!(health.try_acquire(later, cooldown));
+        assert!(health.try_acquire(later, cooldown));
+    }
+}
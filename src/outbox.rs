@@ -0,0 +1,414 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{models::Message, utils::write_atomically, AppClient, Error};
+
+/// Send messages through a persistent queue that survives outages and restarts.
+///
+/// If a message can't be sent because the server is unreachable or returns a
+/// server error, it is appended to a file-backed queue. Queued messages are
+/// delivered in order by [`deliver()`](Self::deliver), which is called before
+/// every new message and periodically by [`run()`](Self::run). While messages
+/// are queued, new messages are queued behind them, so the order is kept.
+///
+/// Queued messages are dropped and passed to the
+/// [failure handler](Self::with_on_failure) if the server rejects them, if
+/// they are older than the maximum age or if the queue is full.
+///
+/// Delivery is at-least-once: a message is removed from the queue only after
+/// the server confirmed it, so a request that times out after the server
+/// received it, or a crash before the queue file is written, sends it again.
+pub struct Outbox {
+    client: AppClient,
+    path: PathBuf,
+    queue: Mutex<VecDeque<OutboxItem>>,
+    delivering: tokio::sync::Mutex<()>,
+    max_age: Option<Duration>,
+    max_items: Option<usize>,
+    on_failure: Option<FailureHandler>,
+}
+
+type FailureHandler = Box<dyn Fn(&OutboxItem, &OutboxFailure) + Send + Sync>;
+
+/// A message waiting in an [`Outbox`].
+#[allow(missing_docs)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct OutboxItem {
+    pub message: String,
+    pub title: Option<String>,
+    pub priority: Option<u8>,
+    pub extras: Option<HashMap<String, serde_json::Value>>,
+    /// When the message was passed to the outbox.
+    #[serde(with = "time::serde::iso8601")]
+    pub created_at: OffsetDateTime,
+}
+
+/// Why a queued message was dropped.
+#[derive(Debug)]
+pub enum OutboxFailure {
+    /// The server rejected the message.
+    Rejected(Error),
+    /// The message was older than the maximum age.
+    Expired,
+    /// The queue was full, so the oldest message was dropped.
+    Overflow,
+}
+
+/// The result of sending a message through an [`Outbox`].
+#[derive(Debug)]
+pub enum OutboxOutcome {
+    /// The message was created.
+    Sent(Message),
+    /// The message was queued and will be delivered later.
+    Queued,
+}
+
+/// Errors that can occur when using an [`Outbox`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum OutboxError {
+    #[error("the server rejected the message")]
+    Rejected(#[source] Error),
+    #[error("failed to read or write the outbox file")]
+    Io(#[from] std::io::Error),
+    #[error("invalid outbox file")]
+    Format(#[from] serde_json::Error),
+}
+
+impl Outbox {
+    /// Open an outbox backed by a file, loading messages queued by a previous run.
+    pub fn open(
+        client: AppClient,
+        path: impl Into<PathBuf>,
+    ) -> core::result::Result<Self, OutboxError> {
+        let path = path.into();
+        let queue = match std::fs::read_to_string(&path) {
+            Ok(content) => content
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(serde_json::from_str)
+                .collect::<core::result::Result<_, _>>()?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => VecDeque::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            client,
+            path,
+            queue: Mutex::new(queue),
+            delivering: tokio::sync::Mutex::new(()),
+            max_age: None,
+            max_items: None,
+            on_failure: None,
+        })
+    }
+    /// Drop queued messages older than `max_age`.
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+    /// Keep at most `max_items` queued messages, dropping the oldest ones.
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        self.max_items = Some(max_items.max(1));
+        self
+    }
+    /// Call `handler` for every queued message that is dropped.
+    pub fn with_on_failure(
+        mut self,
+        handler: impl Fn(&OutboxItem, &OutboxFailure) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_failure = Some(Box::new(handler));
+        self
+    }
+
+    /// Return the number of queued messages.
+    pub fn len(&self) -> usize {
+        self.queue.lock().unwrap().len()
+    }
+    /// Return whether no messages are queued.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Send a message, or queue it if the server is unavailable.
+    pub fn create_message(&self, message: impl Into<String>) -> OutboxMessageBuilder<'_> {
+        OutboxMessageBuilder {
+            outbox: self,
+            item: OutboxItem {
+                message: message.into(),
+                title: None,
+                priority: None,
+                extras: None,
+                created_at: OffsetDateTime::now_utc(),
+            },
+        }
+    }
+
+    /// Deliver queued messages in order until the queue is empty or the server is unavailable.
+    ///
+    /// Returns the number of delivered messages.
+    pub async fn deliver(&self) -> core::result::Result<usize, OutboxError> {
+        let _delivering = self.delivering.lock().await;
+        self.deliver_locked().await
+    }
+
+    /// Call [`deliver()`](Self::deliver) every `period` until the outbox file can't be written.
+    pub async fn run(&self, period: Duration) -> core::result::Result<(), OutboxError> {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.deliver().await?;
+        }
+    }
+
+    async fn deliver_locked(&self) -> core::result::Result<usize, OutboxError> {
+        self.expire()?;
+
+        let mut delivered = 0;
+        loop {
+            let Some(item) = self.queue.lock().unwrap().front().cloned() else {
+                return Ok(delivered);
+            };
+
+            match self.send(item.clone()).await {
+                Ok(_) => delivered += 1,
+                Err(e) if is_transient(&e) => return Ok(delivered),
+                Err(e) => self.fail(&item, &OutboxFailure::Rejected(e)),
+            }
+            self.queue.lock().unwrap().pop_front();
+            self.persist()?;
+        }
+    }
+
+    /// Drop expired messages and messages exceeding the maximum number of items.
+    fn expire(&self) -> core::result::Result<(), OutboxError> {
+        let now = OffsetDateTime::now_utc();
+        let mut dropped = Vec::new();
+        {
+            let mut queue = self.queue.lock().unwrap();
+            if let Some(max_age) = self.max_age {
+                while queue
+                    .front()
+                    .is_some_and(|item| now - item.created_at >= max_age)
+                {
+                    dropped.push((queue.pop_front().unwrap(), OutboxFailure::Expired));
+                }
+            }
+            if let Some(max_items) = self.max_items {
+                while queue.len() > max_items {
+                    dropped.push((queue.pop_front().unwrap(), OutboxFailure::Overflow));
+                }
+            }
+        }
+
+        if !dropped.is_empty() {
+            for (item, failure) in &dropped {
+                self.fail(item, failure);
+            }
+            self.persist()?;
+        }
+        Ok(())
+    }
+
+    fn fail(&self, item: &OutboxItem, failure: &OutboxFailure) {
+        if let Some(on_failure) = &self.on_failure {
+            on_failure(item, failure);
+        }
+    }
+
+    async fn send(&self, item: OutboxItem) -> crate::Result<Message> {
+        let mut builder = self.client.create_message(item.message);
+        if let Some(title) = item.title {
+            builder = builder.with_title(title);
+        }
+        if let Some(priority) = item.priority {
+            builder = builder.with_priority(priority);
+        }
+        if let Some(extras) = item.extras {
+            builder = builder.with_extras(extras);
+        }
+        builder.await
+    }
+
+    /// Write the queue to the outbox file atomically.
+    fn persist(&self) -> core::result::Result<(), OutboxError> {
+        let mut content = Vec::new();
+        for item in self.queue.lock().unwrap().iter() {
+            serde_json::to_writer(&mut content, item)?;
+            content.push(b'\n');
+        }
+
+        write_atomically(&self.path, &content)?;
+        Ok(())
+    }
+}
+
+/// Return whether a request may succeed if it is retried later.
+///
+/// Other request errors, like an unreadable response, won't go away by retrying.
+fn is_transient(e: &Error) -> bool {
+    match e {
+        Error::Reqwest(e) => e.is_connect() || e.is_timeout(),
+        Error::Response(response) => response.error_code >= 500,
+        _ => false,
+    }
+}
+
+impl std::fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Outbox")
+            .field("client", &self.client)
+            .field("path", &self.path)
+            .field("queue", &self.queue)
+            .field("max_age", &self.max_age)
+            .field("max_items", &self.max_items)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Builder for a message sent through an [`Outbox`].
+#[derive(Debug)]
+pub struct OutboxMessageBuilder<'outbox> {
+    outbox: &'outbox Outbox,
+    item: OutboxItem,
+}
+
+#[allow(missing_docs)]
+impl<'outbox> OutboxMessageBuilder<'outbox> {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.item.title = Some(title.into());
+        self
+    }
+    pub fn with_extras(mut self, extras: impl Into<HashMap<String, serde_json::Value>>) -> Self {
+        self.item.extras = Some(extras.into());
+        self
+    }
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.item.priority = Some(priority);
+        self
+    }
+    pub async fn send(self) -> core::result::Result<OutboxOutcome, OutboxError> {
+        let outbox = self.outbox;
+        let _delivering = outbox.delivering.lock().await;
+
+        outbox.deliver_locked().await?;
+        if outbox.is_empty() {
+            match outbox.send(self.item.clone()).await {
+                Ok(message) => return Ok(OutboxOutcome::Sent(message)),
+                Err(e) if is_transient(&e) => {}
+                Err(e) => return Err(OutboxError::Rejected(e)),
+            }
+        }
+
+        outbox.queue.lock().unwrap().push_back(self.item);
+        outbox.expire()?;
+        outbox.persist()?;
+        Ok(OutboxOutcome::Queued)
+    }
+}
+
+impl<'outbox> std::future::IntoFuture for OutboxMessageBuilder<'outbox> {
+    type Output = core::result::Result<OutboxOutcome, OutboxError>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'outbox>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{testsuite::*, AppClient};
+
+    use super::{Outbox, OutboxError, OutboxFailure, OutboxOutcome};
+
+    const UNREACHABLE_URL: &str = "http://localhost:30081";
+
+    #[tokio::test]
+    async fn queue_while_unreachable() -> eyre::Result<()> {
+        let path = temp_path("outbox-unreachable.jsonl");
+        let dropped = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = dropped.clone();
+        let outbox = Outbox::open(AppClient::new(UNREACHABLE_URL, GOTIFY_APP_TOKEN)?, &path)?
+            .with_max_items(2)
+            .with_on_failure(move |item, failure| {
+                assert!(matches!(failure, OutboxFailure::Overflow));
+                recorded.lock().unwrap().push(item.message.clone());
+            });
+
+        for text in ["first", "second", "third"] {
+            let outcome = outbox
+                .create_message(text)
+                .with_title("offline")
+                .with_priority(4)
+                .await?;
+            assert!(matches!(outcome, OutboxOutcome::Queued));
+        }
+        assert_eq!(*dropped.lock().unwrap(), ["first"]);
+
+        let reopened = Outbox::open(AppClient::new(UNREACHABLE_URL, GOTIFY_APP_TOKEN)?, &path)?;
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(reopened.queue.lock().unwrap()[0].message, "second");
+        assert_eq!(reopened.queue.lock().unwrap()[0].priority, Some(4));
+
+        let expiring = reopened.with_max_age(Duration::ZERO);
+        assert_eq!(expiring.deliver().await?, 0);
+        assert!(expiring.is_empty());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unreadable_responses_are_not_retried() -> eyre::Result<()> {
+        let path = temp_path("outbox-unreadable.jsonl");
+        let (url, requests) = http_stand_in(vec![(200, "not json")]).await;
+        let outbox = Outbox::open(AppClient::new(url.as_str(), GOTIFY_APP_TOKEN)?, &path)?;
+
+        let result = outbox.create_message("once").await;
+        assert!(matches!(result, Err(OutboxError::Rejected(_))));
+        assert!(outbox.is_empty());
+        assert_eq!(requests.lock().unwrap().len(), 1);
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn deliver_after_restart() -> eyre::Result<()> {
+        let path = temp_path("outbox-restart.jsonl");
+
+        let offline = Outbox::open(AppClient::new(UNREACHABLE_URL, GOTIFY_APP_TOKEN)?, &path)?;
+        for text in ["queued 1", "queued 2"] {
+            offline.create_message(text).await?;
+        }
+
+        let online = Outbox::open((*app_client()).clone(), &path)?;
+        let OutboxOutcome::Sent(message) = online.create_message("direct").await? else {
+            panic!("the message wasn't sent");
+        };
+        assert_eq!(message.message, "direct");
+        assert!(online.is_empty());
+        assert_eq!(std::fs::read_to_string(&path)?, "");
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
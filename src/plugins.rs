@@ -1,6 +1,12 @@
 use reqwest::Method;
+use serde::{de::DeserializeOwned, Serialize};
+use url::Url;
 
-use crate::{models::PluginConf, ClientClient, Result};
+use crate::{
+    models::{PluginCapability, PluginConf, PluginId},
+    utils::UrlAppend,
+    ClientClient, Error, RequestBuilder, Result,
+};
 
 /// List or configure plugins.
 impl ClientClient {
@@ -10,8 +16,39 @@ impl ClientClient {
             .send_and_read_json()
             .await
     }
+    /// Return the plugin with the given module path, e.g. `github.com/gotify/plugin-webhook`.
+    pub async fn find_plugin_by_module_path(
+        &self,
+        module_path: &str,
+    ) -> Result<Option<PluginConf>> {
+        Ok(self
+            .get_plugins()
+            .await?
+            .into_iter()
+            .find(|plugin| plugin.module_path == module_path))
+    }
+    /// Enable the plugin with the given module path if it isn't enabled yet.
+    ///
+    /// Returns [`Error::PluginNotFound`] if no such plugin is installed.
+    pub async fn ensure_plugin_enabled(&self, module_path: &str) -> Result<PluginConf> {
+        let mut plugin = self
+            .find_plugin_by_module_path(module_path)
+            .await?
+            .ok_or_else(|| Error::PluginNotFound(module_path.to_owned()))?;
+
+        if !plugin.enabled {
+            self.enable_plugin(plugin.id).await?;
+            plugin.enabled = true;
+        }
+
+        Ok(plugin)
+    }
     /// Get YAML configuration for Configurer plugin.
-    pub async fn get_plugin_config(&self, id: i64) -> Result<String> {
+    ///
+    /// Returns [`Error::MissingCapability`] if the plugin isn't a Configurer.
+    pub async fn get_plugin_config(&self, id: PluginId) -> Result<String> {
+        self.require_plugin_capability(id, PluginCapability::Configurer)
+            .await?;
         self.request(
             Method::GET,
             ["plugin".into(), id.to_string(), "config".into()],
@@ -19,15 +56,50 @@ impl ClientClient {
         .send_and_read_string()
         .await
     }
+    /// Get YAML configuration for Configurer plugin and deserialize it.
+    pub async fn get_plugin_config_as<T: DeserializeOwned>(
+        &self,
+        id: PluginId,
+    ) -> core::result::Result<T, PluginConfigError> {
+        Ok(serde_yaml::from_str(&self.get_plugin_config(id).await?)?)
+    }
     /// Update YAML configuration for Configurer plugin.
-    pub async fn update_plugin_config(&self, config: String) -> Result<()> {
-        self.request(Method::GET, ["user"])
-            .with_string_body(config)
-            .send()
+    ///
+    /// Returns [`Error::MissingCapability`] if the plugin isn't a Configurer.
+    pub async fn update_plugin_config(
+        &self,
+        id: PluginId,
+        config: impl Into<String>,
+    ) -> Result<()> {
+        self.require_plugin_capability(id, PluginCapability::Configurer)
+            .await?;
+        self.request(
+            Method::POST,
+            ["plugin".into(), id.to_string(), "config".into()],
+        )
+        .with_yaml_body(config.into())
+        .send()
+        .await
+    }
+    /// Serialize a value as YAML and use it as configuration for Configurer plugin.
+    ///
+    /// If the plugin rejects the configuration, [`PluginConfigError::Invalid`] is returned.
+    pub async fn update_plugin_config_from<T: Serialize + ?Sized>(
+        &self,
+        id: PluginId,
+        config: &T,
+    ) -> core::result::Result<(), PluginConfigError> {
+        match self
+            .update_plugin_config(id, serde_yaml::to_string(config)?)
             .await
+        {
+            Ok(()) => Ok(()),
+            Err(Error::Response(e)) if e.error_code == 400 => Err(PluginConfigError::Invalid(e)),
+            Err(e) => Err(e.into()),
+        }
     }
     /// Disable a plugin.
-    pub async fn disable_plugin(&self, id: i64) -> Result<()> {
+    pub async fn disable_plugin(&self, id: PluginId) -> Result<()> {
         self.request(
             Method::POST,
             ["plugin".into(), id.to_string(), "disable".into()],
@@ -36,7 +108,11 @@ impl ClientClient {
         .await
     }
     /// Get display info for a Displayer plugin.
-    pub async fn get_plugin_display(&self, id: i64) -> Result<String> {
+    ///
+    /// Returns [`Error::MissingCapability`] if the plugin isn't a Displayer.
+    pub async fn get_plugin_display(&self, id: PluginId) -> Result<String> {
+        self.require_plugin_capability(id, PluginCapability::Displayer)
+            .await?;
         self.request(
             Method::GET,
             ["plugin".into(), id.to_string(), "display".into()],
@@ -45,7 +121,7 @@ impl ClientClient {
         .await
     }
     /// Enable a plugin.
-    pub async fn enable_plugin(&self, id: i64) -> Result<()> {
+    pub async fn enable_plugin(&self, id: PluginId) -> Result<()> {
         self.request(
             Method::POST,
             ["plugin".into(), id.to_string(), "enable".into()],
@@ -55,9 +131,133 @@ impl ClientClient {
     }
 }
 
+/// Access custom routes of Webhooker plugins.
+impl ClientClient {
+    /// Return a handle to send requests to the custom routes of a plugin.
+    pub fn plugin_handle(&self, plugin: &PluginConf) -> PluginHandle<'_> {
+        PluginHandle {
+            client: self,
+            id: plugin.id,
+            token: plugin.token.clone(),
+        }
+    }
+}
+
+/// A handle to the custom routes of a Webhooker plugin.
+///
+/// Gotify serves them below `/plugin/{id}/custom/{token}/`. Requests are sent
+/// with the HTTP client of the [`ClientClient`] this handle was created from.
+#[derive(Clone, Debug)]
+pub struct PluginHandle<'client> {
+    client: &'client ClientClient,
+    id: PluginId,
+    token: String,
+}
+
+impl<'client> PluginHandle<'client> {
+    fn segments(&self, route: impl IntoIterator<Item = impl AsRef<str>>) -> Vec<String> {
+        [
+            "plugin".into(),
+            self.id.to_string(),
+            "custom".into(),
+            self.token.clone(),
+        ]
+        .into_iter()
+        .chain(route.into_iter().map(|s| s.as_ref().to_owned()))
+        .collect()
+    }
+    /// Return the URL of a custom route, given as path segments.
+    pub fn url(&self, route: impl IntoIterator<Item = impl AsRef<str>>) -> Url {
+        self.client.base_url.append(self.segments(route))
+    }
+    /// Create a request to a custom route, given as path segments.
+    pub fn request(
+        &self,
+        method: Method,
+        route: impl IntoIterator<Item = impl AsRef<str>>,
+    ) -> PluginRequestBuilder {
+        PluginRequestBuilder(self.client.request(method, self.segments(route)))
+    }
+}
+
+/// Builder for a request to a custom plugin route.
+///
+/// Unsuccessful responses in Gotify's error format are turned into [`Error::Response`],
+/// any other unsuccessful response into [`Error::PluginResponse`].
+pub struct PluginRequestBuilder(RequestBuilder);
+
+#[allow(missing_docs)]
+impl PluginRequestBuilder {
+    pub fn with_header(self, name: &str, value: &str) -> Self {
+        Self(self.0.with_header(name, value))
+    }
+    pub fn with_query(self, params: impl Serialize) -> Self {
+        Self(self.0.with_query(params))
+    }
+    pub fn with_json_body(self, body: impl Serialize) -> Self {
+        Self(self.0.with_json_body(body))
+    }
+    pub fn with_body(self, body: impl Into<reqwest::Body>) -> Self {
+        Self(self.0.with_body(body))
+    }
+    pub async fn send(self) -> Result<()> {
+        self.0.send_to_plugin().await?;
+        Ok(())
+    }
+    pub async fn send_and_read_json<R: DeserializeOwned + 'static>(self) -> Result<R> {
+        Ok(self.0.send_to_plugin().await?.json().await?)
+    }
+    pub async fn send_and_read_string(self) -> Result<String> {
+        Ok(self.0.send_to_plugin().await?.text().await?)
+    }
+}
+
+impl std::fmt::Debug for PluginRequestBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("PluginRequestBuilder")
+            .field(&self.0 .0)
+            .finish()
+    }
+}
+
+impl ClientClient {
+    /// Fail early if a plugin is known to lack a capability.
+    ///
+    /// Unknown plugin ids are passed through, so the server can respond with its own error.
+    async fn require_plugin_capability(
+        &self,
+        id: PluginId,
+        capability: PluginCapability,
+    ) -> Result<()> {
+        match self.get_plugins().await?.into_iter().find(|p| p.id == id) {
+            Some(plugin) if !plugin.has_capability(&capability) => {
+                Err(Error::MissingCapability { id, capability })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Errors that can occur when reading or writing a typed plugin configuration.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum PluginConfigError {
+    #[error("failed to (de)serialize the plugin configuration")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("the plugin rejected the configuration: {0}")]
+    Invalid(crate::models::Error),
+    #[error(transparent)]
+    Request(#[from] Error),
+}
+
 #[cfg(test)]
 pub(crate) mod tests {
-    use crate::testsuite::*;
+    use crate::{
+        models::{PluginCapability, PluginId},
+        testsuite::*,
+    };
+
+    use super::PluginConfigError;
 
     #[apply(run_test_server!)]
     #[test]
@@ -68,4 +268,131 @@ pub(crate) mod tests {
 
         Ok(())
     }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn plugin_config_of_missing_plugin() -> eyre::Result<()> {
+        let client = client_client();
+
+        assert!(matches!(
+            client
+                .get_plugin_config_as::<serde_yaml::Value>(PluginId(1))
+                .await,
+            Err(PluginConfigError::Request(crate::Error::Response(
+                crate::models::Error {
+                    error_code: 404,
+                    ..
+                }
+            )))
+        ));
+        assert!(matches!(
+            client
+                .update_plugin_config_from(PluginId(1), &serde_yaml::Mapping::new())
+                .await,
+            Err(PluginConfigError::Request(crate::Error::Response(
+                crate::models::Error {
+                    error_code: 404,
+                    ..
+                }
+            )))
+        ));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn find_plugin_by_module_path() -> eyre::Result<()> {
+        let client = client_client();
+
+        assert!(client
+            .find_plugin_by_module_path("github.com/gotify/plugin-webhook")
+            .await?
+            .is_none());
+        assert!(matches!(
+            client
+                .ensure_plugin_enabled("github.com/gotify/plugin-webhook")
+                .await,
+            Err(crate::Error::PluginNotFound(path)) if path == "github.com/gotify/plugin-webhook"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn plugin_capabilities() {
+        let capabilities: Vec<PluginCapability> =
+            serde_json::from_str(r#"["webhooker", "displayer", "teleporter"]"#).unwrap();
+
+        assert_eq!(
+            capabilities,
+            vec![
+                PluginCapability::Webhooker,
+                PluginCapability::Displayer,
+                PluginCapability::Unknown("teleporter".into())
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&capabilities).unwrap(),
+            r#"["webhooker","displayer","teleporter"]"#
+        );
+    }
+
+    fn webhook_plugin() -> crate::models::PluginConf {
+        serde_json::from_value(serde_json::json!({
+            "author": null,
+            "capabilities": ["webhooker"],
+            "enabled": true,
+            "id": 3,
+            "license": null,
+            "modulePath": "github.com/gotify/plugin-webhook",
+            "name": "webhook",
+            "token": "Pabc123",
+            "website": null,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn plugin_handle_url() -> eyre::Result<()> {
+        let client = crate::ClientClient::new(GOTIFY_URL, GOTIFY_CLIENT_TOKEN)?;
+        let plugin = webhook_plugin();
+
+        assert_eq!(
+            client
+                .plugin_handle(&plugin)
+                .url(["hook", "deploy"])
+                .as_str(),
+            "http://localhost:30080/plugin/3/custom/Pabc123/hook/deploy"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plugin_error_responses() -> eyre::Result<()> {
+        let (url, _) = http_stand_in(vec![
+            (502, "upstream unavailable"),
+            (
+                400,
+                r#"{"error": "Bad Request", "errorCode": 400, "errorDescription": "invalid"}"#,
+            ),
+        ])
+        .await;
+        let client = crate::ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?;
+        let plugin = webhook_plugin();
+        let handle = client.plugin_handle(&plugin);
+
+        assert!(matches!(
+            handle.request(reqwest::Method::POST, ["hook"]).send().await,
+            Err(crate::Error::PluginResponse { status, body })
+                if status == reqwest::StatusCode::BAD_GATEWAY && body == "upstream unavailable"
+        ));
+        assert!(matches!(
+            handle.request(reqwest::Method::POST, ["hook"]).send().await,
+            Err(crate::Error::Response(e)) if e.error_code == 400
+        ));
+
+        Ok(())
+    }
 }
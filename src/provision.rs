@@ -0,0 +1,757 @@
+//! Declarative provisioning of users, applications, clients and plugins.
+//!
+//! A [`Spec`] describes the desired state of a server and can be read from
+//! TOML, YAML or JSON. [`ClientClient::plan_provisioning()`] compares it
+//! against the server and returns a [`Plan`], which can be reviewed and then
+//! applied with [`ClientClient::apply_provisioning()`]. Applying the same
+//! spec again results in an empty plan.
+//!
+//! ```toml
+//! [[applications]]
+//! name = "backup"
+//! description = "nightly backups"
+//! default_priority = 6
+//! image = "icons/backup.png"
+//!
+//! [[clients]]
+//! name = "dashboard"
+//!
+//! [[plugins]]
+//! module_path = "github.com/gotify/plugin-webhook"
+//! enabled = true
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{ApplicationId, PluginId, UserId},
+    utils, ClientClient, Error, PluginConfigError,
+};
+
+/// The desired state of a Gotify server.
+///
+/// Entities are matched by name (or module path for plugins). Entities that
+/// exist on the server but aren't mentioned in the spec are left untouched.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Spec {
+    /// Users that must exist.
+    #[serde(default)]
+    pub users: Vec<UserSpec>,
+    /// Applications that must exist.
+    #[serde(default)]
+    pub applications: Vec<ApplicationSpec>,
+    /// Clients that must exist.
+    #[serde(default)]
+    pub clients: Vec<ClientSpec>,
+    /// Installed plugins that must be configured.
+    #[serde(default)]
+    pub plugins: Vec<PluginSpec>,
+}
+
+/// The desired state of a user.
+#[derive(Clone, Deserialize, Serialize)]
+pub struct UserSpec {
+    /// The user's name.
+    pub name: String,
+    /// The user's password. Only used when the user is created.
+    pub pass: String,
+    /// Whether the user is an administrator.
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl std::fmt::Debug for UserSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserSpec")
+            .field("name", &self.name)
+            .field("pass", &"<redacted>")
+            .field("admin", &self.admin)
+            .finish()
+    }
+}
+
+/// The desired state of an application.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ApplicationSpec {
+    /// The application's name.
+    pub name: String,
+    /// The application's description, left unchanged if not given.
+    pub description: Option<String>,
+    /// The application's default priority, left unchanged if not given.
+    pub default_priority: Option<u8>,
+    /// Path to an image file.
+    ///
+    /// Relative paths are resolved against the directory of the spec file if
+    /// it was read by [`Spec::from_path()`] and against the working directory
+    /// otherwise.
+    pub image: Option<PathBuf>,
+}
+
+/// The desired state of a client.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ClientSpec {
+    /// The client's name.
+    pub name: String,
+}
+
+/// The desired state of a plugin.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PluginSpec {
+    /// The plugin's module path, e.g. `github.com/gotify/plugin-webhook`.
+    pub module_path: String,
+    /// Whether the plugin should be enabled, left unchanged if not given.
+    pub enabled: Option<bool>,
+    /// The configuration of a Configurer plugin, left unchanged if not given.
+    pub config: Option<serde_yaml::Value>,
+}
+
+impl Spec {
+    /// Parse a spec from TOML.
+    pub fn from_toml_str(s: &str) -> Result<Self, ProvisionError> {
+        Ok(toml::from_str(s)?)
+    }
+    /// Parse a spec from YAML.
+    pub fn from_yaml_str(s: &str) -> Result<Self, ProvisionError> {
+        Ok(serde_yaml::from_str(s)?)
+    }
+    /// Parse a spec from JSON.
+    pub fn from_json_str(s: &str) -> Result<Self, ProvisionError> {
+        Ok(serde_json::from_str(s)?)
+    }
+    /// Read a spec from a file, choosing the format by its extension
+    /// (`.toml`, `.yaml`, `.yml` or `.json`).
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, ProvisionError> {
+        let path = path.as_ref();
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ProvisionError::Io(path.to_owned(), e))?;
+
+        let mut spec = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&content),
+            Some("yaml" | "yml") => Self::from_yaml_str(&content),
+            Some("json") => Self::from_json_str(&content),
+            _ => Err(ProvisionError::UnsupportedFormat(path.to_owned())),
+        }?;
+
+        if let Some(dir) = path.parent() {
+            for image in spec
+                .applications
+                .iter_mut()
+                .filter_map(|application| application.image.as_mut())
+            {
+                if image.is_relative() {
+                    *image = dir.join(&*image);
+                }
+            }
+        }
+        Ok(spec)
+    }
+}
+
+/// The changes required to bring a server into the state described by a [`Spec`].
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+pub struct Plan {
+    /// The actions in the order they are applied.
+    pub actions: Vec<Action>,
+}
+
+impl Plan {
+    /// Check whether the server already matches the spec.
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+}
+
+/// A single change of a [`Plan`].
+#[allow(missing_docs)]
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum Action {
+    CreateUser {
+        name: String,
+        pass: String,
+        admin: bool,
+    },
+    UpdateUser {
+        id: UserId,
+        name: String,
+        admin: bool,
+    },
+    CreateApplication {
+        name: String,
+        description: Option<String>,
+        default_priority: Option<u8>,
+        image: Option<Image>,
+    },
+    UpdateApplication {
+        id: ApplicationId,
+        name: String,
+        description: String,
+        default_priority: Option<u8>,
+    },
+    UploadApplicationImage {
+        id: ApplicationId,
+        name: String,
+        image: Image,
+    },
+    CreateClient {
+        name: String,
+    },
+    EnablePlugin {
+        id: PluginId,
+        module_path: String,
+    },
+    DisablePlugin {
+        id: PluginId,
+        module_path: String,
+    },
+    UpdatePluginConfig {
+        id: PluginId,
+        module_path: String,
+        config: serde_yaml::Value,
+    },
+}
+
+impl std::fmt::Debug for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::CreateUser { name, admin, .. } => f
+                .debug_struct("CreateUser")
+                .field("name", name)
+                .field("pass", &"<redacted>")
+                .field("admin", admin)
+                .finish(),
+            Action::UpdateUser { id, name, admin } => f
+                .debug_struct("UpdateUser")
+                .field("id", id)
+                .field("name", name)
+                .field("admin", admin)
+                .finish(),
+            Action::CreateApplication {
+                name,
+                description,
+                default_priority,
+                image,
+            } => f
+                .debug_struct("CreateApplication")
+                .field("name", name)
+                .field("description", description)
+                .field("default_priority", default_priority)
+                .field("image", image)
+                .finish(),
+            Action::UpdateApplication {
+                id,
+                name,
+                description,
+                default_priority,
+            } => f
+                .debug_struct("UpdateApplication")
+                .field("id", id)
+                .field("name", name)
+                .field("description", description)
+                .field("default_priority", default_priority)
+                .finish(),
+            Action::UploadApplicationImage { id, name, image } => f
+                .debug_struct("UploadApplicationImage")
+                .field("id", id)
+                .field("name", name)
+                .field("image", image)
+                .finish(),
+            Action::CreateClient { name } => {
+                f.debug_struct("CreateClient").field("name", name).finish()
+            }
+            Action::EnablePlugin { id, module_path } => f
+                .debug_struct("EnablePlugin")
+                .field("id", id)
+                .field("module_path", module_path)
+                .finish(),
+            Action::DisablePlugin { id, module_path } => f
+                .debug_struct("DisablePlugin")
+                .field("id", id)
+                .field("module_path", module_path)
+                .finish(),
+            Action::UpdatePluginConfig {
+                id,
+                module_path,
+                config,
+            } => f
+                .debug_struct("UpdatePluginConfig")
+                .field("id", id)
+                .field("module_path", module_path)
+                .field("config", config)
+                .finish(),
+        }
+    }
+}
+
+/// An image file read while planning.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct Image {
+    /// The path the image was read from.
+    pub path: PathBuf,
+    /// The content of the file.
+    pub content: Vec<u8>,
+}
+
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.actions.is_empty() {
+            return writeln!(f, "No changes.");
+        }
+        for action in &self.actions {
+            writeln!(f, "{action}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Action::CreateUser { name, admin, .. } => {
+                write!(f, "+ create user {name:?} (admin: {admin})")
+            }
+            Action::UpdateUser { id, name, admin } => {
+                write!(f, "~ update user {name:?} ({id}): set admin to {admin}")
+            }
+            Action::CreateApplication { name, image, .. } => {
+                write!(f, "+ create application {name:?}")?;
+                if let Some(image) = image {
+                    write!(f, " with image {}", image.path.display())?;
+                }
+                Ok(())
+            }
+            Action::UpdateApplication { id, name, .. } => {
+                write!(f, "~ update application {name:?} ({id})")
+            }
+            Action::UploadApplicationImage { id, name, image } => write!(
+                f,
+                "~ upload image {} for application {name:?} ({id})",
+                image.path.display()
+            ),
+            Action::CreateClient { name } => write!(f, "+ create client {name:?}"),
+            Action::EnablePlugin { id, module_path } => {
+                write!(f, "~ enable plugin {module_path} ({id})")
+            }
+            Action::DisablePlugin { id, module_path } => {
+                write!(f, "~ disable plugin {module_path} ({id})")
+            }
+            Action::UpdatePluginConfig {
+                id, module_path, ..
+            } => write!(f, "~ update configuration of plugin {module_path} ({id})"),
+        }
+    }
+}
+
+/// The result of applying a [`Plan`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct ApplyReport {
+    /// Tokens of newly created applications, by name.
+    #[serde(default)]
+    pub application_tokens: BTreeMap<String, String>,
+    /// Tokens of newly created clients, by name.
+    #[serde(default)]
+    pub client_tokens: BTreeMap<String, String>,
+}
+
+impl ApplyReport {
+    /// Merge the newly minted tokens into a TOML secrets file, creating it if necessary.
+    ///
+    /// Tokens already stored in the file are kept unless they were replaced.
+    /// The file is replaced atomically, so a crash never leaves a partially
+    /// written file behind. On unix, it is only readable and writable by its owner.
+    pub fn write_secrets(&self, path: impl AsRef<Path>) -> Result<(), ProvisionError> {
+        let path = path.as_ref();
+
+        let mut secrets = match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str::<ApplyReport>(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ApplyReport::default(),
+            Err(e) => return Err(ProvisionError::Io(path.to_owned(), e)),
+        };
+        secrets
+            .application_tokens
+            .extend(self.application_tokens.clone());
+        secrets.client_tokens.extend(self.client_tokens.clone());
+
+        let content = toml::to_string(&secrets)?;
+        utils::write_private_atomically(path, content.as_bytes())
+            .map_err(|e| ProvisionError::Io(path.to_owned(), e))
+    }
+}
+
+/// Provision the server from a declarative spec.
+impl ClientClient {
+    /// Compare a spec against the current state of the server.
+    pub async fn plan_provisioning(&self, spec: &Spec) -> Result<Plan, ProvisionError> {
+        let mut actions = Vec::new();
+
+        let users = self.get_users().await?;
+        for user in &spec.users {
+            match users.iter().find(|u| u.name == user.name) {
+                None => actions.push(Action::CreateUser {
+                    name: user.name.clone(),
+                    pass: user.pass.clone(),
+                    admin: user.admin,
+                }),
+                Some(existing) if existing.admin != user.admin => {
+                    actions.push(Action::UpdateUser {
+                        id: existing.id,
+                        name: user.name.clone(),
+                        admin: user.admin,
+                    })
+                }
+                Some(_) => (),
+            }
+        }
+
+        let applications = self.get_applications().await?;
+        for application in &spec.applications {
+            let image = application
+                .image
+                .as_ref()
+                .map(|path| {
+                    std::fs::read(path)
+                        .map(|content| Image {
+                            path: path.clone(),
+                            content,
+                        })
+                        .map_err(|e| ProvisionError::Io(path.clone(), e))
+                })
+                .transpose()?;
+
+            let Some(existing) = applications.iter().find(|a| a.name == application.name) else {
+                actions.push(Action::CreateApplication {
+                    name: application.name.clone(),
+                    description: application.description.clone(),
+                    default_priority: application.default_priority,
+                    image,
+                });
+                continue;
+            };
+
+            let description = application
+                .description
+                .clone()
+                .unwrap_or_else(|| existing.description.clone());
+            let default_priority = application.default_priority.or(existing.default_priority);
+
+            if description != existing.description || default_priority != existing.default_priority
+            {
+                actions.push(Action::UpdateApplication {
+                    id: existing.id,
+                    name: existing.name.clone(),
+                    description,
+                    default_priority,
+                });
+            }
+
+            if let Some(image) = image {
+                let is_current = existing.image.starts_with("image/")
+                    && self
+                        .get_application_image(existing)
+                        .await
+                        .is_ok_and(|current| current.content == image.content);
+
+                if !is_current {
+                    actions.push(Action::UploadApplicationImage {
+                        id: existing.id,
+                        name: existing.name.clone(),
+                        image,
+                    });
+                }
+            }
+        }
+
+        let clients = self.get_clients().await?;
+        for client in &spec.clients {
+            if !clients.iter().any(|c| c.name == client.name) {
+                actions.push(Action::CreateClient {
+                    name: client.name.clone(),
+                });
+            }
+        }
+
+        let plugins = self.get_plugins().await?;
+        for plugin in &spec.plugins {
+            let existing = plugins
+                .iter()
+                .find(|p| p.module_path == plugin.module_path)
+                .ok_or_else(|| Error::PluginNotFound(plugin.module_path.clone()))?;
+
+            match plugin.enabled {
+                Some(true) if !existing.enabled => actions.push(Action::EnablePlugin {
+                    id: existing.id,
+                    module_path: existing.module_path.clone(),
+                }),
+                Some(false) if existing.enabled => actions.push(Action::DisablePlugin {
+                    id: existing.id,
+                    module_path: existing.module_path.clone(),
+                }),
+                _ => (),
+            }
+
+            if let Some(config) = &plugin.config {
+                let current = self
+                    .get_plugin_config_as::<serde_yaml::Value>(existing.id)
+                    .await?;
+                if &current != config {
+                    actions.push(Action::UpdatePluginConfig {
+                        id: existing.id,
+                        module_path: existing.module_path.clone(),
+                        config: config.clone(),
+                    });
+                }
+            }
+        }
+
+        Ok(Plan { actions })
+    }
+
+    /// Apply a plan created by [`ClientClient::plan_provisioning()`].
+    ///
+    /// Stops at the first action that fails. The returned [`ApplyError`]
+    /// contains the tokens minted until then, which should be stored as well:
+    /// planning again won't create those applications and clients a second time.
+    pub async fn apply_provisioning(&self, plan: &Plan) -> Result<ApplyReport, ApplyError> {
+        let mut report = ApplyReport::default();
+        match self.apply_actions(plan, &mut report).await {
+            Ok(()) => Ok(report),
+            Err(source) => Err(ApplyError { report, source }),
+        }
+    }
+
+    async fn apply_actions(
+        &self,
+        plan: &Plan,
+        report: &mut ApplyReport,
+    ) -> Result<(), ProvisionError> {
+        for action in &plan.actions {
+            match action {
+                Action::CreateUser { name, pass, admin } => {
+                    self.create_user(*admin, name, pass).await?;
+                }
+                Action::UpdateUser { id, name, admin } => {
+                    self.update_user(*id, *admin, name).await?;
+                }
+                Action::CreateApplication {
+                    name,
+                    description,
+                    default_priority,
+                    image,
+                } => {
+                    let mut builder = self.create_application(name);
+                    if let Some(description) = description {
+                        builder = builder.with_description(description);
+                    }
+                    if let Some(default_priority) = default_priority {
+                        builder = builder.with_default_priority(*default_priority);
+                    }
+                    let application = builder.await?;
+                    report
+                        .application_tokens
+                        .insert(application.name, application.token);
+
+                    if let Some(image) = image {
+                        self.upload_application_image(
+                            application.id,
+                            image_file_name(&image.path),
+                            image.content.clone(),
+                        )
+                        .await?;
+                    }
+                }
+                Action::UpdateApplication {
+                    id,
+                    name,
+                    description,
+                    default_priority,
+                } => {
+                    let mut builder = self
+                        .update_application(*id, name)
+                        .with_description(description);
+                    if let Some(default_priority) = default_priority {
+                        builder = builder.with_default_priority(*default_priority);
+                    }
+                    builder.await?;
+                }
+                Action::UploadApplicationImage { id, image, .. } => {
+                    self.upload_application_image(
+                        *id,
+                        image_file_name(&image.path),
+                        image.content.clone(),
+                    )
+                    .await?;
+                }
+                Action::CreateClient { name } => {
+                    let client = self.create_client(name).await?;
+                    report.client_tokens.insert(client.name, client.token);
+                }
+                Action::EnablePlugin { id, .. } => self.enable_plugin(*id).await?,
+                Action::DisablePlugin { id, .. } => self.disable_plugin(*id).await?,
+                Action::UpdatePluginConfig { id, config, .. } => {
+                    self.update_plugin_config_from(*id, config).await?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn image_file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "image.png".into())
+}
+
+/// Errors that can occur when planning or applying a provisioning spec.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum ProvisionError {
+    #[error("failed to access {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("unsupported file format: {0}")]
+    UnsupportedFormat(PathBuf),
+    #[error("failed to parse TOML")]
+    TomlDe(#[from] toml::de::Error),
+    #[error("failed to serialize TOML")]
+    TomlSer(#[from] toml::ser::Error),
+    #[error("failed to parse YAML")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("failed to parse JSON")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    PluginConfig(#[from] PluginConfigError),
+    #[error(transparent)]
+    Request(#[from] Error),
+}
+
+/// A failed [`ClientClient::apply_provisioning()`], with the tokens minted before the failure.
+#[derive(Debug, thiserror::Error)]
+#[error("applying the plan stopped early")]
+#[non_exhaustive]
+pub struct ApplyError {
+    /// The tokens of the applications and clients created before the failure.
+    pub report: ApplyReport,
+    /// The reason applying the plan stopped.
+    #[source]
+    pub source: ProvisionError,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testsuite::*;
+
+    use super::{Action, ApplyReport, Spec};
+
+    const SPEC: &str = r#"
+        [[users]]
+        name = "ops"
+        pass = "secret"
+
+        [[applications]]
+        name = "backup"
+        description = "nightly backups"
+        default_priority = 6
+        image = "tests/img.png"
+
+        [[applications]]
+        name = "App0"
+        description = "changed description"
+
+        [[clients]]
+        name = "dashboard"
+    "#;
+
+    #[test]
+    fn parse_spec() -> eyre::Result<()> {
+        let spec = Spec::from_toml_str(SPEC)?;
+        assert_eq!(spec.users[0].name, "ops");
+        assert!(!spec.users[0].admin);
+        assert_eq!(spec.applications[0].default_priority, Some(6));
+        assert_eq!(spec.clients[0].name, "dashboard");
+        assert!(spec.plugins.is_empty());
+
+        let spec = Spec::from_yaml_str("clients:\n  - name: dashboard\n")?;
+        assert_eq!(spec.clients[0].name, "dashboard");
+
+        Ok(())
+    }
+
+    #[test]
+    fn spec_file() -> eyre::Result<()> {
+        let dir = temp_path("provision");
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("spec.toml"), SPEC)?;
+
+        let spec = Spec::from_path(dir.join("spec.toml"))?;
+        assert_eq!(
+            spec.applications[0].image.as_deref(),
+            Some(dir.join("tests/img.png").as_path())
+        );
+        assert!(!format!("{spec:?}").contains("secret"));
+
+        let secrets = dir.join("secrets.toml");
+        std::fs::write(&secrets, "[client_tokens]\ndashboard = \"Cabc\"\n")?;
+        let mut report = ApplyReport::default();
+        report
+            .application_tokens
+            .insert("backup".into(), "Aabc".into());
+        report.write_secrets(&secrets)?;
+
+        let written = std::fs::read_to_string(&secrets)?;
+        assert!(written.contains("dashboard = \"Cabc\""));
+        assert!(written.contains("backup = \"Aabc\""));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = std::fs::metadata(&secrets)?.permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn plan_and_apply() -> eyre::Result<()> {
+        let client = client_client();
+        let spec = Spec::from_toml_str(SPEC)?;
+
+        let plan = client.plan_provisioning(&spec).await?;
+        assert_eq!(plan.actions.len(), 4);
+        assert!(matches!(&plan.actions[0], Action::CreateUser { name, .. } if name == "ops"));
+        assert!(
+            matches!(&plan.actions[1], Action::CreateApplication { name, image: Some(_), .. } if name == "backup")
+        );
+        assert!(
+            matches!(&plan.actions[2], Action::UpdateApplication { description, .. } if description == "changed description")
+        );
+        assert!(matches!(&plan.actions[3], Action::CreateClient { name } if name == "dashboard"));
+        assert!(plan
+            .to_string()
+            .contains("+ create application \"backup\" with image tests/img.png"));
+
+        let report = client.apply_provisioning(&plan).await?;
+        assert!(report.application_tokens.contains_key("backup"));
+        assert!(report.client_tokens.contains_key("dashboard"));
+
+        let secrets = temp_path("provision-secrets.toml");
+        report.write_secrets(&secrets)?;
+        assert!(std::fs::read_to_string(&secrets)?.contains("[application_tokens]"));
+
+        let plan = client.plan_provisioning(&spec).await?;
+        assert!(plan.is_empty(), "{plan}");
+
+        Ok(())
+    }
+}
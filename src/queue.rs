@@ -0,0 +1,563 @@
+//! Use an application as a work queue.
+//!
+//! A producer creates messages in an application, a [`Consumer`] hands every
+//! message to a handler and deletes it after the handler succeeded. Messages
+//! whose handler keeps failing can be moved to a dead-letter application.
+//!
+//! ```no_run
+//! # async fn consume() -> Result<(), Box<dyn std::error::Error>> {
+//! use gotify::models::ApplicationId;
+//!
+//! let client: gotify::ClientClient = gotify::Client::new("https://gotify.example.com", "client-token")?;
+//! client
+//!     .consume(ApplicationId(4))
+//!     .with_workers(4)
+//!     .with_dead_letter(ApplicationId(5))
+//!     .run(|job| async move {
+//!         println!("processing {}", job.message);
+//!         Ok::<_, std::io::Error>(())
+//!     })
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Within one consumer, every message is handed to exactly one worker. Gotify
+//! can't claim messages atomically, so multiple consumers of the same
+//! application in different processes may process a message twice. A message
+//! is only deleted after its handler succeeded, so a crash in between leads to
+//! the message being processed again on the next start.
+
+use std::{
+    future::Future,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use futures_util::{StreamExt, TryStreamExt};
+
+use crate::{
+    models::{ApplicationId, Message, MessageId},
+    AppClient, ClientClient, Error, WebsocketConnectError, WebsocketError,
+};
+
+/// The extras key added to messages moved to the dead-letter application.
+pub const DEAD_LETTER_EXTRAS_KEY: &str = "gotify-rs::dead_letter";
+
+/// Errors that stop a [`Consumer`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum ConsumerError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("the dead-letter application {0} doesn't exist")]
+    UnknownDeadLetterApplication(ApplicationId),
+    #[error("failed to connect to the message stream")]
+    Connect(#[source] Box<WebsocketConnectError>),
+    #[error("the message stream failed")]
+    Stream(#[source] Box<WebsocketError>),
+}
+
+impl From<WebsocketConnectError> for ConsumerError {
+    fn from(e: WebsocketConnectError) -> Self {
+        Self::Connect(Box::new(e))
+    }
+}
+impl From<WebsocketError> for ConsumerError {
+    fn from(e: WebsocketError) -> Self {
+        Self::Stream(Box::new(e))
+    }
+}
+
+/// Statistics of a finished [`Consumer`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ConsumerSummary {
+    /// Messages that were handled successfully and deleted.
+    pub processed: usize,
+    /// Messages that failed every attempt and were left in the queue.
+    pub failed: usize,
+    /// Messages that failed every attempt and were moved to the dead-letter application.
+    pub dead_lettered: usize,
+    /// Messages that couldn't be deleted or moved to the dead-letter application after every attempt.
+    ///
+    /// They are left in the queue and handled again by the next consumer.
+    pub unacknowledged: usize,
+}
+
+impl ClientClient {
+    /// Consume the messages of an application.
+    pub fn consume(&self, application: ApplicationId) -> Consumer<'_> {
+        Consumer {
+            client: self,
+            application,
+            workers: 1,
+            max_attempts: Consumer::DEFAULT_MAX_ATTEMPTS,
+            retry_delay: Consumer::DEFAULT_RETRY_DELAY,
+            dead_letter: None,
+        }
+    }
+}
+
+/// Hands the messages of an application to a handler and deletes them afterwards.
+#[derive(Debug)]
+pub struct Consumer<'client> {
+    client: &'client ClientClient,
+    application: ApplicationId,
+    workers: usize,
+    max_attempts: u32,
+    retry_delay: Duration,
+    dead_letter: Option<ApplicationId>,
+}
+
+impl<'client> Consumer<'client> {
+    /// The number of attempts per message by default.
+    pub const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+    /// The delay between attempts by default.
+    pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+    /// Handle up to `workers` messages concurrently (default: 1).
+    pub fn with_workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+    /// Give up on a message after `max_attempts` failed attempts.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+    /// Wait before retrying a failed message.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+    /// Move messages that failed every attempt to another application instead of leaving them in the queue.
+    ///
+    /// The moved message gets [`DEAD_LETTER_EXTRAS_KEY`] extras with the
+    /// original application and message id, the number of attempts and the
+    /// last error. A message that was moved before but couldn't be deleted
+    /// isn't moved again.
+    pub fn with_dead_letter(mut self, application: ApplicationId) -> Self {
+        self.dead_letter = Some(application);
+        self
+    }
+
+    /// Handle all messages currently in the queue and return.
+    pub async fn drain<F, Fut, E>(self, handler: F) -> Result<ConsumerSummary, ConsumerError>
+    where
+        F: Fn(Message) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        let dead_letter = self.dead_letter_client().await?;
+        let (pending, _) = self.pending().await?;
+
+        self.process(pending, dead_letter.as_ref(), &handler).await
+    }
+
+    /// Handle all messages in the queue and then every new message until the message stream ends.
+    pub async fn run<F, Fut, E>(self, handler: F) -> Result<ConsumerSummary, ConsumerError>
+    where
+        F: Fn(Message) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        let dead_letter = self.dead_letter_client().await?;
+
+        // subscribe before paging, so no message is missed in between
+        let live = self.client.stream_messages().await?;
+        let (pending, newest_pending) = self.pending().await?;
+
+        let application = self.application;
+        let live = live
+            .map_err(ConsumerError::from)
+            .try_filter(move |message| {
+                let new = message.appid == application
+                    && newest_pending.is_none_or(|newest| message.id > newest);
+                std::future::ready(new)
+            });
+
+        self.process(pending.chain(live), dead_letter.as_ref(), &handler)
+            .await
+    }
+
+    /// Stream all messages in the queue, oldest first, and return the id of the newest one.
+    ///
+    /// Gotify returns the newest messages first, so only the bounds of every
+    /// page are collected, and the pages are fetched again in reverse order.
+    async fn pending(
+        &self,
+    ) -> Result<
+        (
+            impl futures_util::Stream<Item = Result<Message, ConsumerError>> + '_,
+            Option<MessageId>,
+        ),
+        Error,
+    > {
+        let mut bounds = Vec::new();
+        let mut pages = std::pin::pin!(self.client.message_pages(Some(self.application), None));
+        while let Some(page) = pages.try_next().await? {
+            if let (Some(newest), Some(oldest)) = (page.messages.first(), page.messages.last()) {
+                bounds.push((newest.id, oldest.id));
+            }
+        }
+        let newest_pending = bounds.first().map(|(newest, _)| *newest);
+
+        let pending = futures_util::stream::iter(bounds.into_iter().rev())
+            .then(move |(newest, oldest)| async move {
+                // messages deleted meanwhile leave room for older ones, which were already handled
+                let page = self
+                    .client
+                    .get_application_messages(self.application)
+                    .with_limit(200)
+                    .with_since(MessageId(newest.0 + 1))
+                    .await?;
+                let messages = page.messages.into_iter().rev();
+                Ok::<_, ConsumerError>(futures_util::stream::iter(
+                    messages.filter(move |m| m.id >= oldest).map(Ok),
+                ))
+            })
+            .try_flatten();
+        Ok((pending, newest_pending))
+    }
+
+    async fn dead_letter_client(&self) -> Result<Option<AppClient>, ConsumerError> {
+        let Some(id) = self.dead_letter else {
+            return Ok(None);
+        };
+        let application = self
+            .client
+            .get_applications()
+            .await?
+            .into_iter()
+            .find(|application| application.id == id)
+            .ok_or(ConsumerError::UnknownDeadLetterApplication(id))?;
+
+        Ok(Some(
+            self.client
+                .with_access_token(application.token.as_str())
+                .map_err(Error::from)?,
+        ))
+    }
+
+    async fn process<F, Fut, E>(
+        &self,
+        messages: impl futures_util::Stream<Item = Result<Message, ConsumerError>>,
+        dead_letter: Option<&AppClient>,
+        handler: &F,
+    ) -> Result<ConsumerSummary, ConsumerError>
+    where
+        F: Fn(Message) -> Fut,
+        Fut: Future<Output = Result<(), E>>,
+        E: std::fmt::Display,
+    {
+        let processed = AtomicUsize::new(0);
+        let failed = AtomicUsize::new(0);
+        let dead_lettered = AtomicUsize::new(0);
+        let unacknowledged = AtomicUsize::new(0);
+
+        messages
+            .try_for_each_concurrent(self.workers, |message| {
+                let (processed, failed, dead_lettered, unacknowledged) =
+                    (&processed, &failed, &dead_lettered, &unacknowledged);
+                async move {
+                    let mut attempts = 0;
+                    let error = loop {
+                        attempts += 1;
+                        match handler(message.clone()).await {
+                            Ok(()) => break None,
+                            Err(e) if attempts >= self.max_attempts => break Some(e.to_string()),
+                            Err(_) => tokio::time::sleep(self.retry_delay).await,
+                        }
+                    };
+
+                    let (acknowledged, counter) = match (error, dead_letter) {
+                        (None, _) => (
+                            self.acknowledge(|| self.delete(message.id)).await,
+                            processed,
+                        ),
+                        (Some(error), Some(dead_letter)) => {
+                            let moved = self
+                                .acknowledge(|| async {
+                                    // a previous consumer may have moved it but failed to delete it
+                                    if !self.is_dead_lettered(&message).await? {
+                                        move_to_dead_letter(
+                                            dead_letter,
+                                            &message,
+                                            attempts,
+                                            &error,
+                                        )
+                                        .await?;
+                                    }
+                                    Ok(())
+                                })
+                                .await;
+                            let acknowledged = match moved {
+                                Ok(()) => self.acknowledge(|| self.delete(message.id)).await,
+                                Err(e) => Err(e),
+                            };
+                            (acknowledged, dead_lettered)
+                        }
+                        (Some(_), None) => (Ok(()), failed),
+                    };
+                    match acknowledged {
+                        Ok(()) => counter.fetch_add(1, Ordering::Relaxed),
+                        Err(_) => unacknowledged.fetch_add(1, Ordering::Relaxed),
+                    };
+                    Ok(())
+                }
+            })
+            .await?;
+
+        Ok(ConsumerSummary {
+            processed: processed.into_inner(),
+            failed: failed.into_inner(),
+            dead_lettered: dead_lettered.into_inner(),
+            unacknowledged: unacknowledged.into_inner(),
+        })
+    }
+
+    /// Try to delete or move a handled message up to `max_attempts` times.
+    async fn acknowledge<Fut>(&self, operation: impl Fn() -> Fut) -> Result<(), Error>
+    where
+        Fut: Future<Output = Result<(), Error>>,
+    {
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            match operation().await {
+                Ok(()) => return Ok(()),
+                Err(e) if attempts >= self.max_attempts => return Err(e),
+                Err(_) => tokio::time::sleep(self.retry_delay).await,
+            }
+        }
+    }
+
+    /// Return whether a message was already moved to the dead-letter application.
+    async fn is_dead_lettered(&self, message: &Message) -> Result<bool, Error> {
+        let Some(dead_letter) = self.dead_letter else {
+            return Ok(false);
+        };
+        let original = serde_json::json!(message.id);
+        let mut pages = std::pin::pin!(self.client.message_pages(Some(dead_letter), None));
+        while let Some(page) = pages.try_next().await? {
+            for moved in &page.messages {
+                // moved messages are newer than their original
+                if moved.id < message.id {
+                    return Ok(false);
+                }
+                let moved_from = moved
+                    .extras
+                    .as_ref()
+                    .and_then(|extras| extras.get(DEAD_LETTER_EXTRAS_KEY))
+                    .and_then(|dead_letter| dead_letter.get("message"));
+                if moved_from == Some(&original) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Delete a message, ignoring messages that were already deleted by someone else.
+    async fn delete(&self, id: MessageId) -> Result<(), Error> {
+        match self.client.delete_message(id).await {
+            Err(Error::Response(e)) if e.error_code == 404 => Ok(()),
+            result => result,
+        }
+    }
+}
+
+async fn move_to_dead_letter(
+    dead_letter: &AppClient,
+    message: &Message,
+    attempts: u32,
+    error: &str,
+) -> Result<(), Error> {
+    let mut extras = message.extras.clone().unwrap_or_default();
+    extras.insert(
+        DEAD_LETTER_EXTRAS_KEY.into(),
+        serde_json::json!({
+            "application": message.appid,
+            "message": message.id,
+            "attempts": attempts,
+            "error": error,
+        }),
+    );
+
+    let mut builder = dead_letter
+        .create_message(&message.message)
+        .with_priority(message.priority)
+        .with_extras(extras);
+    if let Some(title) = &message.title {
+        builder = builder.with_title(title);
+    }
+    builder.await.map(drop)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use crate::{models::ApplicationId, testsuite::*, ClientClient};
+
+    use super::{ConsumerSummary, DEAD_LETTER_EXTRAS_KEY};
+
+    #[tokio::test]
+    async fn failed_deletions_are_counted() -> eyre::Result<()> {
+        const PAGE: &str = r#"{
+            "messages": [
+                {"id": 2, "appid": 4, "date": "2023-08-01T12:00:00Z", "message": "job2", "priority": 0},
+                {"id": 1, "appid": 4, "date": "2023-08-01T12:00:00Z", "message": "job1", "priority": 0}
+            ],
+            "paging": {"limit": 200, "since": 0, "size": 2}
+        }"#;
+        const ERROR: &str =
+            r#"{"error": "Internal Server Error", "errorCode": 500, "errorDescription": ""}"#;
+
+        let (url, requests) = http_stand_in(vec![
+            (200, PAGE),
+            (200, PAGE),
+            (500, ERROR),
+            (500, ERROR),
+            (200, "{}"),
+        ])
+        .await;
+        let client = ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?;
+
+        let summary = client
+            .consume(ApplicationId(4))
+            .with_max_attempts(2)
+            .with_retry_delay(Duration::ZERO)
+            .drain(|_| async { Ok::<_, std::convert::Infallible>(()) })
+            .await?;
+
+        assert_eq!(
+            summary,
+            ConsumerSummary {
+                processed: 1,
+                failed: 0,
+                dead_lettered: 0,
+                unacknowledged: 1,
+            }
+        );
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[1].0.contains("since=3"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn moved_messages_are_not_moved_again() -> eyre::Result<()> {
+        const APPLICATIONS: &str = r#"[{
+            "id": 5, "token": "dead-letter-token", "name": "dead letters", "description": "",
+            "internal": false, "image": "static/defaultapp.png", "defaultPriority": null
+        }]"#;
+        const PAGE: &str = r#"{
+            "messages": [
+                {"id": 7, "appid": 4, "date": "2023-08-01T12:00:00Z", "message": "poison", "priority": 0}
+            ],
+            "paging": {"limit": 200, "since": 0, "size": 1}
+        }"#;
+        const DEAD_LETTERS: &str = r#"{
+            "messages": [
+                {"id": 9, "appid": 5, "date": "2023-08-01T12:00:00Z", "message": "other", "priority": 0,
+                 "extras": {"gotify-rs::dead_letter": {"application": 4, "message": 8}}},
+                {"id": 8, "appid": 5, "date": "2023-08-01T12:00:00Z", "message": "poison", "priority": 0,
+                 "extras": {"gotify-rs::dead_letter": {"application": 4, "message": 7}}}
+            ],
+            "paging": {"limit": 200, "since": 0, "size": 2}
+        }"#;
+
+        let (url, requests) = http_stand_in(vec![
+            (200, APPLICATIONS),
+            (200, PAGE),
+            (200, PAGE),
+            (200, DEAD_LETTERS),
+            (200, "{}"),
+        ])
+        .await;
+        let client = ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?;
+
+        let summary = client
+            .consume(ApplicationId(4))
+            .with_max_attempts(1)
+            .with_dead_letter(ApplicationId(5))
+            .drain(|_| async { Err("cannot handle poison") })
+            .await?;
+
+        assert_eq!(summary.dead_lettered, 1);
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 5);
+        assert!(requests[3].0.starts_with("GET /hook/application/5/message"));
+        assert!(requests[4].0.starts_with("DELETE /hook/message/7"));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn drain_with_dead_letter() -> eyre::Result<()> {
+        let client = client_client();
+        let queue = client.create_application("queue").await?;
+        let dead_letter = client.create_application("queue-dead-letter").await?;
+
+        let producer = client.with_access_token::<crate::AppToken>(queue.token.as_str())?;
+        for job in ["job0", "job1", "poison", "job3", "job4"] {
+            producer.create_message(job).await?;
+        }
+
+        let handled = Mutex::new(Vec::new());
+        let summary = client
+            .consume(queue.id)
+            .with_workers(3)
+            .with_max_attempts(2)
+            .with_retry_delay(Duration::ZERO)
+            .with_dead_letter(dead_letter.id)
+            .drain(|message| {
+                handled.lock().unwrap().push(message.message.clone());
+                async move {
+                    match message.message.as_str() {
+                        "poison" => Err("cannot handle poison"),
+                        _ => Ok(()),
+                    }
+                }
+            })
+            .await?;
+
+        assert_eq!(
+            summary,
+            ConsumerSummary {
+                processed: 4,
+                failed: 0,
+                dead_lettered: 1,
+                unacknowledged: 0,
+            }
+        );
+        let mut handled = handled.into_inner().unwrap();
+        handled.sort();
+        assert_eq!(
+            handled,
+            ["job0", "job1", "job3", "job4", "poison", "poison"]
+        );
+
+        assert!(client
+            .get_application_messages(queue.id)
+            .await?
+            .messages
+            .is_empty());
+        let dead = client
+            .get_application_messages(dead_letter.id)
+            .await?
+            .messages;
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].message, "poison");
+        assert_eq!(
+            dead[0].extras.as_ref().unwrap()[DEAD_LETTER_EXTRAS_KEY]["attempts"],
+            2
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,404 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::PathBuf,
+};
+
+use futures_util::{StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    models::{Application, ApplicationId, Message, MessageId},
+    utils::write_atomically,
+    AppClient, ClientClient, Error, WebsocketConnectError, WebsocketError,
+};
+
+/// Replicate messages from one Gotify server to another.
+///
+/// Applications are matched by name, missing applications are created on
+/// the destination. Every message is re-posted through the destination
+/// application's token with the same title, priority and extras.
+///
+/// The mapping between source and destination ids is kept in a
+/// [`ReplicationState`], which can be persisted to a file with
+/// [`with_state_file()`](Self::with_state_file). After a restart, messages
+/// created on the source in the meantime are replicated before new messages
+/// are streamed. A message is only marked as replicated after it was
+/// accepted by the destination, so a crash in between can result in a
+/// single duplicate, but never in a lost message.
+///
+/// Messages of applications that were deleted on the source before they
+/// could be replicated are skipped and passed to the
+/// [skip handler](Self::with_on_skip).
+pub struct Replicator {
+    source: ClientClient,
+    destination: ClientClient,
+    state: ReplicationState,
+    state_file: Option<PathBuf>,
+    history: bool,
+    max_tracked_messages: usize,
+    on_skip: Option<SkipHandler>,
+    source_applications: HashMap<ApplicationId, Application>,
+    destination_clients: HashMap<ApplicationId, AppClient>,
+}
+
+type SkipHandler = Box<dyn Fn(&Message, &ReplicationError) + Send + Sync>;
+
+/// The persisted progress of a [`Replicator`].
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct ReplicationState {
+    /// Source application ids mapped to destination application ids.
+    pub applications: BTreeMap<ApplicationId, ApplicationId>,
+    /// The most recently replicated source message ids mapped to destination message ids.
+    ///
+    /// Limited to [`Replicator::with_max_tracked_messages()`] entries.
+    pub messages: BTreeMap<MessageId, MessageId>,
+    /// The newest source message that was replicated or skipped.
+    pub last_message: Option<MessageId>,
+}
+
+/// Errors that can occur during replication.
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum ReplicationError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to connect to the source's message stream")]
+    Connect(#[source] Box<WebsocketConnectError>),
+    #[error("the source's message stream failed")]
+    Stream(#[source] Box<WebsocketError>),
+    #[error("failed to read or write the replication state")]
+    StateIo(#[from] std::io::Error),
+    #[error("invalid replication state")]
+    StateFormat(#[from] serde_json::Error),
+    #[error("the source application {0} doesn't exist")]
+    UnknownApplication(ApplicationId),
+}
+
+impl From<WebsocketConnectError> for ReplicationError {
+    fn from(e: WebsocketConnectError) -> Self {
+        Self::Connect(Box::new(e))
+    }
+}
+impl From<WebsocketError> for ReplicationError {
+    fn from(e: WebsocketError) -> Self {
+        Self::Stream(Box::new(e))
+    }
+}
+
+impl Replicator {
+    /// The default for [`with_max_tracked_messages()`](Self::with_max_tracked_messages).
+    pub const DEFAULT_MAX_TRACKED_MESSAGES: usize = 1000;
+
+    /// Create a replicator from a source to a destination server.
+    pub fn new(source: ClientClient, destination: ClientClient) -> Self {
+        Self {
+            source,
+            destination,
+            state: ReplicationState::default(),
+            state_file: None,
+            history: false,
+            max_tracked_messages: Self::DEFAULT_MAX_TRACKED_MESSAGES,
+            on_skip: None,
+            source_applications: HashMap::new(),
+            destination_clients: HashMap::new(),
+        }
+    }
+    /// Load the state from a file if it exists and save it there after every replicated message.
+    pub fn with_state_file(
+        mut self,
+        path: impl Into<PathBuf>,
+    ) -> core::result::Result<Self, ReplicationError> {
+        let path = path.into();
+        match std::fs::read(&path) {
+            Ok(content) => self.state = serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.state_file = Some(path);
+        Ok(self)
+    }
+    /// Also replicate messages that existed before the first run.
+    ///
+    /// By default, only messages created after the first run are replicated.
+    pub fn with_history(mut self, history: bool) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Keep at most this many entries in [`ReplicationState::messages`], dropping the oldest ones.
+    ///
+    /// Defaults to [`DEFAULT_MAX_TRACKED_MESSAGES`](Self::DEFAULT_MAX_TRACKED_MESSAGES).
+    pub fn with_max_tracked_messages(mut self, max: usize) -> Self {
+        self.max_tracked_messages = max;
+        self
+    }
+    /// Call a function for every message that is skipped because it can't be replicated.
+    pub fn with_on_skip(
+        mut self,
+        handler: impl Fn(&Message, &ReplicationError) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_skip = Some(Box::new(handler));
+        self
+    }
+
+    /// Return the current state.
+    pub fn state(&self) -> &ReplicationState {
+        &self.state
+    }
+
+    /// Create all source applications that are missing on the destination.
+    pub async fn sync_applications(&mut self) -> core::result::Result<(), ReplicationError> {
+        self.refresh_source_applications().await?;
+        let ids = self.source_applications.keys().copied().collect::<Vec<_>>();
+        for id in ids {
+            self.destination_client(id).await?;
+        }
+        Ok(())
+    }
+
+    /// Replicate all messages created on the source since the last run.
+    pub async fn catch_up(&mut self) -> core::result::Result<(), ReplicationError> {
+        let Some(last) = self
+            .state
+            .last_message
+            .or(self.history.then_some(MessageId(0)))
+        else {
+            // first run without history, start at the newest message
+            let newest = self.source.get_messages().with_limit(1).await?;
+            self.state.last_message = Some(newest.messages.first().map_or(MessageId(0), |m| m.id));
+            return self.save_state();
+        };
+
+        let mut missed = Vec::new();
+        {
+            let mut pages = std::pin::pin!(self.source.message_pages(None, None));
+            while let Some(page) = pages.try_next().await? {
+                let reached_last = page.messages.iter().any(|m| m.id <= last);
+                missed.extend(page.messages.into_iter().filter(|m| m.id > last));
+                if reached_last {
+                    break;
+                }
+            }
+        }
+
+        for message in missed.into_iter().rev() {
+            self.replicate(message).await?;
+        }
+        Ok(())
+    }
+
+    /// Catch up and replicate new messages until the source's stream ends.
+    pub async fn run(&mut self) -> core::result::Result<(), ReplicationError> {
+        let source = self.source.clone();
+        // subscribe before catching up, so no message is missed in between
+        let stream = source.stream_messages().await?;
+        let mut stream = std::pin::pin!(stream);
+
+        self.catch_up().await?;
+
+        while let Some(message) = stream.next().await {
+            self.replicate(message?).await?;
+        }
+        Ok(())
+    }
+
+    /// Replicate a single message unless it was already replicated.
+    async fn replicate(&mut self, message: Message) -> core::result::Result<(), ReplicationError> {
+        if self
+            .state
+            .last_message
+            .is_some_and(|last| message.id <= last)
+        {
+            return Ok(());
+        }
+
+        let app_client = match self.destination_client(message.appid).await {
+            Ok(app_client) => app_client,
+            Err(e @ ReplicationError::UnknownApplication(_)) => {
+                if let Some(on_skip) = &self.on_skip {
+                    on_skip(&message, &e);
+                }
+                self.state.last_message = Some(message.id);
+                return self.save_state();
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut builder = app_client
+            .create_message(message.message)
+            .with_priority(message.priority);
+        if let Some(title) = message.title {
+            builder = builder.with_title(title);
+        }
+        if let Some(extras) = message.extras {
+            builder = builder.with_extras(extras);
+        }
+        let replicated = builder.await?;
+
+        self.state.messages.insert(message.id, replicated.id);
+        while self.state.messages.len() > self.max_tracked_messages {
+            self.state.messages.pop_first();
+        }
+        self.state.last_message = Some(message.id);
+        self.save_state()
+    }
+
+    /// Return a client for the destination application matching a source application.
+    async fn destination_client(
+        &mut self,
+        source_id: ApplicationId,
+    ) -> core::result::Result<&AppClient, ReplicationError> {
+        if !self.destination_clients.contains_key(&source_id) {
+            if !self.source_applications.contains_key(&source_id) {
+                self.refresh_source_applications().await?;
+            }
+            let Some(source) = self.source_applications.get(&source_id) else {
+                return Err(ReplicationError::UnknownApplication(source_id));
+            };
+
+            let mut builder = self
+                .destination
+                .ensure_application(&source.name)
+                .with_description(&source.description);
+            if let Some(default_priority) = source.default_priority {
+                builder = builder.with_default_priority(default_priority);
+            }
+            let (application, app_client) = builder.await?;
+
+            self.state.applications.insert(source_id, application.id);
+            self.destination_clients.insert(source_id, app_client);
+        }
+
+        Ok(&self.destination_clients[&source_id])
+    }
+
+    async fn refresh_source_applications(&mut self) -> core::result::Result<(), Error> {
+        self.source_applications = self
+            .source
+            .get_applications()
+            .await?
+            .into_iter()
+            .map(|application| (application.id, application))
+            .collect();
+        Ok(())
+    }
+
+    fn save_state(&self) -> core::result::Result<(), ReplicationError> {
+        if let Some(path) = &self.state_file {
+            write_atomically(path, &serde_json::to_vec_pretty(&self.state)?)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Replicator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replicator")
+            .field("source", &self.source)
+            .field("destination", &self.destination)
+            .field("state", &self.state)
+            .field("state_file", &self.state_file)
+            .field("history", &self.history)
+            .field("max_tracked_messages", &self.max_tracked_messages)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::{
+        models::{ApplicationId, MessageId},
+        testsuite::*,
+        ClientClient,
+    };
+
+    use super::{ReplicationError, ReplicationState, Replicator};
+
+    #[test]
+    fn state_roundtrip() -> eyre::Result<()> {
+        let state = ReplicationState {
+            applications: [(ApplicationId(3), ApplicationId(7))].into(),
+            messages: [(MessageId(5), MessageId(12))].into(),
+            last_message: Some(MessageId(5)),
+        };
+
+        let json = serde_json::to_string(&state)?;
+        let parsed: ReplicationState = serde_json::from_str(&json)?;
+
+        assert_eq!(parsed.applications, state.applications);
+        assert_eq!(parsed.messages, state.messages);
+        assert_eq!(parsed.last_message, state.last_message);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn messages_of_deleted_applications_are_skipped() -> eyre::Result<()> {
+        let (url, _) = http_stand_in(vec![(200, "[]")]).await;
+        let skipped = Arc::new(Mutex::new(Vec::new()));
+
+        let recorded = skipped.clone();
+        let mut replicator = Replicator::new(
+            ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?,
+            ClientClient::new("http://localhost:30081", GOTIFY_CLIENT_TOKEN)?,
+        )
+        .with_on_skip(move |message, error| {
+            assert!(matches!(
+                error,
+                ReplicationError::UnknownApplication(ApplicationId(9))
+            ));
+            recorded.lock().unwrap().push(message.id);
+        });
+
+        replicator
+            .replicate(serde_json::from_value(serde_json::json!({
+                "id": 4,
+                "appid": 9,
+                "date": "2023-08-01T12:00:00Z",
+                "message": "",
+                "priority": 0,
+            }))?)
+            .await?;
+
+        assert_eq!(*skipped.lock().unwrap(), vec![MessageId(4)]);
+        assert_eq!(replicator.state().last_message, Some(MessageId(4)));
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn catch_up() -> eyre::Result<()> {
+        let client = client_client();
+        let state_file = temp_path("replication.json");
+
+        let original = client.get_messages().await?.messages;
+
+        // replicating a server onto itself re-posts every message to the same application
+        let mut replicator = Replicator::new((*client).clone(), (*client).clone())
+            .with_history(true)
+            .with_state_file(&state_file)?;
+        replicator.catch_up().await?;
+
+        assert_eq!(replicator.state().messages.len(), original.len());
+        assert_eq!(
+            replicator.state().last_message,
+            original.first().map(|m| m.id)
+        );
+        assert_eq!(
+            client.get_messages().await?.messages.len(),
+            2 * original.len()
+        );
+
+        let restarted =
+            Replicator::new((*client).clone(), (*client).clone()).with_state_file(&state_file)?;
+        assert_eq!(restarted.state().messages, replicator.state().messages);
+
+        std::fs::remove_file(&state_file)?;
+
+        Ok(())
+    }
+}
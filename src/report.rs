@@ -0,0 +1,232 @@
+use std::{backtrace::Backtrace, fmt::Write as _, sync::mpsc, time::Duration};
+
+use crate::{app::MessageBuilder, utils::markdown_extras, AppClient};
+
+/// Report errors as messages.
+impl AppClient {
+    /// Create a markdown-formatted message from an error and its chain of sources.
+    ///
+    /// Reports from `eyre` or `anyhow` can be passed by dereferencing them (`&*report`).
+    pub fn create_error_report(&self, error: &dyn std::error::Error) -> MessageBuilder<'_> {
+        self.create_message(format_error_chain(error))
+            .with_title(error.to_string())
+            .with_extras(markdown_extras())
+    }
+}
+
+fn format_error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = format!("**Error:** {error}");
+
+    let mut source = error.source();
+    if source.is_some() {
+        message.push_str("\n\n**Caused by:**\n");
+    }
+    let mut i = 1;
+    while let Some(cause) = source {
+        let _ = write!(message, "\n{i}. {cause}");
+        source = cause.source();
+        i += 1;
+    }
+
+    message
+}
+
+/// Install a panic hook that sends a message for every panic.
+///
+/// This is a shorthand for `PanicHook::new(client).install()`.
+pub fn install_panic_hook(client: AppClient) {
+    PanicHook::new(client).install()
+}
+
+/// A panic hook that sends a message before the process continues unwinding or aborts.
+///
+/// The message contains the panic payload, its location, the name of the
+/// panicking thread and optionally a backtrace. The previously installed
+/// hook is still called afterwards.
+#[derive(Debug)]
+pub struct PanicHook {
+    client: AppClient,
+    title: String,
+    priority: u8,
+    timeout: Duration,
+    force_backtrace: bool,
+}
+
+impl PanicHook {
+    /// Create a panic hook that sends messages with the given client.
+    pub fn new(client: AppClient) -> Self {
+        Self {
+            client,
+            title: "Panic".into(),
+            priority: 10,
+            timeout: Duration::from_secs(5),
+            force_backtrace: false,
+        }
+    }
+    /// Set the title of the message. Defaults to `Panic`.
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = title.into();
+        self
+    }
+    /// Set the priority of the message. Defaults to `10`.
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+    /// Set how long the panicking thread waits for the message to be sent. Defaults to 5 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+    /// Always include a backtrace.
+    ///
+    /// Otherwise, a backtrace is only included if enabled via the
+    /// `RUST_BACKTRACE` or `RUST_LIB_BACKTRACE` environment variables.
+    pub fn with_backtrace(mut self, force_backtrace: bool) -> Self {
+        self.force_backtrace = force_backtrace;
+        self
+    }
+
+    /// Install this panic hook, keeping the previous hook.
+    pub fn install(self) {
+        let previous_hook = std::panic::take_hook();
+
+        std::panic::set_hook(Box::new(move |info| {
+            let payload = info
+                .payload()
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| info.payload().downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("Box<dyn Any>");
+
+            let thread = std::thread::current();
+
+            let mut message = format!(
+                "**Thread** `{}` panicked",
+                thread.name().unwrap_or("<unnamed>")
+            );
+            if let Some(location) = info.location() {
+                let _ = write!(message, " at `{location}`");
+            }
+            let _ = write!(message, ":\n\n```\n{payload}\n```");
+
+            let backtrace = if self.force_backtrace {
+                Backtrace::force_capture()
+            } else {
+                Backtrace::capture()
+            };
+            if backtrace.status() == std::backtrace::BacktraceStatus::Captured {
+                let _ = write!(message, "\n\n**Backtrace:**\n\n```\n{backtrace}\n```");
+            }
+
+            self.send(message);
+
+            previous_hook(info)
+        }))
+    }
+
+    /// Send the message from a separate thread, as the panicking thread might
+    /// be running inside of an async runtime, and wait at most `self.timeout`.
+    fn send(&self, message: String) {
+        let client = self.client.clone();
+        let title = self.title.clone();
+        let priority = self.priority;
+
+        let (done, wait) = mpsc::channel();
+
+        let spawned = std::thread::Builder::new()
+            .name("gotify-panic-hook".into())
+            .spawn(move || {
+                if let Ok(runtime) = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    let _ = runtime.block_on(
+                        client
+                            .create_message(message)
+                            .with_title(title)
+                            .with_priority(priority)
+                            .with_extras(markdown_extras())
+                            .send(),
+                    );
+                }
+                let _ = done.send(());
+            });
+
+        if spawned.is_ok() {
+            let _ = wait.recv_timeout(self.timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testsuite::*;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("failed to load configuration")]
+    struct ConfigError(#[source] std::io::Error);
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn create_error_report() -> eyre::Result<()> {
+        let error = ConfigError(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "config.toml not found",
+        ));
+
+        let message = app_client().create_error_report(&error).await?;
+
+        assert_eq!(
+            message.title.as_deref(),
+            Some("failed to load configuration")
+        );
+        assert_eq!(
+            message.message,
+            "**Error:** failed to load configuration\n\n**Caused by:**\n\n1. config.toml not found"
+        );
+
+        let report = eyre::Report::new(error).wrap_err("startup failed");
+        let message = app_client().create_error_report(&*report).await?;
+
+        assert_eq!(message.title.as_deref(), Some("startup failed"));
+        assert!(message.message.contains("1. failed to load configuration"));
+        assert!(message.message.contains("2. config.toml not found"));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manage-messages")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn panic_hook() -> eyre::Result<()> {
+        // the hook is process-wide, restore it for the other tests
+        let previous_hook = std::panic::take_hook();
+        super::PanicHook::new(crate::AppClient::new(GOTIFY_URL, GOTIFY_APP_TOKEN)?)
+            .with_title("panic_hook test")
+            .install();
+
+        let result = std::thread::Builder::new()
+            .name("doomed".into())
+            .spawn(|| panic!("something went wrong"))
+            .map(std::thread::JoinHandle::join);
+        std::panic::set_hook(previous_hook);
+        assert!(result?.is_err());
+
+        let message = client_client()
+            .get_messages()
+            .await?
+            .messages
+            .into_iter()
+            .find(|m| m.title.as_deref() == Some("panic_hook test"))
+            .unwrap();
+
+        assert_eq!(message.priority, 10);
+        assert!(message
+            .message
+            .starts_with("**Thread** `doomed` panicked at `src/report.rs:"));
+        assert!(message.message.contains("something went wrong"));
+
+        Ok(())
+    }
+}
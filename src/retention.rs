@@ -0,0 +1,423 @@
+//! Expire messages according to retention rules.
+//!
+//! Gotify keeps messages until they are deleted manually. A [`RetentionPolicy`]
+//! describes which messages should be deleted, either globally or per
+//! application, and [`ClientClient::enforce_retention()`] applies it once.
+//! [`ClientClient::spawn_retention()`] applies it periodically in the background.
+//!
+//! Messages created with [`MessageBuilder::with_ttl()`](crate::builder::MessageBuilder::with_ttl)
+//! are deleted after their time to live, independent of any rules.
+//!
+//! ```no_run
+//! # async fn run(client: gotify::ClientClient) -> gotify::Result<()> {
+//! use std::time::Duration;
+//!
+//! use gotify::{models::ApplicationId, retention::{RetentionPolicy, RetentionRule}};
+//!
+//! let policy = RetentionPolicy::new()
+//!     .with_default_rule(RetentionRule::new().with_max_age(Duration::from_secs(30 * 24 * 3600)))
+//!     .with_application_rule(
+//!         ApplicationId(3),
+//!         RetentionRule::new().with_max_count(100).with_keep_priority(8),
+//!     );
+//!
+//! let report = client.enforce_retention(&policy).await?;
+//! println!("would delete {} messages", report.expired.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::HashMap;
+
+use futures_util::TryStreamExt;
+use time::{Duration, OffsetDateTime};
+
+use crate::{
+    models::{ApplicationId, Message, MessageId},
+    ClientClient, Error, Result,
+};
+
+/// Rules for the messages of one application or of all applications.
+///
+/// A message is expired if any of the rules applies to it.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionRule {
+    max_age: Option<Duration>,
+    max_count: Option<usize>,
+    keep_priority: Option<u8>,
+}
+
+impl RetentionRule {
+    /// Create a rule that doesn't expire any messages.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Expire messages older than this.
+    pub fn with_max_age(mut self, max_age: std::time::Duration) -> Self {
+        self.max_age = Some(Duration::try_from(max_age).unwrap_or(Duration::MAX));
+        self
+    }
+    /// Keep at most this many messages per application, expiring the oldest ones.
+    pub fn with_max_count(mut self, max_count: usize) -> Self {
+        self.max_count = Some(max_count);
+        self
+    }
+    /// Never expire messages with at least this priority because of their age or count.
+    ///
+    /// Kept messages don't count towards [`with_max_count()`](Self::with_max_count).
+    pub fn with_keep_priority(mut self, priority: u8) -> Self {
+        self.keep_priority = Some(priority);
+        self
+    }
+
+    fn keeps(&self, message: &Message) -> bool {
+        self.keep_priority
+            .is_some_and(|priority| message.priority >= priority)
+    }
+}
+
+/// The rules used by [`ClientClient::enforce_retention()`].
+#[derive(Clone, Debug)]
+pub struct RetentionPolicy {
+    default_rule: Option<RetentionRule>,
+    application_rules: HashMap<ApplicationId, RetentionRule>,
+    honor_ttl: bool,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            default_rule: None,
+            application_rules: HashMap::new(),
+            honor_ttl: true,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Create a policy that only expires messages after their time to live.
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Apply a rule to all applications without an application-specific rule.
+    pub fn with_default_rule(mut self, rule: RetentionRule) -> Self {
+        self.default_rule = Some(rule);
+        self
+    }
+    /// Apply a rule to the messages of an application, replacing the default rule.
+    pub fn with_application_rule(mut self, id: ApplicationId, rule: RetentionRule) -> Self {
+        self.application_rules.insert(id, rule);
+        self
+    }
+    /// Expire messages after the time to live stored in their extras. Enabled by default.
+    pub fn with_ttl(mut self, honor_ttl: bool) -> Self {
+        self.honor_ttl = honor_ttl;
+        self
+    }
+
+    fn rule(&self, id: ApplicationId) -> Option<&RetentionRule> {
+        self.application_rules
+            .get(&id)
+            .or(self.default_rule.as_ref())
+    }
+
+    /// Return why a message is expired, if it is.
+    ///
+    /// Messages must be checked from newest to oldest, `counts` tracks the
+    /// number of checked messages per application.
+    fn expiry(
+        &self,
+        message: &Message,
+        counts: &mut HashMap<ApplicationId, usize>,
+        now: OffsetDateTime,
+    ) -> Option<ExpiryReason> {
+        if self.honor_ttl && message.expires_at().is_some_and(|t| t <= now) {
+            return Some(ExpiryReason::Ttl);
+        }
+
+        let rule = self
+            .rule(message.appid)
+            .filter(|rule| !rule.keeps(message))?;
+        let count = counts.entry(message.appid).or_default();
+        *count += 1;
+
+        if rule
+            .max_age
+            .is_some_and(|max_age| now - message.date > max_age)
+        {
+            Some(ExpiryReason::Age)
+        } else if rule.max_count.is_some_and(|max_count| *count > max_count) {
+            Some(ExpiryReason::Count)
+        } else {
+            None
+        }
+    }
+}
+
+/// Why a message was expired.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExpiryReason {
+    /// The message's time to live has passed.
+    Ttl,
+    /// The message is older than [`RetentionRule::with_max_age()`].
+    Age,
+    /// The application has more messages than [`RetentionRule::with_max_count()`].
+    Count,
+}
+
+/// A message that is expired according to a [`RetentionPolicy`].
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct ExpiredMessage {
+    /// The expired message.
+    pub message: Message,
+    /// Why the message is expired.
+    pub reason: ExpiryReason,
+}
+
+/// The messages that were (or in dry-run mode would have been) deleted.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct RetentionReport {
+    /// Whether nothing was deleted.
+    pub dry_run: bool,
+    /// The number of messages that were checked.
+    pub scanned: usize,
+    /// The expired messages, newest first.
+    pub expired: Vec<ExpiredMessage>,
+    /// The expired messages that couldn't be deleted.
+    pub failed: Vec<(MessageId, Error)>,
+}
+
+/// Enforce retention policies.
+impl ClientClient {
+    /// Delete the messages that are expired according to a policy.
+    ///
+    /// Runs in dry-run mode unless disabled with [`RetentionBuilder::with_dry_run()`].
+    pub fn enforce_retention<'a>(&'a self, policy: &'a RetentionPolicy) -> RetentionBuilder<'a> {
+        RetentionBuilder {
+            client: self,
+            policy,
+            dry_run: true,
+        }
+    }
+
+    /// Enforce a policy every `period` in a background task on the current tokio runtime.
+    ///
+    /// The first run starts immediately. The result of every run is passed to `on_report`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn spawn_retention(
+        &self,
+        policy: RetentionPolicy,
+        period: std::time::Duration,
+        mut on_report: impl FnMut(Result<RetentionReport>) + Send + 'static,
+    ) -> RetentionTask {
+        let client = self.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                on_report(
+                    client
+                        .enforce_retention(&policy)
+                        .with_dry_run(false)
+                        .send()
+                        .await,
+                );
+            }
+        });
+
+        RetentionTask { handle }
+    }
+}
+
+/// Builder for [`ClientClient::enforce_retention()`].
+#[derive(Debug)]
+pub struct RetentionBuilder<'a> {
+    client: &'a ClientClient,
+    policy: &'a RetentionPolicy,
+    dry_run: bool,
+}
+
+impl<'a> RetentionBuilder<'a> {
+    /// Only report the expired messages (`true`, the default) or also delete them (`false`).
+    ///
+    /// Messages that couldn't be deleted are listed in [`RetentionReport::failed`].
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Find the expired messages and delete them unless in dry-run mode.
+    ///
+    /// Messages are checked page by page and expired ones are deleted right away.
+    pub async fn send(self) -> Result<RetentionReport> {
+        let policy = self.policy;
+        let now = OffsetDateTime::now_utc();
+        let mut report = RetentionReport {
+            dry_run: self.dry_run,
+            scanned: 0,
+            expired: Vec::new(),
+            failed: Vec::new(),
+        };
+
+        // without TTLs or a default rule, only the applications with a rule need to be checked
+        let applications = if policy.honor_ttl || policy.default_rule.is_some() {
+            vec![None]
+        } else {
+            policy.application_rules.keys().copied().map(Some).collect()
+        };
+
+        let mut counts = HashMap::new();
+        for id in applications {
+            let mut pages = std::pin::pin!(self.client.message_pages(id, None));
+            while let Some(page) = pages.try_next().await? {
+                report.scanned += page.messages.len();
+                for message in page.messages {
+                    let Some(reason) = policy.expiry(&message, &mut counts, now) else {
+                        continue;
+                    };
+                    if !self.dry_run {
+                        if let Err(e) = self.client.delete_message(message.id).await {
+                            report.failed.push((message.id, e));
+                        }
+                    }
+                    report.expired.push(ExpiredMessage { message, reason });
+                }
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+impl<'a> std::future::IntoFuture for RetentionBuilder<'a> {
+    type Output = Result<RetentionReport>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'a>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// A background task created by [`ClientClient::spawn_retention()`].
+///
+/// The task is stopped when this handle is dropped.
+#[derive(Debug)]
+pub struct RetentionTask {
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl RetentionTask {
+    /// Stop the task.
+    pub fn abort(&self) {
+        self.handle.abort()
+    }
+}
+
+impl Drop for RetentionTask {
+    fn drop(&mut self) {
+        self.handle.abort()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use crate::{
+        models::{ApplicationId, Message},
+        testsuite::*,
+    };
+
+    use super::{ExpiryReason, RetentionPolicy, RetentionRule};
+
+    fn message(id: i64, appid: i64, age: time::Duration, priority: u8) -> Message {
+        Message {
+            date: time::OffsetDateTime::UNIX_EPOCH + time::Duration::days(100) - age,
+            priority,
+            ..test_message(id, appid, "")
+        }
+    }
+
+    #[test]
+    fn expired_messages() {
+        let now = time::OffsetDateTime::UNIX_EPOCH + time::Duration::days(100);
+        let mut with_ttl = message(1, 2, time::Duration::hours(2), 0);
+        with_ttl.extras = Some([(Message::TTL_EXTRAS_KEY.into(), 3600.into())].into());
+
+        let messages = [
+            message(6, 1, time::Duration::ZERO, 0),
+            message(5, 1, time::Duration::hours(1), 9),
+            message(4, 1, time::Duration::hours(2), 0),
+            message(3, 1, time::Duration::hours(3), 0),
+            message(2, 2, time::Duration::days(10), 0),
+            with_ttl,
+        ];
+
+        let policy = RetentionPolicy::new()
+            .with_default_rule(RetentionRule::new().with_max_age(Duration::from_secs(86400)))
+            .with_application_rule(
+                ApplicationId(1),
+                RetentionRule::new().with_max_count(2).with_keep_priority(8),
+            );
+
+        let mut counts = HashMap::new();
+        assert_eq!(
+            messages
+                .iter()
+                .filter_map(|m| Some((m.id.0, policy.expiry(m, &mut counts, now)?)))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, ExpiryReason::Count),
+                (2, ExpiryReason::Age),
+                (1, ExpiryReason::Ttl)
+            ]
+        );
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn enforce_retention() -> eyre::Result<()> {
+        let client = client_client();
+        let policy = RetentionPolicy::new()
+            .with_application_rule(ApplicationId(3), RetentionRule::new().with_max_count(1));
+
+        let report = client.enforce_retention(&policy).await?;
+        assert!(report.dry_run);
+        assert_eq!(report.expired.len(), 1);
+        assert_eq!(
+            client
+                .get_application_messages(ApplicationId(3))
+                .await?
+                .messages
+                .len(),
+            2
+        );
+
+        let report = client
+            .enforce_retention(&policy)
+            .with_dry_run(false)
+            .await?;
+        assert!(report.failed.is_empty());
+        assert_eq!(
+            client
+                .get_application_messages(ApplicationId(3))
+                .await?
+                .messages
+                .iter()
+                .map(|m| &m.message)
+                .collect::<Vec<_>>(),
+            vec!["App1-Message1"]
+        );
+
+        Ok(())
+    }
+}
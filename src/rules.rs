@@ -0,0 +1,650 @@
+//! React to incoming messages with configurable rules.
+//!
+//! A [`RuleSet`] is a list of rules, each combining a [`Matcher`] with a list
+//! of [`Action`]s. A [`RuleEngine`] evaluates every message from
+//! [`ClientClient::stream_messages()`] against all rules and executes the
+//! actions of the matching ones. Every action can be rate limited and every
+//! execution is recorded in the engine's log.
+//!
+//! ```toml
+//! [[rules]]
+//! name = "disk alerts"
+//! match = { application = "monitoring", min_priority = 5, message = "(?i)disk .* full" }
+//! actions = [
+//!     { type = "command", program = "notify-oncall", args = ["--team", "ops"], timeout_secs = 10, rate_limit = { max = 3, period_secs = 600 } },
+//!     { type = "repost", application = "ops" },
+//! ]
+//!
+//! [[rules]]
+//! name = "drop heartbeats"
+//! match = { title = "^heartbeat$", extras = { "monitor::kind" = "heartbeat" } }
+//! actions = [{ type = "delete" }]
+//! ```
+//!
+//! Reposted messages are streamed as well, so rules that repost messages
+//! should only match messages of other applications.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use regex::Regex;
+use serde::Deserialize;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    models::{ApplicationId, Message, MessageId},
+    AppClient, ClientClient, Error, WebsocketConnectError, WebsocketError,
+};
+
+/// A list of rules, usually read from a configuration file.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RuleSet {
+    /// The rules in the order they are evaluated.
+    #[serde(default)]
+    pub rules: Vec<Rule>,
+}
+
+/// Actions that are executed when a message matches.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    /// The name used in the execution log.
+    pub name: String,
+    /// The conditions for this rule to match.
+    #[serde(default, rename = "match")]
+    pub matcher: Matcher,
+    /// The actions to execute, in order.
+    #[serde(default)]
+    pub actions: Vec<Action>,
+    /// Don't evaluate any further rules if this rule matches.
+    #[serde(default)]
+    pub stop: bool,
+}
+
+/// Conditions that must all be met for a rule to match. An empty matcher matches every message.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Matcher {
+    /// The name of the application that created the message.
+    pub application: Option<String>,
+    /// The minimum priority of the message.
+    pub min_priority: Option<u8>,
+    /// The maximum priority of the message.
+    pub max_priority: Option<u8>,
+    /// A regular expression that must match the title.
+    pub title: Option<String>,
+    /// A regular expression that must match the message text.
+    pub message: Option<String>,
+    /// Top-level extras keys that must have these values.
+    #[serde(default)]
+    pub extras: HashMap<String, serde_json::Value>,
+}
+
+/// An action with an optional rate limit.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Action {
+    /// What to do.
+    #[serde(flatten)]
+    pub kind: ActionKind,
+    /// Limit how often the action is executed.
+    pub rate_limit: Option<RateLimit>,
+    /// Fail the action if it takes longer than this, killing a running command.
+    ///
+    /// Defaults to the engine's [action timeout](RuleEngine::with_action_timeout).
+    pub timeout_secs: Option<u64>,
+}
+
+/// What to do with a matching message.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum ActionKind {
+    /// Run a local command with the message as JSON on stdin.
+    Command {
+        /// The program to run.
+        program: PathBuf,
+        /// Arguments passed to the program.
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    /// Create a copy of the message in another application.
+    Repost {
+        /// The name of the application.
+        application: String,
+    },
+    /// Delete the message.
+    Delete,
+    /// POST the message as JSON to a URL.
+    Webhook {
+        /// The URL to send the message to.
+        url: String,
+    },
+}
+
+/// Execute an action at most `max` times per `period_secs`. Further executions are skipped.
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct RateLimit {
+    /// The maximum number of executions per period.
+    pub max: usize,
+    /// The length of the period in seconds.
+    pub period_secs: u64,
+}
+
+/// Errors that can occur when loading rules or running a [`RuleEngine`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum RulesError {
+    #[error("failed to access {0}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("unsupported file format: {0}")]
+    UnsupportedFormat(PathBuf),
+    #[error("failed to parse TOML")]
+    Toml(#[from] toml::de::Error),
+    #[error("failed to parse YAML")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("failed to parse JSON")]
+    Json(#[from] serde_json::Error),
+    #[error("invalid regular expression in rule {0:?}")]
+    Regex(String, #[source] regex::Error),
+    #[error("invalid webhook URL in rule {0:?}")]
+    Url(String, #[source] url::ParseError),
+    #[error("rule {0:?} refers to the unknown application {1:?}")]
+    UnknownApplication(String, String),
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to connect to the message stream")]
+    Connect(#[source] Box<WebsocketConnectError>),
+    #[error("the message stream failed")]
+    Stream(#[source] Box<WebsocketError>),
+}
+
+impl From<WebsocketConnectError> for RulesError {
+    fn from(e: WebsocketConnectError) -> Self {
+        Self::Connect(Box::new(e))
+    }
+}
+impl From<WebsocketError> for RulesError {
+    fn from(e: WebsocketError) -> Self {
+        Self::Stream(Box::new(e))
+    }
+}
+
+impl RuleSet {
+    /// Parse rules from TOML.
+    pub fn from_toml_str(s: &str) -> Result<Self, RulesError> {
+        Ok(toml::from_str(s)?)
+    }
+    /// Parse rules from YAML.
+    pub fn from_yaml_str(s: &str) -> Result<Self, RulesError> {
+        Ok(serde_yaml::from_str(s)?)
+    }
+    /// Parse rules from JSON.
+    pub fn from_json_str(s: &str) -> Result<Self, RulesError> {
+        Ok(serde_json::from_str(s)?)
+    }
+    /// Read rules from a file, choosing the format by its extension
+    /// (`.toml`, `.yaml`, `.yml` or `.json`).
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self, RulesError> {
+        let path = path.as_ref();
+        let content =
+            std::fs::read_to_string(path).map_err(|e| RulesError::Io(path.to_owned(), e))?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&content),
+            Some("yaml" | "yml") => Self::from_yaml_str(&content),
+            Some("json") => Self::from_json_str(&content),
+            _ => Err(RulesError::UnsupportedFormat(path.to_owned())),
+        }
+    }
+}
+
+/// The result of executing an action.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExecutionOutcome {
+    /// The action was executed.
+    Success,
+    /// The action was skipped because of its rate limit.
+    RateLimited,
+    /// The action failed with this error.
+    Failed(String),
+}
+
+/// An entry of the execution log.
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct ExecutionRecord {
+    /// When the action was executed.
+    pub time: OffsetDateTime,
+    /// The name of the rule.
+    pub rule: String,
+    /// The index of the action in the rule.
+    pub action: usize,
+    /// The message that matched.
+    pub message: MessageId,
+    /// The result of the action.
+    pub outcome: ExecutionOutcome,
+}
+
+/// Evaluates rules against messages and executes their actions.
+#[derive(Debug)]
+pub struct RuleEngine {
+    client: ClientClient,
+    rules: Vec<CompiledRule>,
+    log: Mutex<VecDeque<ExecutionRecord>>,
+    log_capacity: usize,
+    action_timeout: Duration,
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    name: String,
+    application: Option<ApplicationId>,
+    min_priority: Option<u8>,
+    max_priority: Option<u8>,
+    title: Option<Regex>,
+    message: Option<Regex>,
+    extras: HashMap<String, serde_json::Value>,
+    actions: Vec<CompiledAction>,
+    stop: bool,
+}
+
+#[derive(Debug)]
+struct CompiledAction {
+    kind: CompiledActionKind,
+    rate_limit: Option<(RateLimit, Mutex<VecDeque<Instant>>)>,
+    timeout: Option<Duration>,
+}
+
+#[derive(Debug)]
+enum CompiledActionKind {
+    Command { program: PathBuf, args: Vec<String> },
+    Repost(AppClient),
+    Delete,
+    Webhook(url::Url),
+}
+
+impl RuleEngine {
+    /// The number of log entries kept by default.
+    pub const DEFAULT_LOG_CAPACITY: usize = 1000;
+    /// The time after which an action fails by default.
+    pub const DEFAULT_ACTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+    /// Compile the rules and resolve the application names they refer to.
+    pub async fn new(client: ClientClient, rule_set: RuleSet) -> Result<Self, RulesError> {
+        let applications = client.get_applications().await?;
+        let find_application = |rule: &str, name: &str| {
+            applications
+                .iter()
+                .find(|a| a.name == name)
+                .ok_or_else(|| RulesError::UnknownApplication(rule.to_owned(), name.to_owned()))
+        };
+        let compile_regex = |rule: &str, pattern: Option<String>| {
+            pattern
+                .map(|pattern| Regex::new(&pattern))
+                .transpose()
+                .map_err(|e| RulesError::Regex(rule.to_owned(), e))
+        };
+
+        let mut rules = Vec::new();
+        for rule in rule_set.rules {
+            let mut actions = Vec::new();
+            for action in rule.actions {
+                let kind = match action.kind {
+                    ActionKind::Command { program, args } => {
+                        CompiledActionKind::Command { program, args }
+                    }
+                    ActionKind::Repost { application } => {
+                        let application = find_application(&rule.name, &application)?;
+                        CompiledActionKind::Repost(
+                            client
+                                .with_access_token(application.token.as_str())
+                                .map_err(Error::from)?,
+                        )
+                    }
+                    ActionKind::Delete => CompiledActionKind::Delete,
+                    ActionKind::Webhook { url } => CompiledActionKind::Webhook(
+                        url::Url::parse(&url).map_err(|e| RulesError::Url(rule.name.clone(), e))?,
+                    ),
+                };
+                actions.push(CompiledAction {
+                    kind,
+                    rate_limit: action
+                        .rate_limit
+                        .map(|limit| (limit, Mutex::new(VecDeque::new()))),
+                    timeout: action.timeout_secs.map(Duration::from_secs),
+                });
+            }
+
+            rules.push(CompiledRule {
+                application: rule
+                    .matcher
+                    .application
+                    .as_deref()
+                    .map(|name| find_application(&rule.name, name).map(|a| a.id))
+                    .transpose()?,
+                min_priority: rule.matcher.min_priority,
+                max_priority: rule.matcher.max_priority,
+                title: compile_regex(&rule.name, rule.matcher.title)?,
+                message: compile_regex(&rule.name, rule.matcher.message)?,
+                extras: rule.matcher.extras,
+                actions,
+                stop: rule.stop,
+                name: rule.name,
+            });
+        }
+
+        Ok(Self {
+            client,
+            rules,
+            log: Mutex::new(VecDeque::new()),
+            log_capacity: Self::DEFAULT_LOG_CAPACITY,
+            action_timeout: Self::DEFAULT_ACTION_TIMEOUT,
+        })
+    }
+    /// Set how many log entries are kept.
+    pub fn with_log_capacity(mut self, capacity: usize) -> Self {
+        self.log_capacity = capacity;
+        self
+    }
+    /// Set the time after which actions without their own `timeout_secs` fail.
+    ///
+    /// Commands that are still running when their action times out are killed.
+    pub fn with_action_timeout(mut self, timeout: Duration) -> Self {
+        self.action_timeout = timeout;
+        self
+    }
+
+    /// Return the execution log, oldest entries first.
+    pub fn log(&self) -> Vec<ExecutionRecord> {
+        self.log
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Return the names of the rules matching a message.
+    pub fn matching_rules(&self, message: &Message) -> Vec<&str> {
+        let mut names = Vec::new();
+        for rule in &self.rules {
+            if rule.matches(message) {
+                names.push(rule.name.as_str());
+                if rule.stop {
+                    break;
+                }
+            }
+        }
+        names
+    }
+
+    /// Evaluate all rules and execute the actions of the matching ones.
+    ///
+    /// Returns the records that were added to the log.
+    pub async fn handle(&self, message: &Message) -> Vec<ExecutionRecord> {
+        let mut records = Vec::new();
+
+        for rule in &self.rules {
+            if !rule.matches(message) {
+                continue;
+            }
+
+            for (i, action) in rule.actions.iter().enumerate() {
+                let outcome = if action.is_rate_limited() {
+                    ExecutionOutcome::RateLimited
+                } else {
+                    let timeout = action.timeout.unwrap_or(self.action_timeout);
+                    match tokio::time::timeout(timeout, self.execute(&action.kind, message)).await {
+                        Ok(Ok(())) => ExecutionOutcome::Success,
+                        Ok(Err(e)) => ExecutionOutcome::Failed(e),
+                        Err(_) => ExecutionOutcome::Failed(format!("timed out after {timeout:?}")),
+                    }
+                };
+                records.push(ExecutionRecord {
+                    time: OffsetDateTime::now_utc(),
+                    rule: rule.name.clone(),
+                    action: i,
+                    message: message.id,
+                    outcome,
+                });
+            }
+
+            if rule.stop {
+                break;
+            }
+        }
+
+        let mut log = self.log.lock().unwrap_or_else(|e| e.into_inner());
+        log.extend(records.iter().cloned());
+        while log.len() > self.log_capacity {
+            log.pop_front();
+        }
+
+        records
+    }
+
+    /// Handle all messages from the message stream until it ends.
+    pub async fn run(&self) -> Result<(), RulesError> {
+        let mut stream = self.client.stream_messages().await?;
+        while let Some(message) = stream.next().await {
+            self.handle(&message?).await;
+        }
+        Ok(())
+    }
+
+    async fn execute(&self, action: &CompiledActionKind, message: &Message) -> Result<(), String> {
+        match action {
+            CompiledActionKind::Command { program, args } => {
+                // the command is killed if the action times out
+                let mut child = tokio::process::Command::new(program)
+                    .args(args)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()
+                    .map_err(|e| e.to_string())?;
+
+                let json = serde_json::to_vec(message).map_err(|e| e.to_string())?;
+                if let Some(mut stdin) = child.stdin.take() {
+                    // a command that doesn't read its input isn't an error
+                    let _ = stdin.write_all(&json).await;
+                }
+
+                let status = child.wait().await.map_err(|e| e.to_string())?;
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("command exited with {status}"))
+                }
+            }
+            CompiledActionKind::Repost(app_client) => {
+                let mut builder = app_client
+                    .create_message(&message.message)
+                    .with_priority(message.priority);
+                if let Some(title) = &message.title {
+                    builder = builder.with_title(title);
+                }
+                if let Some(extras) = &message.extras {
+                    builder = builder.with_extras(extras.clone());
+                }
+                builder.await.map(drop).map_err(|e| e.to_string())
+            }
+            CompiledActionKind::Delete => self
+                .client
+                .delete_message(message.id)
+                .await
+                .map_err(|e| e.to_string()),
+            CompiledActionKind::Webhook(url) => self
+                .client
+                .http
+                .post(url.clone())
+                .json(message)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .map(drop)
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl CompiledRule {
+    fn matches(&self, message: &Message) -> bool {
+        self.application.is_none_or(|id| id == message.appid)
+            && self.min_priority.is_none_or(|p| message.priority >= p)
+            && self.max_priority.is_none_or(|p| message.priority <= p)
+            && self.title.as_ref().is_none_or(|regex| {
+                message
+                    .title
+                    .as_deref()
+                    .is_some_and(|title| regex.is_match(title))
+            })
+            && self
+                .message
+                .as_ref()
+                .is_none_or(|regex| regex.is_match(&message.message))
+            && self.extras.iter().all(|(key, expected)| {
+                message
+                    .extras
+                    .as_ref()
+                    .and_then(|extras| extras.get(key))
+                    .is_some_and(|value| value == expected)
+            })
+    }
+}
+
+impl CompiledAction {
+    fn is_rate_limited(&self) -> bool {
+        let Some((limit, executions)) = &self.rate_limit else {
+            return false;
+        };
+        let mut executions = executions.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let period = Duration::from_secs(limit.period_secs);
+
+        while executions
+            .front()
+            .is_some_and(|&t| now.duration_since(t) >= period)
+        {
+            executions.pop_front();
+        }
+        if executions.len() >= limit.max {
+            return true;
+        }
+        executions.push_back(now);
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{models::Message, testsuite::*, ClientClient};
+
+    use super::{ExecutionOutcome, RuleEngine, RuleSet};
+
+    fn message(appid: i64, priority: u8, title: &str, text: &str) -> Message {
+        Message {
+            priority,
+            title: Some(title.into()),
+            extras: Some([("monitor::kind".into(), "heartbeat".into())].into()),
+            ..test_message(1, appid, text)
+        }
+    }
+
+    #[test]
+    fn parse_rules() -> eyre::Result<()> {
+        let rules = RuleSet::from_toml_str(
+            r#"
+            [[rules]]
+            name = "disk alerts"
+            match = { min_priority = 5, message = "(?i)disk .* full" }
+            actions = [
+                { type = "command", program = "cat", rate_limit = { max = 3, period_secs = 600 } },
+                { type = "webhook", url = "http://localhost:8080/hook" },
+            ]
+            "#,
+        )?;
+
+        assert_eq!(rules.rules[0].actions.len(), 2);
+        assert_eq!(rules.rules[0].matcher.min_priority, Some(5));
+        assert_eq!(rules.rules[0].actions[0].rate_limit.unwrap().max, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slow_commands_are_killed() -> eyre::Result<()> {
+        let rules = RuleSet::from_toml_str(
+            r#"
+            [[rules]]
+            name = "slow"
+            actions = [{ type = "command", program = "sleep", args = ["10"] }]
+            "#,
+        )?;
+        let (url, _) = http_stand_in(vec![(200, "[]")]).await;
+        let engine = RuleEngine::new(ClientClient::new(url.as_str(), GOTIFY_CLIENT_TOKEN)?, rules)
+            .await?
+            .with_action_timeout(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let records = engine.handle(&message(1, 0, "", "")).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(
+            matches!(&records[0].outcome, ExecutionOutcome::Failed(e) if e.starts_with("timed out"))
+        );
+
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn rule_engine() -> eyre::Result<()> {
+        let rules = RuleSet::from_yaml_str(
+            r#"
+            rules:
+              - name: heartbeats
+                match:
+                  application: App1
+                  title: "^heart"
+                  extras:
+                    monitor::kind: heartbeat
+                actions:
+                  - type: command
+                    program: cat
+                    rate_limit: { max: 1, period_secs: 60 }
+                stop: true
+              - name: everything
+                actions:
+                  - type: command
+                    program: "false"
+            "#,
+        )?;
+        let engine = RuleEngine::new((*client_client()).clone(), rules).await?;
+
+        let heartbeat = message(3, 0, "heartbeat", "still alive");
+        assert_eq!(engine.matching_rules(&heartbeat), vec!["heartbeats"]);
+        assert_eq!(
+            engine.matching_rules(&message(2, 0, "heartbeat", "")),
+            vec!["everything"]
+        );
+
+        let records = engine.handle(&heartbeat).await;
+        assert_eq!(records[0].outcome, ExecutionOutcome::Success);
+        let records = engine.handle(&heartbeat).await;
+        assert_eq!(records[0].outcome, ExecutionOutcome::RateLimited);
+
+        let records = engine.handle(&message(2, 0, "", "")).await;
+        assert!(matches!(records[0].outcome, ExecutionOutcome::Failed(_)));
+        assert_eq!(engine.log().len(), 3);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,744 @@
+//! Send future and recurring messages.
+//!
+//! A [`Scheduler`] keeps a list of jobs in a JSON file. Every job combines a
+//! [`Trigger`] (a point in time, an interval or a cron expression in a time
+//! zone) with a [`ScheduledMessage`] that is sent whenever the trigger fires.
+//!
+//! ```no_run
+//! # async fn schedule() -> Result<(), Box<dyn std::error::Error>> {
+//! use gotify::schedule::{CatchUp, ScheduledMessage, Scheduler, Trigger};
+//!
+//! let client: gotify::AppClient = gotify::Client::new("https://gotify.example.com", "app-token")?;
+//! let scheduler = Scheduler::open(client, "schedule.json")?.with_catch_up(CatchUp::Once);
+//!
+//! if scheduler.jobs().is_empty() {
+//!     scheduler.add(
+//!         Trigger::cron("0 9 1 * *")?.with_time_zone("Europe/Berlin")?,
+//!         ScheduledMessage::new("rotate certs").with_priority(6),
+//!     )?;
+//! }
+//! scheduler.run().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Cron expressions have five fields (minute, hour, day of month, month, day
+//! of week) or six to seven fields starting with seconds and optionally
+//! ending with years.
+//!
+//! Occurrences that are more than the [grace period](Scheduler::with_grace_period)
+//! in the past when they are checked, e.g. because the process wasn't running,
+//! are handled according to the [`CatchUp`] policy.
+
+use std::{collections::HashMap, path::PathBuf, str::FromStr, sync::Mutex, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{utils::write_atomically, AppClient, Error};
+
+/// The identifier of a job in a [`Scheduler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct JobId(pub u64);
+
+impl std::fmt::Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// When a job fires.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Trigger {
+    /// Fire once.
+    At {
+        /// When to fire.
+        #[serde(with = "time::serde::iso8601")]
+        time: OffsetDateTime,
+    },
+    /// Fire at `start` and every `interval` afterwards.
+    Every {
+        /// The first occurrence.
+        #[serde(with = "time::serde::iso8601")]
+        start: OffsetDateTime,
+        /// The time between two occurrences.
+        #[serde(rename = "interval_nanos", with = "duration_nanos")]
+        interval: Duration,
+    },
+    /// Fire whenever a cron expression matches in a time zone.
+    Cron {
+        /// The cron expression, e.g. `0 9 1 * *`.
+        expression: String,
+        /// The IANA name of the time zone, e.g. `Europe/Berlin`.
+        time_zone: String,
+    },
+}
+
+impl Trigger {
+    /// Fire once at `time`.
+    pub fn at(time: OffsetDateTime) -> Self {
+        Self::At { time }
+    }
+    /// Fire every `interval`, starting after the first interval.
+    pub fn every(interval: Duration) -> Self {
+        Self::Every {
+            start: OffsetDateTime::now_utc() + interval,
+            interval,
+        }
+    }
+    /// Fire whenever a cron expression matches in UTC.
+    pub fn cron(expression: impl Into<String>) -> Result<Self, ScheduleError> {
+        let expression = expression.into();
+        parse_cron(&expression)?;
+        Ok(Self::Cron {
+            expression,
+            time_zone: "UTC".into(),
+        })
+    }
+
+    /// Start an interval trigger at `start` instead of after the first interval.
+    pub fn starting_at(self, start: OffsetDateTime) -> Self {
+        match self {
+            Self::Every { interval, .. } => Self::Every { start, interval },
+            other => other,
+        }
+    }
+    /// Evaluate a cron trigger in an IANA time zone like `Europe/Berlin`.
+    pub fn with_time_zone(self, time_zone: impl Into<String>) -> Result<Self, ScheduleError> {
+        let time_zone = time_zone.into();
+        parse_time_zone(&time_zone)?;
+        Ok(match self {
+            Self::Cron { expression, .. } => Self::Cron {
+                expression,
+                time_zone,
+            },
+            other => other,
+        })
+    }
+
+    /// Return the first occurrence after `time`.
+    pub fn next_after(
+        &self,
+        time: OffsetDateTime,
+    ) -> Result<Option<OffsetDateTime>, ScheduleError> {
+        self.compile()?.next_after(time)
+    }
+
+    /// Parse the cron expression and time zone once for evaluating many occurrences.
+    fn compile(&self) -> Result<CompiledTrigger, ScheduleError> {
+        Ok(match self {
+            Self::At { time } => CompiledTrigger::At(*time),
+            Self::Every { start, interval } => CompiledTrigger::Every {
+                start: *start,
+                interval: *interval,
+            },
+            Self::Cron {
+                expression,
+                time_zone,
+            } => CompiledTrigger::Cron(
+                Box::new(parse_cron(expression)?),
+                parse_time_zone(time_zone)?,
+            ),
+        })
+    }
+}
+
+enum CompiledTrigger {
+    At(OffsetDateTime),
+    Every {
+        start: OffsetDateTime,
+        interval: Duration,
+    },
+    Cron(Box<cron::Schedule>, chrono_tz::Tz),
+}
+
+impl CompiledTrigger {
+    fn next_after(&self, time: OffsetDateTime) -> Result<Option<OffsetDateTime>, ScheduleError> {
+        Ok(match self {
+            Self::At(at) => (*at > time).then_some(*at),
+            Self::Every { start, interval } => {
+                if *start > time {
+                    Some(*start)
+                } else if interval.is_zero() {
+                    None
+                } else {
+                    // jump straight to the period after `time`
+                    let elapsed = Duration::try_from(time - *start).unwrap_or_default();
+                    let periods = elapsed.as_nanos() / interval.as_nanos() + 1;
+                    let offset = periods
+                        .checked_mul(interval.as_nanos())
+                        .and_then(|offset| {
+                            Some(time::Duration::new(
+                                i64::try_from(offset / 1_000_000_000).ok()?,
+                                (offset % 1_000_000_000) as i32,
+                            ))
+                        })
+                        .ok_or(ScheduleError::InvalidInterval)?;
+                    start.checked_add(offset)
+                }
+            }
+            Self::Cron(schedule, tz) => {
+                use chrono::TimeZone;
+
+                let after = tz
+                    .timestamp_opt(time.unix_timestamp(), 0)
+                    .single()
+                    .ok_or(ScheduleError::InvalidInterval)?;
+                schedule
+                    .after(&after)
+                    .next()
+                    .and_then(|next| OffsetDateTime::from_unix_timestamp(next.timestamp()).ok())
+            }
+        })
+    }
+}
+
+fn parse_cron(expression: &str) -> Result<cron::Schedule, ScheduleError> {
+    // the cron crate expects seconds, accept the common five field format as well
+    let normalized = match expression.split_whitespace().count() {
+        5 => format!("0 {expression}"),
+        _ => expression.to_owned(),
+    };
+    cron::Schedule::from_str(&normalized)
+        .map_err(|e| ScheduleError::InvalidCron(expression.to_owned(), e.to_string()))
+}
+
+fn parse_time_zone(time_zone: &str) -> Result<chrono_tz::Tz, ScheduleError> {
+    chrono_tz::Tz::from_str(time_zone)
+        .map_err(|_| ScheduleError::UnknownTimeZone(time_zone.to_owned()))
+}
+
+/// Store intervals with full precision, saturating at about 584 years.
+mod duration_nanos {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_nanos)
+    }
+}
+
+/// The message sent when a job fires.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct ScheduledMessage {
+    /// The message text.
+    pub message: String,
+    /// The message title.
+    pub title: Option<String>,
+    /// The message priority.
+    pub priority: Option<u8>,
+    /// The message extras.
+    pub extras: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[allow(missing_docs)]
+impl ScheduledMessage {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+    pub fn with_extras(mut self, extras: impl Into<HashMap<String, serde_json::Value>>) -> Self {
+        self.extras = Some(extras.into());
+        self
+    }
+}
+
+/// A job of a [`Scheduler`].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[non_exhaustive]
+pub struct Job {
+    /// The id of the job.
+    pub id: JobId,
+    /// When the job fires.
+    pub trigger: Trigger,
+    /// The message sent when the job fires.
+    pub message: ScheduledMessage,
+    /// The next occurrence, `None` if the job won't fire again.
+    #[serde(with = "time::serde::iso8601::option")]
+    pub next_run: Option<OffsetDateTime>,
+}
+
+/// What to do with occurrences that were missed, e.g. while the process wasn't running.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CatchUp {
+    /// Don't send missed occurrences.
+    Skip,
+    /// Send a single message for all missed occurrences of a job.
+    #[default]
+    Once,
+    /// Send a message for every missed occurrence.
+    All,
+}
+
+/// Errors that can occur when using a [`Scheduler`].
+#[allow(missing_docs)]
+#[derive(Debug, thiserror::Error)]
+pub enum ScheduleError {
+    #[error(transparent)]
+    Request(#[from] Error),
+    #[error("failed to read or write the schedule file")]
+    Io(#[from] std::io::Error),
+    #[error("invalid schedule file")]
+    Format(#[from] serde_json::Error),
+    #[error("invalid cron expression {0:?}: {1}")]
+    InvalidCron(String, String),
+    #[error("unknown time zone {0:?}")]
+    UnknownTimeZone(String),
+    #[error("the interval is out of range")]
+    InvalidInterval,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct ScheduleFile {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+/// The result of [`Scheduler::run_pending()`].
+#[derive(Debug, Default)]
+#[non_exhaustive]
+pub struct RunReport {
+    /// The number of messages that were sent.
+    pub sent: usize,
+    /// The jobs whose message couldn't be sent.
+    pub failed: Vec<(JobId, Error)>,
+}
+
+type FailureHandler = Box<dyn Fn(JobId, &Error) + Send + Sync>;
+
+/// Sends scheduled messages and persists the schedule to a file.
+pub struct Scheduler {
+    client: AppClient,
+    path: PathBuf,
+    state: Mutex<ScheduleFile>,
+    changed: tokio::sync::Notify,
+    catch_up: CatchUp,
+    grace_period: Duration,
+    retry_delay: Duration,
+    on_failure: Option<FailureHandler>,
+}
+
+impl Scheduler {
+    /// The default time after which an occurrence counts as missed.
+    pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(60);
+    /// The default delay before a message that couldn't be sent is retried by [`run()`](Self::run).
+    pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+    /// Open a schedule file, creating an empty schedule if it doesn't exist.
+    pub fn open(client: AppClient, path: impl Into<PathBuf>) -> Result<Self, ScheduleError> {
+        let path = path.into();
+        let state = match std::fs::read(&path) {
+            Ok(content) => serde_json::from_slice(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => ScheduleFile::default(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            client,
+            path,
+            state: Mutex::new(state),
+            changed: tokio::sync::Notify::new(),
+            catch_up: CatchUp::default(),
+            grace_period: Self::DEFAULT_GRACE_PERIOD,
+            retry_delay: Self::DEFAULT_RETRY_DELAY,
+            on_failure: None,
+        })
+    }
+    /// Decide what happens to missed occurrences.
+    pub fn with_catch_up(mut self, catch_up: CatchUp) -> Self {
+        self.catch_up = catch_up;
+        self
+    }
+    /// Treat occurrences as missed if they are more than `grace_period` in the past.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+    /// Retry messages that couldn't be sent by [`run()`](Self::run) after `retry_delay`.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+    /// Call `handler` whenever [`run()`](Self::run) couldn't send the message of a job.
+    pub fn with_on_failure(
+        mut self,
+        handler: impl Fn(JobId, &Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_failure = Some(Box::new(handler));
+        self
+    }
+
+    /// Return all jobs.
+    pub fn jobs(&self) -> Vec<Job> {
+        self.state.lock().unwrap().jobs.clone()
+    }
+
+    /// Add a job and return its id.
+    pub fn add(&self, trigger: Trigger, message: ScheduledMessage) -> Result<JobId, ScheduleError> {
+        let next_run = trigger.next_after(OffsetDateTime::now_utc() - time::Duration::SECOND)?;
+
+        let id = {
+            let mut state = self.state.lock().unwrap();
+            state.next_id += 1;
+            let id = JobId(state.next_id);
+            state.jobs.push(Job {
+                id,
+                trigger,
+                message,
+                next_run,
+            });
+            self.persist(&state)?;
+            id
+        };
+        self.changed.notify_one();
+        Ok(id)
+    }
+
+    /// Remove a job and return whether it existed.
+    pub fn remove(&self, id: JobId) -> Result<bool, ScheduleError> {
+        let mut state = self.state.lock().unwrap();
+        let len = state.jobs.len();
+        state.jobs.retain(|job| job.id != id);
+        let removed = state.jobs.len() != len;
+        if removed {
+            self.persist(&state)?;
+        }
+        Ok(removed)
+    }
+
+    /// Send the messages of all due jobs.
+    ///
+    /// Progress is saved after every message, so a job whose message
+    /// couldn't be sent continues with that occurrence next time. A failing
+    /// job doesn't keep the other jobs from being run. Jobs that won't fire
+    /// again are removed afterwards. Only fails if the schedule file can't be
+    /// written.
+    pub async fn run_pending(&self) -> Result<RunReport, ScheduleError> {
+        let now = OffsetDateTime::now_utc();
+        let missed_before = now - self.grace_period;
+        let due = self
+            .jobs()
+            .into_iter()
+            .filter(|job| job.next_run.is_some_and(|next_run| next_run <= now))
+            .collect::<Vec<_>>();
+
+        let mut report = RunReport::default();
+        for job in due {
+            match self.run_job(&job, now, missed_before, &mut report).await {
+                Ok(()) => {}
+                Err(ScheduleError::Request(e)) => report.failed.push((job.id, e)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(report)
+    }
+
+    async fn run_job(
+        &self,
+        job: &Job,
+        now: OffsetDateTime,
+        missed_before: OffsetDateTime,
+        report: &mut RunReport,
+    ) -> Result<(), ScheduleError> {
+        let trigger = job.trigger.compile()?;
+        let mut next_run = job.next_run;
+
+        if let Some(first_missed) = next_run.filter(|&occurrence| occurrence < missed_before) {
+            let first_on_time = trigger.next_after(missed_before - time::Duration::NANOSECOND)?;
+            match self.catch_up {
+                CatchUp::Skip => {}
+                CatchUp::Once => {
+                    self.send(&job.message).await?;
+                    report.sent += 1;
+                }
+                CatchUp::All => {
+                    let mut occurrence = Some(first_missed);
+                    while let Some(missed) = occurrence.filter(|&o| o < missed_before) {
+                        self.send(&job.message).await?;
+                        report.sent += 1;
+                        occurrence = trigger.next_after(missed)?;
+                        self.advance(job.id, occurrence)?;
+                    }
+                }
+            }
+            next_run = first_on_time;
+            self.advance(job.id, next_run)?;
+        }
+
+        while let Some(occurrence) = next_run.filter(|&occurrence| occurrence <= now) {
+            self.send(&job.message).await?;
+            report.sent += 1;
+            next_run = trigger.next_after(occurrence)?;
+            self.advance(job.id, next_run)?;
+        }
+        Ok(())
+    }
+
+    /// Save the next occurrence of a job or remove it if there is none.
+    fn advance(&self, id: JobId, next_run: Option<OffsetDateTime>) -> Result<(), ScheduleError> {
+        let mut state = self.state.lock().unwrap();
+        match next_run {
+            Some(next_run) => {
+                if let Some(stored) = state.jobs.iter_mut().find(|stored| stored.id == id) {
+                    stored.next_run = Some(next_run);
+                }
+            }
+            None => state.jobs.retain(|stored| stored.id != id),
+        }
+        self.persist(&state)
+    }
+
+    /// Send due messages until the schedule file can't be written.
+    ///
+    /// Messages that can't be sent are passed to the
+    /// [failure handler](Self::with_on_failure) and retried after the
+    /// [retry delay](Self::with_retry_delay).
+    pub async fn run(&self) -> Result<(), ScheduleError> {
+        loop {
+            let report = self.run_pending().await?;
+            if let Some(on_failure) = &self.on_failure {
+                for (id, error) in &report.failed {
+                    on_failure(*id, error);
+                }
+            }
+
+            let now = OffsetDateTime::now_utc();
+            let next_run = self
+                .state
+                .lock()
+                .unwrap()
+                .jobs
+                .iter()
+                .filter_map(|job| job.next_run)
+                // jobs that failed are retried below
+                .filter(|next_run| report.failed.is_empty() || *next_run > now)
+                .min();
+            let mut sleep = match next_run {
+                Some(next_run) => (next_run - now).try_into().unwrap_or(Duration::ZERO),
+                // nothing to do until a job is added
+                None => Duration::from_secs(3600),
+            };
+            if !report.failed.is_empty() {
+                sleep = sleep.min(self.retry_delay);
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = self.changed.notified() => {}
+            }
+        }
+    }
+
+    async fn send(&self, message: &ScheduledMessage) -> Result<(), Error> {
+        let mut builder = self.client.create_message(&message.message);
+        if let Some(title) = &message.title {
+            builder = builder.with_title(title);
+        }
+        if let Some(priority) = message.priority {
+            builder = builder.with_priority(priority);
+        }
+        if let Some(extras) = &message.extras {
+            builder = builder.with_extras(extras.clone());
+        }
+        builder.await.map(drop)
+    }
+
+    /// Write the schedule to its file atomically.
+    fn persist(&self, state: &ScheduleFile) -> Result<(), ScheduleError> {
+        write_atomically(&self.path, &serde_json::to_vec_pretty(state)?)?;
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Scheduler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("client", &self.client)
+            .field("path", &self.path)
+            .field("state", &self.state)
+            .field("catch_up", &self.catch_up)
+            .field("grace_period", &self.grace_period)
+            .field("retry_delay", &self.retry_delay)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use time::{macros::datetime, OffsetDateTime};
+
+    use crate::{testsuite::*, AppClient};
+
+    use super::{CatchUp, ScheduledMessage, Scheduler, Trigger};
+
+    #[test]
+    fn triggers() -> eyre::Result<()> {
+        let berlin = Trigger::cron("0 9 * * *")?.with_time_zone("Europe/Berlin")?;
+        assert_eq!(
+            berlin.next_after(datetime!(2023-07-01 12:00 UTC))?,
+            Some(datetime!(2023-07-02 07:00 UTC))
+        );
+        assert_eq!(
+            berlin.next_after(datetime!(2023-12-01 12:00 UTC))?,
+            Some(datetime!(2023-12-02 08:00 UTC))
+        );
+
+        let every =
+            Trigger::every(Duration::from_secs(3600)).starting_at(datetime!(2023-07-01 12:00 UTC));
+        assert_eq!(
+            every.next_after(datetime!(2023-07-01 11:00 UTC))?,
+            Some(datetime!(2023-07-01 12:00 UTC))
+        );
+        assert_eq!(
+            every.next_after(datetime!(2023-07-01 14:30 UTC))?,
+            Some(datetime!(2023-07-01 15:00 UTC))
+        );
+
+        let once = Trigger::at(datetime!(2023-07-01 12:00 UTC));
+        assert_eq!(once.next_after(datetime!(2023-07-01 12:00 UTC))?, None);
+
+        assert!(Trigger::cron("not a cron expression").is_err());
+        assert!(Trigger::cron("0 9 * * *")?
+            .with_time_zone("Mars/Olympus_Mons")
+            .is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn skip_missed_occurrences() -> eyre::Result<()> {
+        let path = temp_path("schedule-skip.json");
+        let start = OffsetDateTime::now_utc() - time::Duration::minutes(210);
+
+        // the client is never used because all occurrences are skipped
+        let client = AppClient::new("http://localhost:30081", GOTIFY_APP_TOKEN)?;
+        let scheduler = Scheduler::open(client.clone(), &path)?.with_catch_up(CatchUp::Skip);
+        let id = scheduler.add(
+            Trigger::every(Duration::from_secs(3600)).starting_at(start),
+            ScheduledMessage::new("missed"),
+        )?;
+        // pretend the process was down since the first occurrence
+        scheduler.state.lock().unwrap().jobs[0].next_run = Some(start);
+
+        assert_eq!(scheduler.run_pending().await?.sent, 0);
+
+        let reopened = Scheduler::open(client, &path)?;
+        let jobs = reopened.jobs();
+        assert_eq!(jobs[0].id, id);
+        assert_eq!(jobs[0].next_run, Some(start + time::Duration::hours(4)));
+
+        assert!(reopened.remove(id)?);
+        assert!(reopened.jobs().is_empty());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn sub_second_intervals_survive_reopening() -> eyre::Result<()> {
+        let path = temp_path("schedule-sub-second.json");
+        let client = AppClient::new("http://localhost:30081", GOTIFY_APP_TOKEN)?;
+
+        let scheduler = Scheduler::open(client.clone(), &path)?;
+        scheduler.add(
+            Trigger::every(Duration::from_millis(500)),
+            ScheduledMessage::new("tick"),
+        )?;
+
+        let jobs = Scheduler::open(client, &path)?.jobs();
+        assert!(matches!(
+            jobs[0].trigger,
+            Trigger::Every { interval, .. } if interval == Duration::from_millis(500)
+        ));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn progress_is_saved_after_every_message() -> eyre::Result<()> {
+        const MESSAGE: &str =
+            r#"{"id":1,"appid":1,"date":"2023-08-01T12:00:00Z","message":"sent","priority":0}"#;
+        const ERROR: &str =
+            r#"{"error":"Internal Server Error","errorCode":500,"errorDescription":"stand-in"}"#;
+
+        let path = temp_path("schedule-progress.json");
+        let start = OffsetDateTime::now_utc() - time::Duration::minutes(210);
+
+        // the second catch-up message of the first job fails, the second job is still run
+        let (url, requests) =
+            http_stand_in(vec![(200, MESSAGE), (500, ERROR), (200, MESSAGE)]).await;
+        let client = AppClient::new(url.as_str(), GOTIFY_APP_TOKEN)?;
+        let scheduler = Scheduler::open(client.clone(), &path)?.with_catch_up(CatchUp::All);
+        let id = scheduler.add(
+            Trigger::every(Duration::from_secs(3600)).starting_at(start),
+            ScheduledMessage::new("missed"),
+        )?;
+        scheduler.state.lock().unwrap().jobs[0].next_run = Some(start);
+        scheduler.add(
+            Trigger::at(OffsetDateTime::now_utc() + time::Duration::milliseconds(50)),
+            ScheduledMessage::new("once"),
+        )?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let report = scheduler.run_pending().await?;
+        assert_eq!(report.sent, 2);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].0, id);
+        assert_eq!(requests.lock().unwrap().len(), 3);
+
+        let jobs = Scheduler::open(client, &path)?.jobs();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].next_run, Some(start + time::Duration::hours(1)));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[apply(run_test_server!)]
+    #[test]
+    async fn catch_up_once() -> eyre::Result<()> {
+        let path = temp_path("schedule-once.json");
+        let start = OffsetDateTime::now_utc() - time::Duration::minutes(210);
+
+        let scheduler = Scheduler::open((*app_client()).clone(), &path)?;
+        scheduler.add(
+            Trigger::every(Duration::from_secs(3600)).starting_at(start),
+            ScheduledMessage::new("rotate certs").with_priority(6),
+        )?;
+        scheduler.state.lock().unwrap().jobs[0].next_run = Some(start);
+        scheduler.add(
+            Trigger::at(OffsetDateTime::now_utc() + time::Duration::milliseconds(100)),
+            ScheduledMessage::new("once"),
+        )?;
+
+        assert_eq!(scheduler.run_pending().await?.sent, 1);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(scheduler.run_pending().await?.sent, 1);
+        assert_eq!(scheduler.jobs().len(), 1);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
@@ -5,7 +5,9 @@ pub use macro_rules_attribute::apply;
 use super::*;
 
 pub const GOTIFY_URL: &str = "http://localhost:30080";
+#[cfg(feature = "app")]
 pub const GOTIFY_APP_TOKEN: &str = "AGo8b9paHo5wPkI";
+#[cfg(feature = "client-core")]
 pub const GOTIFY_CLIENT_TOKEN: &str = "C4er8DTiNk08mtt";
 
 pub fn unauthenticated_client() -> Arc<UnauthenticatedClient> {
@@ -16,6 +18,7 @@ pub fn unauthenticated_client() -> Arc<UnauthenticatedClient> {
         .clone()
 }
 
+#[cfg(feature = "app")]
 pub fn app_client() -> Arc<AppClient> {
     static CLIENT: OnceLock<Arc<AppClient>> = OnceLock::new();
 
@@ -24,6 +27,7 @@ pub fn app_client() -> Arc<AppClient> {
         .clone()
 }
 
+#[cfg(feature = "client-core")]
 pub fn client_client() -> Arc<ClientClient> {
     static CLIENT: OnceLock<Arc<ClientClient>> = OnceLock::new();
 
@@ -32,6 +36,110 @@ pub fn client_client() -> Arc<ClientClient> {
         .clone()
 }
 
+/// A path in the temporary directory that is unique to this test process and doesn't exist yet.
+#[cfg(any(
+    feature = "heartbeat",
+    feature = "image-cache",
+    feature = "outbox",
+    feature = "provision",
+    feature = "replicate",
+    feature = "schedule"
+))]
+pub fn temp_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("gotify-rs-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path).or_else(|_| std::fs::remove_dir_all(&path));
+    path
+}
+
+/// A message dated 2023-08-01 12:00 UTC with priority 0 and without title or extras.
+///
+/// Use struct update syntax to set the other fields.
+#[cfg(any(
+    feature = "forward",
+    feature = "mirror",
+    feature = "retention",
+    feature = "rules"
+))]
+pub fn test_message(id: i64, appid: i64, message: &str) -> models::Message {
+    models::Message {
+        appid: appid.into(),
+        date: time::macros::datetime!(2023-08-01 12:00 UTC),
+        extras: None,
+        id: id.into(),
+        message: message.into(),
+        priority: 0,
+        title: None,
+    }
+}
+
+#[cfg(any(
+    all(feature = "app", feature = "manage-applications"),
+    feature = "audit",
+    feature = "forward",
+    feature = "heartbeat",
+    feature = "manage-plugins",
+    feature = "mirror",
+    feature = "outbox",
+    feature = "queue",
+    feature = "replicate",
+    feature = "rules",
+    feature = "schedule"
+))]
+/// A stand-in HTTP server answering with the given status codes and bodies and recording request lines and bodies.
+pub async fn http_stand_in(
+    responses: Vec<(u16, &'static str)>,
+) -> (Url, Arc<std::sync::Mutex<Vec<(String, String)>>>) {
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/hook", listener.local_addr().unwrap())
+        .parse()
+        .unwrap();
+    let requests = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+    let recorded = requests.clone();
+    tokio::spawn(async move {
+        for (status, body) in responses {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut request_line = String::new();
+            stream.read_line(&mut request_line).await.unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                stream.read_line(&mut header).await.unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut request_body = vec![0; content_length];
+            stream.read_exact(&mut request_body).await.unwrap();
+            recorded.lock().unwrap().push((
+                request_line.trim().to_owned(),
+                String::from_utf8(request_body).unwrap(),
+            ));
+
+            let response = format!(
+                "HTTP/1.1 {status} Stand-in\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream
+                .get_mut()
+                .write_all(response.as_bytes())
+                .await
+                .unwrap();
+        }
+    });
+
+    (url, requests)
+}
+
 macro_rules! run_test_server {
     (
         #[test]
@@ -0,0 +1,541 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{models::Message, AppClient, Result};
+
+/// Send messages while suppressing duplicates and limiting the rate.
+///
+/// Every message has a key, which is computed from its title, text and extras
+/// unless it is supplied with
+/// [`with_dedup_key()`](ThrottledMessageBuilder::with_dedup_key). A message is
+/// suppressed if
+///
+/// - a message with the same key was sent within the deduplication TTL,
+/// - the token bucket of its key is empty or
+/// - the global token bucket is empty.
+///
+/// Instead of the suppressed messages, a single summary message ("Suppressed
+/// 12 similar messages") is sent per key once the summary interval has passed
+/// since the first suppression. Summaries are sent by
+/// [`flush_summaries()`](Self::flush_summaries), which is called before every
+/// message and periodically by [`run_summaries()`](Self::run_summaries). A
+/// summary that couldn't be sent is kept and sent by the next flush.
+#[derive(Debug)]
+pub struct ThrottledAppClient {
+    client: AppClient,
+    dedup_ttl: Option<Duration>,
+    key_rate: Option<(u32, Duration)>,
+    global_rate: Option<(u32, Duration)>,
+    summary_interval: Duration,
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug, Default)]
+struct ThrottleState {
+    keys: HashMap<String, KeyState>,
+    global: Option<TokenBucket>,
+}
+
+#[derive(Debug, Default)]
+struct KeyState {
+    last_sent: Option<Instant>,
+    bucket: Option<TokenBucket>,
+    suppressed: Option<Suppressed>,
+}
+
+#[derive(Debug)]
+struct Suppressed {
+    count: usize,
+    since: Instant,
+    title: Option<String>,
+    priority: Option<u8>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new((burst, period): (u32, Duration), now: Instant) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec: capacity / period.as_secs_f64().max(f64::EPSILON),
+            last_refill: now,
+        }
+    }
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.last_refill = now;
+    }
+    fn has_token(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= 1.0
+    }
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.capacity
+    }
+}
+
+impl ThrottledAppClient {
+    /// The default deduplication TTL.
+    pub const DEFAULT_DEDUP_TTL: Duration = Duration::from_secs(300);
+    /// The default interval after which a summary of suppressed messages is sent.
+    pub const DEFAULT_SUMMARY_INTERVAL: Duration = Duration::from_secs(300);
+    /// The extras key of summary messages, containing the key and the number of suppressed messages.
+    pub const SUPPRESSED_EXTRAS_KEY: &'static str = "gotify-rs::suppressed";
+
+    /// Wrap an [`AppClient`] with deduplication enabled and no rate limits.
+    pub fn new(client: AppClient) -> Self {
+        Self {
+            client,
+            dedup_ttl: Some(Self::DEFAULT_DEDUP_TTL),
+            key_rate: None,
+            global_rate: None,
+            summary_interval: Self::DEFAULT_SUMMARY_INTERVAL,
+            state: Mutex::default(),
+        }
+    }
+    /// Suppress messages whose key was sent within `ttl`. `None` disables deduplication.
+    pub fn with_dedup_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.dedup_ttl = ttl;
+        self
+    }
+    /// Allow bursts of up to `burst` messages per key, refilled at `burst` messages per `period`.
+    pub fn with_key_rate_limit(mut self, burst: u32, period: Duration) -> Self {
+        self.key_rate = Some((burst, period));
+        self
+    }
+    /// Allow bursts of up to `burst` messages in total, refilled at `burst` messages per `period`.
+    pub fn with_global_rate_limit(mut self, burst: u32, period: Duration) -> Self {
+        self.global_rate = Some((burst, period));
+        self
+    }
+    /// Send a summary of suppressed messages once `interval` has passed since the first suppression.
+    pub fn with_summary_interval(mut self, interval: Duration) -> Self {
+        self.summary_interval = interval;
+        self
+    }
+
+    /// Create a message unless it is suppressed.
+    pub fn create_message(&self, message: impl Into<String>) -> ThrottledMessageBuilder<'_> {
+        ThrottledMessageBuilder {
+            client: self,
+            message: message.into(),
+            title: None,
+            extras: None,
+            priority: None,
+            key: None,
+        }
+    }
+
+    /// Send the summaries that are due and return how many were sent.
+    pub async fn flush_summaries(&self) -> Result<usize> {
+        let now = Instant::now();
+        let due = {
+            let mut state = self.state.lock().unwrap();
+            let mut due = Vec::new();
+            for (key, key_state) in &mut state.keys {
+                if key_state.suppressed.as_ref().is_some_and(|suppressed| {
+                    now.saturating_duration_since(suppressed.since) >= self.summary_interval
+                }) {
+                    due.push((key.clone(), key_state.suppressed.take().unwrap()));
+                }
+            }
+            self.prune(&mut state, now);
+            due
+        };
+
+        let count = due.len();
+        let mut due = due.into_iter();
+        while let Some((key, suppressed)) = due.next() {
+            if let Err(e) = self.send_summary(&key, &suppressed).await {
+                for (key, suppressed) in std::iter::once((key, suppressed)).chain(due) {
+                    self.restore_summary(key, suppressed);
+                }
+                return Err(e);
+            }
+        }
+        Ok(count)
+    }
+
+    async fn send_summary(&self, key: &str, suppressed: &Suppressed) -> Result<Message> {
+        let text = match suppressed.count {
+            1 => "Suppressed 1 similar message".to_owned(),
+            n => format!("Suppressed {n} similar messages"),
+        };
+        let mut builder = self
+            .client
+            .create_message(text)
+            .with_extras(HashMap::from([(
+                Self::SUPPRESSED_EXTRAS_KEY.into(),
+                serde_json::json!({ "key": key, "count": suppressed.count }),
+            )]));
+        if let Some(title) = &suppressed.title {
+            builder = builder.with_title(title.clone());
+        }
+        if let Some(priority) = suppressed.priority {
+            builder = builder.with_priority(priority);
+        }
+        builder.await
+    }
+
+    /// Put back a summary that couldn't be sent, merging it with messages suppressed meanwhile.
+    fn restore_summary(&self, key: String, suppressed: Suppressed) {
+        let mut state = self.state.lock().unwrap();
+        let key_state = state.keys.entry(key).or_default();
+        match &mut key_state.suppressed {
+            Some(newer) => {
+                newer.count += suppressed.count;
+                newer.since = suppressed.since;
+                newer.title = suppressed.title;
+                newer.priority = suppressed.priority;
+            }
+            None => key_state.suppressed = Some(suppressed),
+        }
+    }
+
+    /// Call [`flush_summaries()`](Self::flush_summaries) every `period` until a request fails.
+    pub async fn run_summaries(&self, period: Duration) -> Result<()> {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.flush_summaries().await?;
+        }
+    }
+
+    /// Decide whether a message is sent, updating the state as if it was.
+    fn admit(&self, builder: &ThrottledMessageBuilder<'_>, key: &str) -> Option<SuppressionReason> {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let ThrottleState { keys, global } = &mut *state;
+        let key_state = keys.entry(key.to_owned()).or_default();
+
+        let reason = if self.dedup_ttl.is_some_and(|ttl| {
+            key_state
+                .last_sent
+                .is_some_and(|last_sent| now.saturating_duration_since(last_sent) < ttl)
+        }) {
+            Some(SuppressionReason::Duplicate)
+        } else if let Some(false) = self.key_rate.map(|rate| {
+            key_state
+                .bucket
+                .get_or_insert_with(|| TokenBucket::new(rate, now))
+                .has_token(now)
+        }) {
+            Some(SuppressionReason::KeyRateLimited)
+        } else if let Some(false) = self.global_rate.map(|rate| {
+            global
+                .get_or_insert_with(|| TokenBucket::new(rate, now))
+                .has_token(now)
+        }) {
+            Some(SuppressionReason::GlobalRateLimited)
+        } else {
+            None
+        };
+
+        match reason {
+            Some(_) => {
+                let suppressed = key_state.suppressed.get_or_insert_with(|| Suppressed {
+                    count: 0,
+                    since: now,
+                    title: builder.title.clone(),
+                    priority: builder.priority,
+                });
+                suppressed.count += 1;
+            }
+            None => {
+                key_state.last_sent = Some(now);
+                if let Some(bucket) = &mut key_state.bucket {
+                    bucket.take();
+                }
+                if let Some(bucket) = global {
+                    bucket.take();
+                }
+            }
+        }
+        reason
+    }
+
+    /// Forget that a message was sent because the request failed.
+    fn forget(&self, key: &str) {
+        if let Some(key_state) = self.state.lock().unwrap().keys.get_mut(key) {
+            key_state.last_sent = None;
+        }
+    }
+
+    /// Remove keys that don't influence future messages anymore.
+    fn prune(&self, state: &mut ThrottleState, now: Instant) {
+        let dedup_ttl = self.dedup_ttl.unwrap_or_default();
+        state.keys.retain(|_, key_state| {
+            key_state.suppressed.is_some()
+                || key_state
+                    .last_sent
+                    .is_some_and(|last_sent| now.saturating_duration_since(last_sent) < dedup_ttl)
+                || key_state
+                    .bucket
+                    .as_mut()
+                    .is_some_and(|bucket| !bucket.is_full(now))
+        });
+    }
+}
+
+/// Compute the default key of a message from its title, text and extras.
+fn content_key(
+    title: Option<&str>,
+    message: &str,
+    extras: Option<&HashMap<String, serde_json::Value>>,
+) -> String {
+    let mut hasher = DefaultHasher::new();
+    title.hash(&mut hasher);
+    message.hash(&mut hasher);
+    if let Some(extras) = extras {
+        // sort the keys so equal extras produce equal keys
+        let sorted = extras.iter().collect::<BTreeMap<_, _>>();
+        serde_json::to_string(&sorted)
+            .unwrap_or_default()
+            .hash(&mut hasher);
+    }
+    format!("{:016x}", hasher.finish())
+}
+
+/// Builder for a message sent by a [`ThrottledAppClient`].
+#[derive(Debug)]
+pub struct ThrottledMessageBuilder<'client> {
+    client: &'client ThrottledAppClient,
+    message: String,
+    title: Option<String>,
+    extras: Option<HashMap<String, serde_json::Value>>,
+    priority: Option<u8>,
+    key: Option<String>,
+}
+
+#[allow(missing_docs)]
+impl<'client> ThrottledMessageBuilder<'client> {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+    pub fn with_extras(mut self, extras: impl Into<HashMap<String, serde_json::Value>>) -> Self {
+        self.extras = Some(extras.into());
+        self
+    }
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+    /// Use this key for deduplication and throttling instead of one computed from the content.
+    pub fn with_dedup_key(mut self, key: impl Into<String>) -> Self {
+        self.key = Some(key.into());
+        self
+    }
+    /// Send the message unless it is suppressed.
+    ///
+    /// Due summaries are sent first. If they can't be sent, they are kept for
+    /// the next flush and the message is sent anyway.
+    pub async fn send(self) -> Result<SendOutcome> {
+        let client = self.client;
+        // a failing summary must not fail this message, it is retried by the next flush
+        let _ = client.flush_summaries().await;
+
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => content_key(self.title.as_deref(), &self.message, self.extras.as_ref()),
+        };
+        if let Some(reason) = client.admit(&self, &key) {
+            return Ok(SendOutcome::Suppressed(reason));
+        }
+
+        let mut request = client.client.create_message(self.message);
+        if let Some(title) = self.title {
+            request = request.with_title(title);
+        }
+        if let Some(extras) = self.extras {
+            request = request.with_extras(extras);
+        }
+        if let Some(priority) = self.priority {
+            request = request.with_priority(priority);
+        }
+
+        match request.await {
+            Ok(message) => Ok(SendOutcome::Sent(message)),
+            Err(e) => {
+                client.forget(&key);
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<'client> std::future::IntoFuture for ThrottledMessageBuilder<'client> {
+    type Output = Result<SendOutcome>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'client>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.send())
+    }
+}
+
+/// The result of sending a message with a [`ThrottledAppClient`].
+#[derive(Debug)]
+pub enum SendOutcome {
+    /// The message was created.
+    Sent(Message),
+    /// The message was suppressed and will be included in a summary.
+    Suppressed(SuppressionReason),
+}
+
+impl SendOutcome {
+    /// Return the created message.
+    pub fn message(&self) -> Option<&Message> {
+        match self {
+            SendOutcome::Sent(message) => Some(message),
+            SendOutcome::Suppressed(_) => None,
+        }
+    }
+}
+
+/// Why a message was suppressed by a [`ThrottledAppClient`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SuppressionReason {
+    /// A message with the same key was sent within the deduplication TTL.
+    Duplicate,
+    /// The token bucket of the message's key is empty.
+    KeyRateLimited,
+    /// The global token bucket is empty.
+    GlobalRateLimited,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::testsuite::*;
+
+    use super::{content_key, SendOutcome, SuppressionReason, ThrottledAppClient, TokenBucket};
+
+    #[test]
+    fn token_bucket() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new((2, Duration::from_secs(10)), start);
+
+        for _ in 0..2 {
+            assert!(bucket.has_token(start));
+            bucket.take();
+        }
+        assert!(!bucket.has_token(start));
+        assert!(bucket.has_token(start + Duration::from_secs(5)));
+        assert!(bucket.is_full(start + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn key_ignores_extras_order() {
+        let a = [("a".to_owned(), 1.into()), ("b".to_owned(), 2.into())].into();
+        let b = [("b".to_owned(), 2.into()), ("a".to_owned(), 1.into())].into();
+
+        assert_eq!(
+            content_key(Some("t"), "m", Some(&a)),
+            content_key(Some("t"), "m", Some(&b))
+        );
+        assert_ne!(
+            content_key(Some("t"), "m", None),
+            content_key(None, "m", None)
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_summaries_are_kept() -> eyre::Result<()> {
+        let client = ThrottledAppClient::new(crate::AppClient::new(
+            "http://localhost:30081",
+            GOTIFY_APP_TOKEN,
+        )?)
+        .with_global_rate_limit(1, Duration::from_secs(3600))
+        .with_summary_interval(Duration::ZERO);
+
+        // the first message takes the only token, even though it can't be sent
+        assert!(client.create_message("first").await.is_err());
+        assert!(matches!(
+            client.create_message("second").with_dedup_key("k").await?,
+            SendOutcome::Suppressed(SuppressionReason::GlobalRateLimited)
+        ));
+        assert!(client.flush_summaries().await.is_err());
+
+        // the failing summary doesn't fail the next message and is merged with it
+        assert!(matches!(
+            client.create_message("third").with_dedup_key("k").await?,
+            SendOutcome::Suppressed(SuppressionReason::GlobalRateLimited)
+        ));
+        let state = client.state.lock().unwrap();
+        assert_eq!(state.keys["k"].suppressed.as_ref().unwrap().count, 2);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "manage-messages")]
+    #[apply(run_test_server!)]
+    #[test]
+    async fn suppress_and_summarize() -> eyre::Result<()> {
+        let client = ThrottledAppClient::new((*app_client()).clone())
+            .with_global_rate_limit(2, Duration::from_secs(3600))
+            .with_summary_interval(Duration::from_millis(100));
+
+        let sent = client
+            .create_message("disk full")
+            .with_title("check")
+            .await?;
+        assert!(matches!(sent, SendOutcome::Sent(_)));
+        for _ in 0..3 {
+            let outcome = client
+                .create_message("disk full")
+                .with_title("check")
+                .await?;
+            assert!(matches!(
+                outcome,
+                SendOutcome::Suppressed(SuppressionReason::Duplicate)
+            ));
+        }
+
+        assert!(client.create_message("other").await?.message().is_some());
+        assert!(matches!(
+            client.create_message("third").await?,
+            SendOutcome::Suppressed(SuppressionReason::GlobalRateLimited)
+        ));
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(client.flush_summaries().await?, 2);
+
+        let messages = client_client().get_messages().with_limit(2).await?.messages;
+        let mut texts = messages
+            .iter()
+            .map(|m| m.message.as_str())
+            .collect::<Vec<_>>();
+        texts.sort();
+        assert_eq!(
+            texts,
+            [
+                "Suppressed 1 similar message",
+                "Suppressed 3 similar messages"
+            ]
+        );
+
+        Ok(())
+    }
+}
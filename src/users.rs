@@ -1,6 +1,10 @@
 use reqwest::Method;
 
-use crate::{models::User, utils::request_builder, ClientClient, Result};
+use crate::{
+    models::{User, UserId},
+    utils::request_builder,
+    ClientClient, Result,
+};
 
 /// List, create, update or delete users.
 impl ClientClient {
@@ -11,7 +15,7 @@ impl ClientClient {
             .await
     }
     /// Update the password of the current user.
-    pub fn update_current_user(&self, pass: impl Into<String>) -> UpdateCurrentUserBuilder {
+    pub fn update_current_user(&self, pass: impl Into<String>) -> UpdateCurrentUserBuilder<'_> {
         UpdateCurrentUserBuilder::new(self, pass)
     }
     /// Return all users.
@@ -26,21 +30,26 @@ impl ClientClient {
         admin: bool,
         name: impl Into<String>,
         pass: impl Into<String>,
-    ) -> CreateUserBuilder {
+    ) -> CreateUserBuilder<'_> {
         CreateUserBuilder::new(self, admin, name, pass)
     }
     /// Get a user.
-    pub async fn get_user(&self, id: i64) -> Result<User> {
+    pub async fn get_user(&self, id: UserId) -> Result<User> {
         self.request(Method::GET, ["user".into(), id.to_string()])
             .send_and_read_json()
             .await
     }
     /// Update a client.
-    pub fn update_user(&self, id: i64, admin: bool, name: impl Into<String>) -> UpdateUserBuilder {
+    pub fn update_user(
+        &self,
+        id: UserId,
+        admin: bool,
+        name: impl Into<String>,
+    ) -> UpdateUserBuilder<'_> {
         UpdateUserBuilder::new(self, id, admin, name)
     }
     /// Delete a user.
-    pub async fn delete_user(&self, id: i64) -> Result<()> {
+    pub async fn delete_user(&self, id: UserId) -> Result<()> {
         self.request(Method::DELETE, ["user".into(), id.to_string()])
             .send()
             .await
@@ -79,7 +88,7 @@ request_builder! {
     return_type = User,
     required_fields = {
         #[serde(skip)]
-        id: i64 => i64,
+        id: UserId => UserId,
         admin: bool => bool,
         name: impl Into<String> => .into() => String,
     },
@@ -90,7 +99,7 @@ request_builder! {
 
 #[cfg(test)]
 pub(crate) mod tests {
-    use crate::testsuite::*;
+    use crate::{models::UserId, testsuite::*};
 
     #[apply(run_test_server!)]
     #[test]
@@ -147,7 +156,7 @@ pub(crate) mod tests {
     async fn get_user() -> eyre::Result<()> {
         let client = client_client();
 
-        let user = client.get_user(1).await?;
+        let user = client.get_user(UserId(1)).await?;
         assert_eq!(user.name, "admin");
 
         Ok(())
@@ -15,7 +15,73 @@ impl UrlAppend for Url {
     }
 }
 
-#[cfg(any(feature = "app", feature = "client-core"))]
+/// Extras that make Gotify's clients render the message as markdown.
+#[cfg(any(
+    feature = "digest",
+    feature = "log",
+    feature = "report",
+    feature = "tracing"
+))]
+pub(crate) fn markdown_extras() -> std::collections::HashMap<String, serde_json::Value> {
+    std::collections::HashMap::from([(
+        "client::display".into(),
+        serde_json::json!({ "contentType": "text/markdown" }),
+    )])
+}
+
+/// Replace a file's content by writing to a temporary file next to it, syncing and renaming it.
+///
+/// A crash leaves either the old or the new content, never a partially written file.
+#[cfg(any(
+    feature = "heartbeat",
+    feature = "outbox",
+    feature = "replicate",
+    feature = "schedule"
+))]
+pub(crate) fn write_atomically(path: &std::path::Path, content: &[u8]) -> std::io::Result<()> {
+    replace_file(path, content, false)
+}
+
+/// Like [`write_atomically()`], but on unix the file is only readable and writable by its owner.
+#[cfg(feature = "provision")]
+pub(crate) fn write_private_atomically(
+    path: &std::path::Path,
+    content: &[u8],
+) -> std::io::Result<()> {
+    replace_file(path, content, true)
+}
+
+#[cfg(any(
+    feature = "heartbeat",
+    feature = "outbox",
+    feature = "provision",
+    feature = "replicate",
+    feature = "schedule"
+))]
+#[cfg_attr(not(unix), allow(unused_variables))]
+fn replace_file(path: &std::path::Path, content: &[u8], private: bool) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if private {
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    }
+    let mut file = options.open(&tmp)?;
+    // the mode only applies to new files, a temporary file might be left over
+    #[cfg(unix)]
+    if private {
+        file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    }
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+#[cfg(feature = "client-core")]
 macro_rules! request_builder {
     (
         name = $name:ident,
@@ -100,7 +166,7 @@ macro_rules! request_builder {
     };
 }
 
-#[cfg(any(feature = "app", feature = "client-core"))]
+#[cfg(feature = "client-core")]
 macro_rules! _send_and_match_return_type {
     ( $r:ident, () ) => {
         $r.send().await
@@ -113,7 +179,7 @@ macro_rules! _send_and_match_return_type {
     };
 }
 
-#[cfg(any(feature = "app", feature = "client-core"))]
+#[cfg(feature = "client-core")]
 pub(crate) use _send_and_match_return_type;
-#[cfg(any(feature = "app", feature = "client-core"))]
+#[cfg(feature = "client-core")]
 pub(crate) use request_builder;
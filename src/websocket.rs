@@ -1,11 +1,11 @@
 use futures_util::{Stream, StreamExt};
-use reqwest::{header, StatusCode};
+use reqwest::{header, Method, StatusCode};
 use tokio_tungstenite::{
     tungstenite::{self, handshake::derive_accept_key},
     WebSocketStream,
 };
 
-use crate::{models::Message, utils::UrlAppend, ClientClient};
+use crate::{models::Message, ClientClient};
 
 /// Subscribe to newly created messages.
 impl ClientClient {
@@ -18,8 +18,8 @@ impl ClientClient {
         let request_key = tungstenite::handshake::client::generate_key();
 
         let response = self
-            .http
-            .get(self.base_url.append(["stream"]))
+            .request(Method::GET, ["stream"])
+            .0
             .version(reqwest::Version::HTTP_11)
             .header(header::CONNECTION, "Upgrade")
             .header(header::UPGRADE, "websocket")
@@ -34,11 +34,11 @@ impl ClientClient {
             .error_for_status()?;
 
         if response.status() != StatusCode::SWITCHING_PROTOCOLS
-            || !response
+            || response
                 .headers()
                 .get(header::SEC_WEBSOCKET_ACCEPT)
                 .and_then(|v| v.to_str().ok())
-                .is_some_and(|key| key == derive_accept_key(request_key.as_ref()))
+                .is_none_or(|key| key != derive_accept_key(request_key.as_ref()))
         {
             return Err(WebsocketConnectError::Response(response));
         }